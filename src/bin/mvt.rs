@@ -0,0 +1,282 @@
+use rosm_mvt::compression::{decompress, Compression};
+use rosm_mvt::directory::DirectoryStore;
+use rosm_mvt::geojson::{from_geojson, to_geojson};
+#[cfg(feature = "mbtiles")]
+use rosm_mvt::mbtiles::MbTiles;
+#[cfg(feature = "pmtiles")]
+use rosm_mvt::pmtiles::PmTiles;
+use rosm_mvt::read::{self, Geometry};
+use rosm_mvt::stats::TileStats;
+use rosm_mvt::store::TileStore;
+use rosm_mvt::tile::TileId;
+use rosm_mvt::validate::{is_compliant, validate, validate_store, Severity, ValidationOptions};
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "Usage:
+    mvt inspect <tile> [--samples <count>]
+    mvt validate <tile>...
+    mvt validate-archive <archive> [--max-tile-size <bytes>] [--threads <count>] [--limit <count>]
+    mvt to-geojson <tile> [--tile <z/x/y>]
+    mvt from-geojson <geojson> --tile <z/x/y> [--layer <name>] [--extent <extent>] [--buffer <buffer>] [--output <tile>]
+
+Tiles may be gzip or zstd compressed. A file name of - reads standard input. Archives are tile directories,
+or .mbtiles and .pmtiles files if the crate was built with the mbtiles and pmtiles features.";
+
+type CommandResult = Result<bool, Box<dyn Error>>;
+
+/// Positional arguments and `--name value` options of a command.
+struct Args {
+    positional: Vec<String>,
+    options: BTreeMap<String, String>,
+}
+
+impl Args {
+    fn parse(args: &[String], allowed: &[&str]) -> Result<Args, Box<dyn Error>> {
+        let mut positional = Vec::new();
+        let mut options = BTreeMap::new();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if allowed.contains(&name) => {
+                    let value = args.next().ok_or(format!("Missing value of --{}", name))?;
+                    options.insert(name.to_string(), value.clone());
+                }
+                Some(name) => return Err(format!("Unknown option --{}", name).into()),
+                None => positional.push(arg.clone()),
+            }
+        }
+
+        Ok(Args { positional, options })
+    }
+
+    fn single(&self) -> Result<&str, Box<dyn Error>> {
+        match self.positional.as_slice() {
+            [path] => Ok(path),
+            _ => Err("Expected exactly one input file".into()),
+        }
+    }
+
+    fn tile(&self) -> Result<Option<TileId>, Box<dyn Error>> {
+        match self.options.get("tile") {
+            Some(tile) => Ok(Some(TileId::parse(tile).ok_or(format!("Invalid tile id {}", tile))?)),
+            None => Ok(None),
+        }
+    }
+
+    fn number(&self, name: &str, default: u32) -> Result<u32, Box<dyn Error>> {
+        match self.options.get(name) {
+            Some(value) => Ok(value.parse().map_err(|_| format!("Invalid --{} {}", name, value))?),
+            None => Ok(default),
+        }
+    }
+}
+
+fn read_input(path: &str) -> io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data)?;
+        Ok(data)
+    } else {
+        fs::read(path)
+    }
+}
+
+fn geometry_name(geometry: &Geometry) -> &'static str {
+    match geometry {
+        Geometry::Point(_) => "Point",
+        Geometry::MultiPoint(_) => "MultiPoint",
+        Geometry::Line(_) => "LineString",
+        Geometry::MultiLine(_) => "MultiLineString",
+        Geometry::Polygon(..) => "Polygon",
+        Geometry::MultiPolygon(_) => "MultiPolygon",
+    }
+}
+
+fn inspect(args: &[String]) -> CommandResult {
+    let args = Args::parse(args, &["samples"])?;
+    let samples = args.number("samples", 3)? as usize;
+
+    let data = read_input(args.single()?)?;
+    let data = decompress(&data, Compression::detect(&data))?;
+    let stats = TileStats::from_bytes(&data)?;
+    let tile = read::Tile::from_bytes(&data)?;
+
+    println!("{} bytes, {} layers", stats.size, tile.layers.len());
+
+    for (layer, layer_stats) in tile.layers.iter().zip(&stats.layers) {
+        let counts = layer_stats.geometry_counts;
+        println!();
+        println!(
+            "Layer {:?} (version {}, extent {}, {} bytes)",
+            layer.name, layer.version, layer.extent, layer_stats.size
+        );
+        println!(
+            "  {} features: {} points, {} lines, {} polygons, {} vertices",
+            layer_stats.features, counts.points, counts.lines, counts.polygons, layer_stats.vertices
+        );
+
+        let mut keys: BTreeMap<&str, HashSet<_>> = BTreeMap::new();
+        for feature in &layer.features {
+            for (key, value) in &feature.tags {
+                keys.entry(key).or_default().insert(value.key());
+            }
+        }
+        for (key, values) in keys {
+            println!("  key {:?}: {} distinct values", key, values.len());
+        }
+
+        for feature in layer.features.iter().take(samples) {
+            let id = feature.id.map_or("-".to_string(), |id| id.to_string());
+            println!(
+                "  feature {} {}: {:?}",
+                id,
+                geometry_name(&feature.geometry),
+                feature.tags
+            );
+        }
+    }
+
+    Ok(true)
+}
+
+fn validate_command(args: &[String]) -> CommandResult {
+    let args = Args::parse(args, &[])?;
+    if args.positional.is_empty() {
+        return Err("Expected at least one input file".into());
+    }
+
+    let mut compliant = true;
+
+    for path in &args.positional {
+        let violations = validate(&read_input(path)?);
+        let must = violations
+            .iter()
+            .filter(|violation| violation.kind.severity() == Severity::Must)
+            .count();
+
+        println!(
+            "{}: {} violations of MUST and {} of SHOULD requirements",
+            path,
+            must,
+            violations.len() - must
+        );
+        for violation in &violations {
+            println!("  {}: {}", violation.kind.severity().as_str().to_uppercase(), violation);
+        }
+
+        compliant &= is_compliant(&violations);
+    }
+
+    Ok(compliant)
+}
+
+fn open_store(path: &str) -> Result<Box<dyn TileStore>, Box<dyn Error>> {
+    #[cfg(feature = "mbtiles")]
+    {
+        if path.ends_with(".mbtiles") {
+            return Ok(Box::new(MbTiles::open(path)?));
+        }
+    }
+    #[cfg(feature = "pmtiles")]
+    {
+        if path.ends_with(".pmtiles") {
+            return Ok(Box::new(PmTiles::open(io::BufReader::new(fs::File::open(path)?))?));
+        }
+    }
+
+    if Path::new(path).is_dir() {
+        Ok(Box::new(DirectoryStore::open(path)?))
+    } else {
+        Err(format!("Unsupported archive {}", path).into())
+    }
+}
+
+fn validate_archive(args: &[String]) -> CommandResult {
+    let args = Args::parse(args, &["max-tile-size", "threads", "limit"])?;
+
+    let defaults = ValidationOptions::default();
+    let options = ValidationOptions {
+        max_tile_size: match args.options.get("max-tile-size") {
+            Some(_) => Some(args.number("max-tile-size", 0)? as usize),
+            None => defaults.max_tile_size,
+        },
+        threads: args.number("threads", defaults.threads as u32)? as usize,
+    };
+
+    let mut store = open_store(args.single()?)?;
+    let report = validate_store(store.as_mut(), &options)?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report.to_json(args.number("limit", 100)? as usize))?
+    );
+
+    Ok(report.is_compliant())
+}
+
+fn to_geojson_command(args: &[String]) -> CommandResult {
+    let args = Args::parse(args, &["tile"])?;
+    let tile = read::Tile::from_compressed_bytes(&read_input(args.single()?)?)?;
+
+    println!("{}", to_geojson(&tile, args.tile()?));
+
+    Ok(true)
+}
+
+fn from_geojson_command(args: &[String]) -> CommandResult {
+    let args = Args::parse(args, &["tile", "layer", "extent", "buffer", "output"])?;
+    let id = args.tile()?.ok_or("Missing --tile")?;
+    let layer = args.options.get("layer").map_or("layer", String::as_str);
+
+    let json = serde_json::from_slice(&read_input(args.single()?)?)?;
+    let tile = from_geojson(
+        &json,
+        id,
+        args.number("extent", 4096)?,
+        args.number("buffer", 64)?,
+        layer,
+    )?;
+
+    match args.options.get("output") {
+        Some(path) => fs::write(path, tile.to_bytes())?,
+        None => io::stdout().write_all(&tile.to_bytes())?,
+    }
+
+    Ok(true)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.split_first() {
+        Some((command, args)) => match command.as_str() {
+            "inspect" => inspect(args),
+            "validate" => validate_command(args),
+            "validate-archive" => validate_archive(args),
+            "to-geojson" => to_geojson_command(args),
+            "from-geojson" => from_geojson_command(args),
+            "help" | "--help" | "-h" => {
+                println!("{}", USAGE);
+                Ok(true)
+            }
+            command => Err(format!("Unknown command {}\n\n{}", command, USAGE).into()),
+        },
+        None => Err(USAGE.into()),
+    };
+
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,471 @@
+use super::error::SpecViolation;
+
+use super::read::{self, ring_area, Geometry};
+use super::simplify::simplify;
+use super::tiny::TinyPolygonFilter;
+use super::write::{self, EncodableGeometry};
+
+use std::collections::HashMap;
+
+/// Size limits for `encode`, in bytes of the uncompressed tile.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Budget {
+    pub tile_size: usize,
+    /// Limits of individual layers, checked before the tile limit.
+    pub layer_sizes: HashMap<String, usize>,
+    /// Simplification tolerance in tile units is doubled from 1 up to this value before features are dropped.
+    pub max_tolerance: f64,
+    /// Polygons and holes below this area in square tile units are dropped before encoding, see
+    /// `tiny::drop_tiny_polygons`. Zero keeps them all.
+    pub min_polygon_area: f64,
+    /// Accumulates the area of dropped polygons into placeholder squares, see `tiny::drop_tiny_polygons`.
+    pub reduce_tiny_polygons: bool,
+}
+
+impl Budget {
+    pub fn new(tile_size: usize) -> Budget {
+        Budget {
+            tile_size,
+            layer_sizes: HashMap::new(),
+            max_tolerance: 16.0,
+            min_polygon_area: 0.0,
+            reduce_tiny_polygons: false,
+        }
+    }
+
+    pub fn with_layer_size<Name: Into<String>>(mut self, layer: Name, size: usize) -> Budget {
+        self.layer_sizes.insert(layer.into(), size);
+        self
+    }
+
+    pub fn with_min_polygon_area(mut self, min_area: f64, reduce: bool) -> Budget {
+        self.min_polygon_area = min_area;
+        self.reduce_tiny_polygons = reduce;
+        self
+    }
+}
+
+impl Default for Budget {
+    /// 500 KB, the limit of most map clients.
+    fn default() -> Budget {
+        Budget::new(500 * 1024)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropReason {
+    /// The geometry cannot be encoded.
+    Invalid,
+    /// Nothing was left after dropping polygons below the minimum area.
+    Tiny,
+    /// The geometry collapsed during simplification.
+    Collapsed,
+    /// The feature was among the lowest priority or smallest ones when the budget was exceeded.
+    Budget,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DroppedFeature {
+    pub layer: String,
+    pub id: Option<u64>,
+    pub reason: DropReason,
+}
+
+/// What `encode` did to fit the budget.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BudgetReport {
+    /// Size of the encoded tile.
+    pub size: usize,
+    /// Final simplification tolerance of each simplified layer.
+    pub tolerances: Vec<(String, f64)>,
+    pub dropped: Vec<DroppedFeature>,
+}
+
+impl BudgetReport {
+    pub fn fits(&self, budget: &Budget) -> bool {
+        self.size <= budget.tile_size
+    }
+}
+
+struct Candidate<'a> {
+    feature: &'a read::Feature,
+    /// The geometry of the feature without its tiny polygons, simplified into `geometry`.
+    source: Geometry,
+    geometry: Option<Geometry>,
+    priority: f64,
+    magnitude: f64,
+    dropped: Option<DropReason>,
+}
+
+struct LayerState<'a> {
+    layer: &'a read::Layer,
+    candidates: Vec<Candidate<'a>>,
+    tolerance: f64,
+}
+
+impl<'a> LayerState<'a> {
+    fn encode(&self) -> Result<Option<write::Layer>, SpecViolation> {
+        let mut features = Vec::new();
+
+        for candidate in &self.candidates {
+            if let (None, Some(geometry)) = (candidate.dropped, &candidate.geometry) {
+                // Geometries failing to encode are dropped upfront
+                let mut feature = write::Feature::new(geometry.encode().unwrap());
+                feature.id = candidate.feature.id;
+                feature.tags = candidate.feature.tags.clone();
+                features.push(feature);
+            }
+        }
+
+        if features.is_empty() {
+            return Ok(None);
+        }
+
+        let mut layer = write::Layer::new(self.layer.name.clone(), features)?;
+        layer.extent = self.layer.extent;
+
+        Ok(Some(layer))
+    }
+
+    /// Size of the layer inside a tile. Layers are independent fields of the tile message, so the size of
+    /// a tile is the sum of the sizes of its layers.
+    fn size(&self) -> Result<usize, SpecViolation> {
+        Ok(match self.encode()? {
+            Some(layer) => write::Tile::new(vec![layer])?.to_bytes().len(),
+            None => 0,
+        })
+    }
+
+    fn simplify(&mut self, tolerance: f64) {
+        self.tolerance = tolerance;
+
+        for candidate in self
+            .candidates
+            .iter_mut()
+            .filter(|candidate| candidate.dropped.is_none())
+        {
+            candidate.geometry = simplify(&candidate.source, tolerance);
+
+            match &candidate.geometry {
+                Some(geometry) if geometry.encode().is_ok() => {}
+                _ => candidate.dropped = Some(DropReason::Collapsed),
+            }
+        }
+    }
+}
+
+/// Area of polygons, length of lines and zero for points, used to drop the smallest features first.
+fn magnitude(geometry: &Geometry) -> f64 {
+    let length = |line: &[(i32, i32)]| -> f64 {
+        line.windows(2)
+            .map(|pair| {
+                let (dx, dy) = ((pair[1].0 - pair[0].0) as f64, (pair[1].1 - pair[0].1) as f64);
+                (dx * dx + dy * dy).sqrt()
+            })
+            .sum()
+    };
+    let area = |exterior_ring: &[(i32, i32)], interior_rings: &[Vec<(i32, i32)>]| -> f64 {
+        let area = ring_area(exterior_ring) + interior_rings.iter().map(|ring| ring_area(ring)).sum::<i64>();
+        area.abs() as f64 / 2.0
+    };
+
+    match geometry {
+        Geometry::Point(_) | Geometry::MultiPoint(_) => 0.0,
+        Geometry::Line(line) => length(line),
+        Geometry::MultiLine(lines) => lines.iter().map(|line| length(line)).sum(),
+        Geometry::Polygon(exterior_ring, interior_rings) => area(exterior_ring, interior_rings),
+        Geometry::MultiPolygon(polygons) => polygons
+            .iter()
+            .map(|(exterior_ring, interior_rings)| area(exterior_ring, interior_rings))
+            .sum(),
+    }
+}
+
+fn group_size(layers: &[LayerState], group: &[usize]) -> Result<usize, SpecViolation> {
+    group.iter().map(|idx| layers[*idx].size()).sum()
+}
+
+/// Simplifies, then drops features of the layers in `group` until they fit into `limit` bytes.
+fn shrink(layers: &mut [LayerState], group: &[usize], limit: usize, max_tolerance: f64) -> Result<(), SpecViolation> {
+    let mut tolerance = 1.0;
+
+    while group_size(layers, group)? > limit && tolerance <= max_tolerance {
+        for idx in group {
+            if layers[*idx].tolerance < tolerance {
+                layers[*idx].simplify(tolerance);
+            }
+        }
+        tolerance *= 2.0;
+    }
+
+    while group_size(layers, group)? > limit {
+        let mut remaining: Vec<(usize, usize)> = group
+            .iter()
+            .flat_map(|layer_idx| {
+                layers[*layer_idx]
+                    .candidates
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, candidate)| candidate.dropped.is_none())
+                    .map(move |(idx, _)| (*layer_idx, idx))
+            })
+            .collect();
+
+        if remaining.is_empty() {
+            break;
+        }
+
+        remaining.sort_by(|a, b| {
+            let a = &layers[a.0].candidates[a.1];
+            let b = &layers[b.0].candidates[b.1];
+            a.priority
+                .total_cmp(&b.priority)
+                .then(a.magnitude.total_cmp(&b.magnitude))
+        });
+
+        // Drop a tenth of the remaining features at a time to keep the number of re-encodings low
+        let count = (remaining.len() / 10).max(1);
+        for (layer_idx, idx) in remaining.into_iter().take(count) {
+            layers[layer_idx].candidates[idx].dropped = Some(DropReason::Budget);
+        }
+    }
+
+    Ok(())
+}
+
+/// Encodes a tile so that it fits into `budget`. Polygons below the minimum area of the budget are dropped
+/// first. Then layers over their own limit and the whole tile are shrunk by simplifying geometries with a growing tolerance and, if that is not enough, by dropping the
+/// features with the lowest `priority` (the smallest ones first among equal priorities).
+///
+/// `priority` gets the layer name and the feature, higher values are kept longer. Layers losing all their
+/// features are omitted; an empty result means nothing fit.
+pub fn encode<P>(tile: &read::Tile, budget: &Budget, priority: P) -> Result<(Vec<u8>, BudgetReport), SpecViolation>
+where
+    P: Fn(&str, &read::Feature) -> f64,
+{
+    let mut layers: Vec<LayerState> = tile
+        .layers
+        .iter()
+        .map(|layer| {
+            let mut tiny_polygons = TinyPolygonFilter::new(budget.min_polygon_area, budget.reduce_tiny_polygons);
+
+            let candidates = layer
+                .features
+                .iter()
+                .map(|feature| {
+                    let (source, dropped) = match tiny_polygons.apply(feature.geometry.clone()) {
+                        Some(source) => {
+                            let dropped = source.encode().err().map(|_| DropReason::Invalid);
+                            (source, dropped)
+                        }
+                        None => (feature.geometry.clone(), Some(DropReason::Tiny)),
+                    };
+
+                    Candidate {
+                        feature,
+                        geometry: Some(source.clone()),
+                        priority: priority(&layer.name, feature),
+                        magnitude: magnitude(&source),
+                        dropped,
+                        source,
+                    }
+                })
+                .collect();
+
+            LayerState {
+                layer,
+                candidates,
+                tolerance: 0.0,
+            }
+        })
+        .collect();
+
+    for idx in 0..layers.len() {
+        if let Some(limit) = budget.layer_sizes.get(&layers[idx].layer.name) {
+            shrink(&mut layers, &[idx], *limit, budget.max_tolerance)?;
+        }
+    }
+
+    let all: Vec<usize> = (0..layers.len()).collect();
+    shrink(&mut layers, &all, budget.tile_size, budget.max_tolerance)?;
+
+    let mut report = BudgetReport::default();
+    let mut encoded = Vec::new();
+
+    for state in &layers {
+        if state.tolerance > 0.0 {
+            report.tolerances.push((state.layer.name.clone(), state.tolerance));
+        }
+
+        for candidate in &state.candidates {
+            if let Some(reason) = candidate.dropped {
+                report.dropped.push(DroppedFeature {
+                    layer: state.layer.name.clone(),
+                    id: candidate.feature.id,
+                    reason,
+                });
+            }
+        }
+
+        encoded.extend(state.encode()?);
+    }
+
+    let bytes = if encoded.is_empty() {
+        Vec::new()
+    } else {
+        write::Tile::new(encoded)?.to_bytes()
+    };
+    report.size = bytes.len();
+
+    Ok((bytes, report))
+}
+
+#[cfg(test)]
+mod budget_test {
+    use super::*;
+    use crate::common::Value;
+
+    fn create_test_tile() -> read::Tile {
+        let mut buildings = Vec::new();
+
+        for idx in 0..50 {
+            let (x, size) = (idx * 80, 10 + idx);
+            // Wobbly outline with many vertices, simplified away by small tolerances
+            let mut ring: Vec<(i32, i32)> = (0..size).map(|step| (x + step, step % 2)).collect();
+            ring.extend((0..size).map(|step| (x + size - step, size + step % 2)));
+
+            buildings.push(read::Feature {
+                id: Some(idx as u64 + 1),
+                tags: vec![("height".to_string(), Value::UInt(idx as u64))],
+                geometry: Geometry::Polygon(ring, vec![]),
+            });
+        }
+
+        let pois = (0..20)
+            .map(|idx| read::Feature {
+                id: Some(idx + 1),
+                tags: vec![("rank".to_string(), Value::UInt(idx))],
+                geometry: Geometry::Point((idx as i32, idx as i32)),
+            })
+            .collect();
+
+        let layer = |name: &str, features| read::Layer {
+            name: name.into(),
+            version: 2,
+            extent: 4096,
+            features,
+        };
+
+        read::Tile {
+            layers: vec![layer("buildings", buildings), layer("pois", pois)],
+        }
+    }
+
+    fn rank(_: &str, feature: &read::Feature) -> f64 {
+        match feature.tag("rank") {
+            Some(Value::UInt(rank)) => *rank as f64,
+            _ => 0.0,
+        }
+    }
+
+    #[test]
+    fn within_budget() {
+        let tile = create_test_tile();
+        let (bytes, report) = encode(&tile, &Budget::default(), rank).unwrap();
+
+        assert_eq!(report.size, bytes.len());
+        assert!(report.tolerances.is_empty());
+        assert!(report.dropped.is_empty());
+        assert_eq!(read::Tile::from_bytes(&bytes).unwrap(), tile);
+    }
+
+    #[test]
+    fn simplify_and_drop() {
+        let tile = create_test_tile();
+        let (original, _) = encode(&tile, &Budget::default(), rank).unwrap();
+
+        // Simplification alone is enough
+        let budget = Budget::new(original.len() / 2);
+        let (bytes, report) = encode(&tile, &budget, rank).unwrap();
+        assert!(report.fits(&budget));
+        assert_eq!(report.tolerances[0], ("buildings".to_string(), 1.0));
+        assert!(report.dropped.is_empty());
+        assert_eq!(read::Tile::from_bytes(&bytes).unwrap().layers[0].features.len(), 50);
+
+        // The pois layer has its own, tighter budget, so its low ranked features go first
+        let budget = Budget::new(original.len() / 2).with_layer_size("pois", 100);
+        let (bytes, report) = encode(&tile, &budget, rank).unwrap();
+        assert!(report.fits(&budget));
+        let tile = read::Tile::from_bytes(&bytes).unwrap();
+        let pois = tile.layer("pois").unwrap();
+        assert!(pois.features.len() < 20);
+        assert!(pois
+            .features
+            .iter()
+            .all(|feature| rank("pois", feature) >= (20 - pois.features.len()) as f64));
+        assert!(report
+            .dropped
+            .iter()
+            .all(|dropped| dropped.layer == "pois" && dropped.reason == DropReason::Budget));
+
+        // Nothing fits
+        let (bytes, report) = encode(&create_test_tile(), &Budget::new(0), rank).unwrap();
+        assert!(bytes.is_empty());
+        assert_eq!(report.dropped.len(), 70);
+    }
+
+    #[test]
+    fn tiny_polygons() {
+        let tile = create_test_tile();
+
+        // Building areas grow from 90 to 3422 square units, the first ten are below 350
+        let budget = Budget::default().with_min_polygon_area(350.0, false);
+        let (bytes, report) = encode(&tile, &budget, rank).unwrap();
+
+        let dropped: Vec<Option<u64>> = report.dropped.iter().map(|dropped| dropped.id).collect();
+        assert_eq!(dropped, (1..=10).map(Some).collect::<Vec<_>>());
+        assert!(report
+            .dropped
+            .iter()
+            .all(|dropped| dropped.layer == "buildings" && dropped.reason == DropReason::Tiny));
+
+        let encoded = read::Tile::from_bytes(&bytes).unwrap();
+        assert_eq!(encoded.layers[0].features.len(), 40);
+        assert_eq!(encoded.layers[1], tile.layers[1]);
+    }
+
+    #[test]
+    fn closed_lines() {
+        // A wobbly ring road, which simplification must not mistake for a collapsed line
+        let mut line: Vec<(i32, i32)> = (0..200).map(|step| (step * 10, step % 2)).collect();
+        line.extend((0..200).map(|step| (2000 - step * 10, 1000 + step % 2)));
+        line.push((0, 0));
+
+        let tile = read::Tile {
+            layers: vec![read::Layer {
+                name: "roads".into(),
+                version: 2,
+                extent: 4096,
+                features: vec![read::Feature {
+                    id: Some(1),
+                    tags: vec![],
+                    geometry: Geometry::Line(line),
+                }],
+            }],
+        };
+
+        let (original, _) = encode(&tile, &Budget::default(), rank).unwrap();
+        let budget = Budget::new(original.len() / 2);
+        let (bytes, report) = encode(&tile, &budget, rank).unwrap();
+
+        assert!(report.fits(&budget));
+        assert!(!report.tolerances.is_empty());
+        assert!(report.dropped.is_empty());
+
+        let tile = read::Tile::from_bytes(&bytes).unwrap();
+        match &tile.layers[0].features[0].geometry {
+            Geometry::Line(line) => assert_eq!(line.first(), line.last()),
+            geometry => panic!("unexpected geometry {:?}", geometry),
+        }
+    }
+}
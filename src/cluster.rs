@@ -0,0 +1,490 @@
+use super::common::{TileCoord, Value};
+
+use super::cover::LonLat;
+use super::tile::{lon_lat_to_unit, unit_to_lon_lat, TileId};
+use super::write::{self, EncodableGeometry};
+
+use std::collections::HashMap;
+
+/// How a numeric attribute of the clustered points is combined into the cluster. Non-numeric values are
+/// ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aggregation {
+    /// Sum of the values as a `Value::Double`.
+    Sum,
+    /// The smallest value, keeping its type.
+    Min,
+    /// The largest value, keeping its type.
+    Max,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClusterOptions {
+    /// Cluster radius in pixels of a tile of `extent` pixels.
+    pub radius: f64,
+    pub extent: u32,
+    /// Lowest zoom level clustered. Queries below it return the clusters of `min_zoom`. Lowered to `max_zoom`
+    /// if above it.
+    pub min_zoom: u8,
+    /// Points are returned unclustered above this zoom level. Lowered to `TileId::MAX_ZOOM` if above it.
+    pub max_zoom: u8,
+    /// Minimum number of points forming a cluster.
+    pub min_points: usize,
+    /// Attributes aggregated into cluster features, under the same key.
+    pub aggregations: Vec<(String, Aggregation)>,
+}
+
+impl Default for ClusterOptions {
+    fn default() -> ClusterOptions {
+        ClusterOptions {
+            radius: 40.0,
+            extent: 512,
+            min_zoom: 0,
+            max_zoom: 16,
+            min_points: 2,
+            aggregations: Vec::new(),
+        }
+    }
+}
+
+/// A cluster or an unclustered input point.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cluster {
+    /// Identifies clusters for `ClusterIndex::children`, `None` for input points.
+    pub id: Option<u64>,
+    pub position: LonLat,
+    pub point_count: usize,
+    /// `point_count` and the aggregated attributes for clusters, the original attributes for points.
+    pub tags: Vec<(String, Value)>,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    x: f64,
+    y: f64,
+    count: usize,
+    /// Index of the input point, for nodes which are not clusters.
+    point: Option<usize>,
+    id: Option<u64>,
+    /// Cluster of the next lower zoom level this node was merged into.
+    parent: Option<u64>,
+    aggregates: Vec<Option<Value>>,
+}
+
+/// Hierarchical greedy clustering of points over a range of zoom levels, in the style of supercluster.
+///
+/// Starting from the input points one level above `max_zoom`, every level is built from the level above it:
+/// points and clusters within `radius` pixels of a not yet merged node are merged into a new cluster placed
+/// at their weighted center.
+pub struct ClusterIndex {
+    options: ClusterOptions,
+    points: Vec<(LonLat, Vec<(String, Value)>)>,
+    /// Nodes of zoom levels `min_zoom..=max_zoom + 1`, each with its node indices sorted by `x`.
+    levels: Vec<(Vec<Node>, Vec<usize>)>,
+}
+
+fn cluster_id(idx: usize, zoom: u8) -> u64 {
+    ((idx as u64) << 5) | zoom as u64
+}
+
+fn aggregate(current: &mut Option<Value>, value: Option<&Value>, aggregation: Aggregation) {
+    let value = match value {
+        Some(value) if value.as_f64().is_some() => value,
+        _ => return,
+    };
+
+    *current = match (current.take(), aggregation) {
+        (None, Aggregation::Sum) => Some(Value::Double(value.as_f64().unwrap())),
+        (None, _) => Some(value.clone()),
+        (Some(Value::Double(sum)), Aggregation::Sum) => Some(Value::Double(sum + value.as_f64().unwrap())),
+        (Some(current), Aggregation::Min) if value.as_f64() < current.as_f64() => Some(value.clone()),
+        (Some(current), Aggregation::Max) if value.as_f64() > current.as_f64() => Some(value.clone()),
+        (current, _) => current,
+    };
+}
+
+impl ClusterIndex {
+    pub fn new(points: Vec<(LonLat, Vec<(String, Value)>)>, mut options: ClusterOptions) -> ClusterIndex {
+        // Cluster ids keep the zoom level in 5 bits, which also covers every valid tile zoom level
+        options.max_zoom = options.max_zoom.min(TileId::MAX_ZOOM);
+        options.min_zoom = options.min_zoom.min(options.max_zoom);
+
+        let nodes: Vec<Node> = points
+            .iter()
+            .enumerate()
+            .map(|(idx, ((lon, lat), tags))| {
+                let (x, y) = lon_lat_to_unit(*lon, *lat);
+                let aggregates = options
+                    .aggregations
+                    .iter()
+                    .map(|(key, aggregation)| {
+                        let mut value = None;
+                        let tag = tags.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+                        aggregate(&mut value, tag, *aggregation);
+                        value
+                    })
+                    .collect();
+
+                Node {
+                    x,
+                    y,
+                    count: 1,
+                    point: Some(idx),
+                    id: None,
+                    parent: None,
+                    aggregates,
+                }
+            })
+            .collect();
+
+        let mut index = ClusterIndex {
+            options,
+            points,
+            levels: Vec::new(),
+        };
+
+        let mut levels = vec![nodes];
+        for zoom in (index.options.min_zoom..=index.options.max_zoom).rev() {
+            let clusters = index.cluster(levels.last_mut().unwrap(), zoom);
+            levels.push(clusters);
+        }
+        levels.reverse();
+
+        index.levels = levels
+            .into_iter()
+            .map(|nodes| {
+                let mut order: Vec<usize> = (0..nodes.len()).collect();
+                order.sort_by(|a, b| nodes[*a].x.total_cmp(&nodes[*b].x));
+                (nodes, order)
+            })
+            .collect();
+
+        index
+    }
+
+    /// Builds the nodes of `zoom` from the nodes of `zoom + 1`, setting their parents.
+    fn cluster(&self, nodes: &mut [Node], zoom: u8) -> Vec<Node> {
+        let radius = self.options.radius / (self.options.extent as f64 * 2f64.powi(zoom as i32));
+
+        let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+        for (idx, node) in nodes.iter().enumerate() {
+            let cell = ((node.x / radius).floor() as i64, (node.y / radius).floor() as i64);
+            grid.entry(cell).or_default().push(idx);
+        }
+
+        let mut merged = vec![false; nodes.len()];
+        let mut clusters = Vec::new();
+
+        for idx in 0..nodes.len() {
+            if merged[idx] {
+                continue;
+            }
+            merged[idx] = true;
+
+            let (x, y) = (nodes[idx].x, nodes[idx].y);
+            let (cell_x, cell_y) = ((x / radius).floor() as i64, (y / radius).floor() as i64);
+
+            let mut neighbours = Vec::new();
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for other in grid.get(&(cell_x + dx, cell_y + dy)).into_iter().flatten() {
+                        let other_node = &nodes[*other];
+                        let distance = (other_node.x - x).powi(2) + (other_node.y - y).powi(2);
+                        if !merged[*other] && distance <= radius * radius {
+                            neighbours.push(*other);
+                        }
+                    }
+                }
+            }
+
+            let count = nodes[idx].count + neighbours.iter().map(|other| nodes[*other].count).sum::<usize>();
+
+            if neighbours.is_empty() || count < self.options.min_points {
+                let mut node = nodes[idx].clone();
+                node.parent = None;
+                clusters.push(node);
+                continue;
+            }
+
+            let id = cluster_id(clusters.len(), zoom);
+            let mut cluster = Node {
+                x: 0.0,
+                y: 0.0,
+                count,
+                point: None,
+                id: Some(id),
+                parent: None,
+                aggregates: vec![None; self.options.aggregations.len()],
+            };
+
+            for member in std::iter::once(idx).chain(neighbours) {
+                merged[member] = true;
+
+                let node = &mut nodes[member];
+                node.parent = Some(id);
+
+                cluster.x += node.x * node.count as f64;
+                cluster.y += node.y * node.count as f64;
+
+                for ((value, (_, aggregation)), current) in node
+                    .aggregates
+                    .iter()
+                    .zip(&self.options.aggregations)
+                    .zip(cluster.aggregates.iter_mut())
+                {
+                    aggregate(current, value.as_ref(), *aggregation);
+                }
+            }
+
+            cluster.x /= count as f64;
+            cluster.y /= count as f64;
+            clusters.push(cluster);
+        }
+
+        clusters
+    }
+
+    fn level(&self, zoom: u8) -> &(Vec<Node>, Vec<usize>) {
+        let zoom = zoom.clamp(self.options.min_zoom, self.options.max_zoom + 1);
+        &self.levels[(zoom - self.options.min_zoom) as usize]
+    }
+
+    fn to_cluster(&self, node: &Node) -> Cluster {
+        let position = unit_to_lon_lat(node.x, node.y);
+
+        match node.point {
+            Some(point) => Cluster {
+                id: None,
+                position,
+                point_count: 1,
+                tags: self.points[point].1.clone(),
+            },
+            None => {
+                let mut tags = vec![("point_count".to_string(), Value::UInt(node.count as u64))];
+                for ((key, _), value) in self.options.aggregations.iter().zip(&node.aggregates) {
+                    if let Some(value) = value {
+                        tags.push((key.clone(), value.clone()));
+                    }
+                }
+
+                Cluster {
+                    id: node.id,
+                    position,
+                    point_count: node.count,
+                    tags,
+                }
+            }
+        }
+    }
+
+    /// Nodes of `zoom` whose Web Mercator unit coordinates fall into the given box.
+    fn query(&self, zoom: u8, min: (f64, f64), max: (f64, f64)) -> impl Iterator<Item = &Node> {
+        let (nodes, order) = self.level(zoom);
+        let start = order.partition_point(|idx| nodes[*idx].x < min.0);
+
+        order[start..]
+            .iter()
+            .map(move |idx| &nodes[*idx])
+            .take_while(move |node| node.x <= max.0)
+            .filter(move |node| node.y >= min.1 && node.y <= max.1)
+    }
+
+    /// Clusters and points at `zoom` within `(west, south, east, north)`.
+    pub fn clusters(&self, bounds: (f64, f64, f64, f64), zoom: u8) -> Vec<Cluster> {
+        let (west, south, east, north) = bounds;
+        let min = lon_lat_to_unit(west, north);
+        let max = lon_lat_to_unit(east, south);
+
+        self.query(zoom, min, max).map(|node| self.to_cluster(node)).collect()
+    }
+
+    /// The clusters and points a cluster was made of, one zoom level higher. `None` for unknown ids.
+    pub fn children(&self, cluster_id: u64) -> Option<Vec<Cluster>> {
+        let zoom = (cluster_id & 0x1f) as u8;
+
+        if zoom < self.options.min_zoom || zoom > self.options.max_zoom {
+            return None;
+        }
+
+        let (nodes, _) = self.level(zoom + 1);
+        let children: Vec<Cluster> = nodes
+            .iter()
+            .filter(|node| node.parent == Some(cluster_id))
+            .map(|node| self.to_cluster(node))
+            .collect();
+
+        if children.is_empty() {
+            None
+        } else {
+            Some(children)
+        }
+    }
+
+    /// The lowest zoom level at which a cluster falls apart into more than one child.
+    pub fn expansion_zoom(&self, cluster_id: u64) -> Option<u8> {
+        let mut id = cluster_id;
+
+        loop {
+            let children = self.children(id)?;
+            let zoom = (id & 0x1f) as u8 + 1;
+
+            match (children.len(), children[0].id) {
+                (1, Some(child)) => id = child,
+                _ => return Some(zoom),
+            }
+        }
+    }
+
+    /// Features for a tile of the given extent: clusters as points tagged with `point_count` and the
+    /// aggregates, with their cluster id as feature id, and unclustered points with their own attributes.
+    /// `buffer` is in tile units, like the extent.
+    pub fn tile_features(&self, id: TileId, extent: u32, buffer: u32) -> Vec<write::Feature> {
+        let scale = 2f64.powi(id.z() as i32);
+        let buffer = buffer as f64 / extent as f64;
+
+        let min = ((id.x() as f64 - buffer) / scale, (id.y() as f64 - buffer) / scale);
+        let max = (
+            (id.x() as f64 + 1.0 + buffer) / scale,
+            (id.y() as f64 + 1.0 + buffer) / scale,
+        );
+
+        self.query(id.z(), min, max)
+            .map(|node| {
+                let position: TileCoord = (
+                    ((node.x * scale - id.x() as f64) * extent as f64).round() as i32,
+                    ((node.y * scale - id.y() as f64) * extent as f64).round() as i32,
+                );
+
+                let cluster = self.to_cluster(node);
+                let mut feature = write::Feature::new(write::Geometry::Point(position).encode().unwrap());
+                feature.id = cluster.id;
+                feature.tags = cluster.tags;
+                feature
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod cluster_test {
+    use super::*;
+
+    fn create_index() -> ClusterIndex {
+        let mut points = Vec::new();
+
+        // A dense group of 10 points around Budapest and a lone point in Vienna
+        for idx in 0..10 {
+            let offset = idx as f64 * 0.001;
+            let tags = vec![
+                ("population".to_string(), Value::UInt(idx)),
+                ("rank".to_string(), Value::Int(idx as i64)),
+            ];
+            points.push(((19.04 + offset, 47.49 + offset), tags));
+        }
+        points.push(((16.37, 48.21), vec![("population".to_string(), Value::UInt(100))]));
+
+        ClusterIndex::new(
+            points,
+            ClusterOptions {
+                aggregations: vec![
+                    ("population".to_string(), Aggregation::Sum),
+                    ("rank".to_string(), Aggregation::Max),
+                ],
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn zoom_range() {
+        let points = vec![((19.04, 47.49), Vec::new()), ((19.05, 47.49), Vec::new())];
+        let bounds = (-180.0, -85.0, 180.0, 85.0);
+
+        let index = ClusterIndex::new(
+            points.clone(),
+            ClusterOptions {
+                max_zoom: 255,
+                ..Default::default()
+            },
+        );
+        assert_eq!(index.clusters(bounds, 255).len(), 2);
+
+        let clusters = index.clusters(bounds, 0);
+        assert_eq!(clusters.len(), 1);
+        let id = clusters[0].id.unwrap();
+        assert_eq!(index.children(id).map(|children| children.len()), Some(2));
+
+        let index = ClusterIndex::new(
+            points,
+            ClusterOptions {
+                min_zoom: 40,
+                max_zoom: 5,
+                ..Default::default()
+            },
+        );
+        assert_eq!(index.clusters(bounds, 0).len(), 1);
+        assert_eq!(index.clusters(bounds, 6).len(), 2);
+    }
+
+    #[test]
+    fn clustering() {
+        let index = create_index();
+        let world = (-180.0, -85.0, 180.0, 85.0);
+
+        let clusters = index.clusters(world, 0);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].point_count, 11);
+        assert_eq!(clusters[0].tags[0], ("point_count".to_string(), Value::UInt(11)));
+        assert_eq!(clusters[0].tags[1], ("population".to_string(), Value::Double(145.0)));
+        assert_eq!(clusters[0].tags[2], ("rank".to_string(), Value::Int(9)));
+
+        let clusters = index.clusters(world, 8);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters.iter().map(|cluster| cluster.point_count).sum::<usize>(), 11);
+
+        let clusters = index.clusters(world, 17);
+        assert_eq!(clusters.len(), 11);
+        assert!(clusters.iter().all(|cluster| cluster.id.is_none()));
+
+        let budapest = index.clusters((18.0, 47.0, 20.0, 48.0), 8);
+        assert_eq!(budapest.len(), 1);
+        assert_eq!(budapest[0].point_count, 10);
+    }
+
+    #[test]
+    fn expansion() {
+        let index = create_index();
+        let root = index.clusters((-180.0, -85.0, 180.0, 85.0), 0)[0].clone();
+
+        let mut total = 0;
+        let mut stack = vec![root.id.unwrap()];
+        while let Some(id) = stack.pop() {
+            for child in index.children(id).unwrap() {
+                match child.id {
+                    Some(id) => stack.push(id),
+                    None => total += 1,
+                }
+            }
+        }
+        assert_eq!(total, 11);
+
+        let zoom = index.expansion_zoom(root.id.unwrap()).unwrap();
+        assert!(index.clusters((-180.0, -85.0, 180.0, 85.0), zoom).len() > 1);
+        assert_eq!(index.clusters((-180.0, -85.0, 180.0, 85.0), zoom - 1).len(), 1);
+        assert_eq!(index.children(12345 << 5), None);
+    }
+
+    #[test]
+    fn tile_features() {
+        let index = create_index();
+        let id = TileId::from_lon_lat(19.04, 47.49, 6).unwrap();
+
+        let features = index.tile_features(id, 4096, 64);
+        assert_eq!(features.len(), 1);
+        assert!(features[0].id.is_some());
+        assert_eq!(features[0].tags[0], ("point_count".to_string(), Value::UInt(10)));
+
+        let layer = write::Layer::new("clusters", features).unwrap();
+        assert!(write::Tile::new(vec![layer]).is_ok());
+    }
+}
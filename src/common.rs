@@ -12,3 +12,65 @@ pub enum Value {
 impl Eq for Value {
     // TODO: binary equality for f32/f64
 }
+
+impl Value {
+    /// The value as a number, `None` for strings and booleans.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Float(v) => Some(*v as f64),
+            Value::Double(v) => Some(*v),
+            Value::Int(v) | Value::SInt(v) => Some(*v as f64),
+            Value::UInt(v) => Some(*v as f64),
+            Value::String(_) | Value::Bool(_) => None,
+        }
+    }
+
+    /// A hashable key identifying the value as it is encoded.
+    pub fn key(&self) -> ValueKey {
+        match self {
+            Value::String(v) => ValueKey::String(v.clone()),
+            Value::Float(v) => ValueKey::Float(v.to_bits()),
+            Value::Double(v) => ValueKey::Double(v.to_bits()),
+            Value::Int(v) => ValueKey::Int(*v),
+            Value::UInt(v) => ValueKey::UInt(*v),
+            Value::SInt(v) => ValueKey::SInt(*v),
+            Value::Bool(v) => ValueKey::Bool(*v),
+        }
+    }
+}
+
+/// Key of a `Value` for hash maps and sets. Floats are compared by their bits, so NaN equals itself and
+/// values of different types (such as `Double(1.0)` and `Int(1)`) stay distinct, as they are in a tile.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ValueKey {
+    String(String),
+    Float(u32),
+    Double(u64),
+    Int(i64),
+    UInt(u64),
+    SInt(i64),
+    Bool(bool),
+}
+
+pub type TileCoord = (i32, i32);
+
+/// Geometry type of a feature as encoded in tiles, where multi-geometries share the type of their parts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GeometryType {
+    Unknown,
+    Point,
+    LineString,
+    Polygon,
+}
+
+impl GeometryType {
+    /// Name as used by the `$type` of style filters.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GeometryType::Unknown => "Unknown",
+            GeometryType::Point => "Point",
+            GeometryType::LineString => "LineString",
+            GeometryType::Polygon => "Polygon",
+        }
+    }
+}
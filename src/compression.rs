@@ -0,0 +1,175 @@
+#[cfg(feature = "gzip")]
+use flate2::read::GzDecoder;
+#[cfg(feature = "gzip")]
+use flate2::write::GzEncoder;
+
+use std::io;
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+use std::io::{Read, Write};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression applied to encoded tiles. Each codec needs its cargo feature (`gzip`, `zstd` or `brotli`),
+/// otherwise compressing or decompressing with it fails with `io::ErrorKind::Unsupported`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Brotli,
+}
+
+impl Compression {
+    /// Guesses the compression of `data` from its magic bytes. Brotli streams have no magic bytes, so they
+    /// are reported as `None`.
+    pub fn detect(data: &[u8]) -> Compression {
+        if data.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if data.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Whether `data` may be compressed this way. Brotli streams cannot be detected, so any data that is not
+    /// gzip or zstd compressed matches `Brotli`.
+    pub fn matches(self, data: &[u8]) -> bool {
+        match (self, Compression::detect(data)) {
+            (Compression::Brotli, Compression::None) => true,
+            (expected, found) => expected == found,
+        }
+    }
+
+    /// The value of the HTTP `Content-Encoding` header for data compressed this way.
+    pub fn content_encoding(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+            Compression::Brotli => Some("br"),
+        }
+    }
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd", feature = "brotli")))]
+fn unsupported(feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} support is disabled, enable the `{}` feature", feature, feature),
+    )
+}
+
+pub fn compress(data: &[u8], compression: Compression) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => gzip(data),
+        #[cfg(not(feature = "gzip"))]
+        Compression::Gzip => Err(unsupported("gzip")),
+
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::encode_all(data, 0),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => Err(unsupported("zstd")),
+
+        #[cfg(feature = "brotli")]
+        Compression::Brotli => {
+            let mut compressed = Vec::new();
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 9, 22);
+                encoder.write_all(data)?;
+            }
+            Ok(compressed)
+        }
+        #[cfg(not(feature = "brotli"))]
+        Compression::Brotli => Err(unsupported("brotli")),
+    }
+}
+
+pub fn decompress(data: &[u8], compression: Compression) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => gunzip(data),
+        #[cfg(not(feature = "gzip"))]
+        Compression::Gzip => Err(unsupported("gzip")),
+
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::decode_all(data),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => Err(unsupported("zstd")),
+
+        #[cfg(feature = "brotli")]
+        Compression::Brotli => {
+            let mut decompressed = Vec::new();
+            brotli::Decompressor::new(data, 4096).read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        }
+        #[cfg(not(feature = "brotli"))]
+        Compression::Brotli => Err(unsupported("brotli")),
+    }
+}
+
+#[cfg(feature = "gzip")]
+pub(crate) fn gzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+#[cfg(feature = "gzip")]
+pub(crate) fn gunzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod compression_test {
+    use super::*;
+
+    fn round_trip(compression: Compression) {
+        let data = b"some tile data, some tile data, some tile data".to_vec();
+
+        let compressed = compress(&data, compression).unwrap();
+        assert_eq!(decompress(&compressed, compression).unwrap(), data);
+
+        if compression != Compression::Brotli {
+            assert_eq!(Compression::detect(&compressed), compression);
+        }
+    }
+
+    #[test]
+    fn no_compression() {
+        round_trip(Compression::None);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_round_trip() {
+        round_trip(Compression::Gzip);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trip() {
+        round_trip(Compression::Zstd);
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn brotli_round_trip() {
+        round_trip(Compression::Brotli);
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn disabled_codec() {
+        let error = compress(b"data", Compression::Zstd).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,291 @@
+use super::tile::{lon_lat_to_unit, TileId};
+
+use std::collections::BTreeMap;
+
+/// WGS84 position as `(longitude, latitude)` in degrees.
+pub type LonLat = (f64, f64);
+type LonLats<'a> = &'a [LonLat];
+
+pub enum Geometry<'a> {
+    Point(LonLat),
+    MultiPoint(LonLats<'a>),
+    Line(LonLats<'a>),
+    MultiLine(&'a [LonLats<'a>]),
+    Polygon(LonLats<'a>, &'a [LonLats<'a>]),
+    MultiPolygon(&'a [(LonLats<'a>, &'a [LonLats<'a>])]),
+}
+
+/// Returns the sorted list of tiles at zoom level `z` that a geometry touches.
+///
+/// A tile counts as touched if the geometry intersects it after growing the tile by `buffer` on every side.
+/// The buffer is a fraction of the tile size, so a 64 unit buffer of a 4096 extent tile is `64.0 / 4096.0`.
+///
+/// Lines and polygon boundaries are traced exactly and polygon interiors are filled row by row, so the
+/// cost is proportional to the number of vertices plus the number of tile rows, not to the bounding box.
+///
+/// Returns no tiles if `z` is above `TileId::MAX_ZOOM` or the geometry or buffer is not finite.
+pub fn tiles(geometry: &Geometry, z: u8, buffer: f64) -> Vec<TileId> {
+    if z > TileId::MAX_ZOOM || !buffer.is_finite() || !is_finite(geometry) {
+        return Vec::new();
+    }
+
+    let mut cover = Cover::new(z, buffer);
+
+    match geometry {
+        Geometry::Point(point) => cover.point(*point),
+        Geometry::MultiPoint(points) => points.iter().for_each(|point| cover.point(*point)),
+        Geometry::Line(line) => cover.line(line),
+        Geometry::MultiLine(lines) => lines.iter().for_each(|line| cover.line(line)),
+        Geometry::Polygon(exterior_ring, interior_rings) => cover.polygon(exterior_ring, interior_rings),
+        Geometry::MultiPolygon(polygons) => polygons
+            .iter()
+            .for_each(|(exterior_ring, interior_rings)| cover.polygon(exterior_ring, interior_rings)),
+    }
+
+    cover.into_tiles()
+}
+
+fn is_finite(geometry: &Geometry) -> bool {
+    let points = |points: &[LonLat]| points.iter().all(|(lon, lat)| lon.is_finite() && lat.is_finite());
+    let polygon = |exterior_ring: &[LonLat], interior_rings: &[LonLats]| {
+        points(exterior_ring) && interior_rings.iter().all(|ring| points(ring))
+    };
+
+    match geometry {
+        Geometry::Point(point) => points(std::slice::from_ref(point)),
+        Geometry::MultiPoint(multi_points) => points(multi_points),
+        Geometry::Line(line) => points(line),
+        Geometry::MultiLine(lines) => lines.iter().all(|line| points(line)),
+        Geometry::Polygon(exterior_ring, interior_rings) => polygon(exterior_ring, interior_rings),
+        Geometry::MultiPolygon(polygons) => polygons
+            .iter()
+            .all(|(exterior_ring, interior_rings)| polygon(exterior_ring, interior_rings)),
+    }
+}
+
+type TilePoint = (f64, f64);
+
+struct Cover {
+    z: u8,
+    size: f64,
+    buffer: f64,
+    /// Covered column spans per row, possibly overlapping.
+    rows: BTreeMap<u32, Vec<(u32, u32)>>,
+}
+
+impl Cover {
+    fn new(z: u8, buffer: f64) -> Cover {
+        Cover {
+            z,
+            size: (1u64 << z) as f64,
+            buffer: buffer.max(0.0),
+            rows: BTreeMap::new(),
+        }
+    }
+
+    fn project(&self, (lon, lat): LonLat) -> TilePoint {
+        let (x, y) = lon_lat_to_unit(lon, lat);
+        (x * self.size, y * self.size)
+    }
+
+    /// Adds the tiles of `row` whose columns intersect `[x_min, x_max]` in tile units.
+    fn span(&mut self, row: f64, x_min: f64, x_max: f64) {
+        let max = self.size - 1.0;
+
+        if row < 0.0 || row > max || x_max < 0.0 || x_min > max || x_min > x_max {
+            return;
+        }
+
+        self.rows
+            .entry(row as u32)
+            .or_default()
+            .push((x_min.max(0.0) as u32, x_max.min(max) as u32));
+    }
+
+    fn point(&mut self, point: LonLat) {
+        let point = self.project(point);
+        self.segment(point, point);
+    }
+
+    fn line(&mut self, line: &[LonLat]) {
+        let points: Vec<TilePoint> = line.iter().map(|point| self.project(*point)).collect();
+
+        if points.len() == 1 {
+            self.segment(points[0], points[0]);
+        }
+
+        for pair in points.windows(2) {
+            self.segment(pair[0], pair[1]);
+        }
+    }
+
+    /// Covers the tiles whose buffered box intersects the segment. The segment is clipped to the buffered
+    /// band of each row it crosses, which gives the exact column range for that row.
+    fn segment(&mut self, from: TilePoint, to: TilePoint) {
+        let buffer = self.buffer;
+        let (y_min, y_max) = (from.1.min(to.1), from.1.max(to.1));
+
+        let first_row = (y_min - buffer).floor().max(0.0);
+        let last_row = (y_max + buffer).floor().min(self.size - 1.0);
+
+        let mut row = first_row;
+        while row <= last_row {
+            let (band_min, band_max) = (row - buffer, row + 1.0 + buffer);
+
+            let (x_min, x_max) = if from.1 == to.1 {
+                (from.0.min(to.0), from.0.max(to.0))
+            } else {
+                let t_a = ((band_min - from.1) / (to.1 - from.1)).clamp(0.0, 1.0);
+                let t_b = ((band_max - from.1) / (to.1 - from.1)).clamp(0.0, 1.0);
+                let x_a = from.0 + t_a * (to.0 - from.0);
+                let x_b = from.0 + t_b * (to.0 - from.0);
+                (x_a.min(x_b), x_a.max(x_b))
+            };
+
+            self.span(row, (x_min - buffer).floor(), (x_max + buffer).floor());
+
+            row += 1.0;
+        }
+    }
+
+    /// Covers the boundary of a polygon, then fills every row with the tiles whose centers lie inside.
+    fn polygon(&mut self, exterior_ring: &[LonLat], interior_rings: &[LonLats]) {
+        let rings: Vec<Vec<TilePoint>> = std::iter::once(exterior_ring)
+            .chain(interior_rings.iter().copied())
+            .map(|ring| ring.iter().map(|point| self.project(*point)).collect())
+            .collect();
+
+        let mut crossings: BTreeMap<i64, Vec<f64>> = BTreeMap::new();
+
+        for ring in &rings {
+            if ring.len() < 3 {
+                continue;
+            }
+
+            for (idx, from) in ring.iter().enumerate() {
+                let to = ring[(idx + 1) % ring.len()];
+
+                self.segment(*from, to);
+
+                // Rows whose center line crosses the edge, counting the lower end point only
+                let (y_min, y_max) = (from.1.min(to.1), from.1.max(to.1));
+                let first_row = (y_min - 0.5).ceil() as i64;
+                let last_row = (y_max - 0.5).ceil() as i64 - 1;
+
+                for row in first_row.max(0)..=last_row.min(self.size as i64 - 1) {
+                    let center = row as f64 + 0.5;
+                    let x = from.0 + (center - from.1) * (to.0 - from.0) / (to.1 - from.1);
+                    crossings.entry(row).or_default().push(x);
+                }
+            }
+        }
+
+        for (row, mut xs) in crossings {
+            xs.sort_by(|a, b| a.total_cmp(b));
+
+            for pair in xs.chunks_exact(2) {
+                self.span(row as f64, (pair[0] - 0.5).ceil(), (pair[1] - 0.5).floor());
+            }
+        }
+    }
+
+    fn into_tiles(self) -> Vec<TileId> {
+        let z = self.z;
+        let mut tiles = Vec::new();
+
+        for (y, mut spans) in self.rows {
+            spans.sort_unstable();
+
+            let mut merged: Vec<(u32, u32)> = Vec::with_capacity(spans.len());
+            for (start, end) in spans {
+                match merged.last_mut() {
+                    Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                    _ => merged.push((start, end)),
+                }
+            }
+
+            for (start, end) in merged {
+                tiles.extend((start..=end).filter_map(|x| TileId::new(z, x, y)));
+            }
+        }
+
+        tiles.sort_unstable();
+        tiles
+    }
+}
+
+#[cfg(test)]
+mod cover_test {
+    use super::*;
+    use crate::tile::unit_to_lon_lat;
+
+    /// Converts tile space coordinates at zoom level `z` to WGS84.
+    fn lon_lat(z: u8, points: &[(f64, f64)]) -> Vec<LonLat> {
+        let size = (1u32 << z) as f64;
+        points
+            .iter()
+            .map(|(x, y)| unit_to_lon_lat(x / size, y / size))
+            .collect()
+    }
+
+    fn tile_ids(z: u8, ids: &[(u32, u32)]) -> Vec<TileId> {
+        let mut tiles: Vec<TileId> = ids.iter().map(|(x, y)| TileId::new(z, *x, *y).unwrap()).collect();
+        tiles.sort_unstable();
+        tiles
+    }
+
+    #[test]
+    fn point() {
+        let point = lon_lat(4, &[(3.5, 7.99)])[0];
+        assert_eq!(tiles(&Geometry::Point(point), 4, 0.0), tile_ids(4, &[(3, 7)]));
+        assert_eq!(tiles(&Geometry::Point(point), 4, 0.05), tile_ids(4, &[(3, 7), (3, 8)]));
+        assert_eq!(tiles(&Geometry::Point((0.0, 0.0)), 0, 0.0), tile_ids(0, &[(0, 0)]));
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert_eq!(tiles(&Geometry::Point((10.0, 10.0)), 40, 0.0), vec![]);
+        assert_eq!(tiles(&Geometry::Point((f64::NAN, 10.0)), 4, 0.0), vec![]);
+        assert_eq!(tiles(&Geometry::Point((10.0, 10.0)), 4, f64::INFINITY), vec![]);
+
+        let ring = [(0.0, 0.0), (10.0, 0.0), (f64::NAN, 10.0)];
+        assert_eq!(tiles(&Geometry::Polygon(&ring, &[]), 20, 0.0), vec![]);
+    }
+
+    #[test]
+    fn diagonal_line() {
+        let line = lon_lat(4, &[(0.5, 0.3), (3.5, 3.6)]);
+        assert_eq!(
+            tiles(&Geometry::Line(&line), 4, 0.0),
+            tile_ids(4, &[(0, 0), (1, 0), (1, 1), (2, 1), (2, 2), (2, 3), (3, 3)])
+        );
+    }
+
+    #[test]
+    fn polygon_with_hole() {
+        let exterior = lon_lat(6, &[(0.5, 0.5), (9.5, 0.5), (9.5, 9.5), (0.5, 9.5)]);
+        let interior = lon_lat(6, &[(3.5, 3.5), (3.5, 6.5), (6.5, 6.5), (6.5, 3.5)]);
+
+        let covered = tiles(&Geometry::Polygon(&exterior, &[&interior]), 6, 0.0);
+
+        let mut expected = Vec::new();
+        for x in 0..10 {
+            for y in 0..10 {
+                if !(4..6).contains(&x) || !(4..6).contains(&y) {
+                    expected.push((x, y));
+                }
+            }
+        }
+
+        assert_eq!(covered, tile_ids(6, &expected));
+    }
+
+    #[test]
+    fn large_polygon() {
+        let exterior = lon_lat(
+            14,
+            &[(8000.5, 5000.5), (9000.5, 5000.5), (9000.5, 6000.5), (8000.5, 6000.5)],
+        );
+        assert_eq!(tiles(&Geometry::Polygon(&exterior, &[]), 14, 0.0).len(), 1001 * 1001);
+    }
+}
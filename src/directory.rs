@@ -0,0 +1,225 @@
+#[cfg(feature = "gzip")]
+use super::compression::gzip;
+
+use super::error::ArchiveError;
+
+use super::metadata::Metadata;
+
+use super::store::TileStore;
+use super::tile::TileId;
+use super::write;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const EXTENSION: &str = "pbf";
+const METADATA_FILE: &str = "metadata.json";
+
+/// A tile store writing every tile into its own `{z}/{x}/{y}.pbf` file, with the tileset metadata as
+/// TileJSON in `metadata.json`. Suitable for debugging and for serving tiles from a static file server.
+pub struct DirectoryStore {
+    root: PathBuf,
+    #[cfg_attr(not(feature = "gzip"), allow(dead_code))]
+    gzip: bool,
+}
+
+impl DirectoryStore {
+    /// Creates the root directory if needed. Tiles are written uncompressed unless gzip is enabled.
+    pub fn create<P: AsRef<Path>>(root: P) -> Result<DirectoryStore, ArchiveError> {
+        fs::create_dir_all(&root)?;
+
+        Ok(DirectoryStore {
+            root: root.as_ref().to_path_buf(),
+            gzip: false,
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<DirectoryStore, ArchiveError> {
+        if !root.as_ref().is_dir() {
+            return Err(ArchiveError::InvalidArchive(format!(
+                "{} is not a directory",
+                root.as_ref().display()
+            )));
+        }
+
+        Ok(DirectoryStore {
+            root: root.as_ref().to_path_buf(),
+            gzip: false,
+        })
+    }
+
+    /// Gzip compresses tiles written by `put_tile`. Servers then have to send `Content-Encoding: gzip`.
+    #[cfg(feature = "gzip")]
+    pub fn with_gzip(mut self, gzip: bool) -> DirectoryStore {
+        self.gzip = gzip;
+        self
+    }
+
+    fn tile_path(&self, id: TileId) -> PathBuf {
+        self.root
+            .join(id.z().to_string())
+            .join(id.x().to_string())
+            .join(format!("{}.{}", id.y(), EXTENSION))
+    }
+
+    /// Encodes a tile, compresses it if gzip is enabled, then stores it.
+    pub fn put_tile(&mut self, id: TileId, tile: write::Tile) -> Result<(), ArchiveError> {
+        let data = tile.to_bytes();
+
+        #[cfg(feature = "gzip")]
+        let data = if self.gzip { gzip(&data)? } else { data };
+
+        self.put(id, &data)
+    }
+
+    /// All tiles found in the directory tree. Files not following the `{z}/{x}/{y}.pbf` pattern are skipped.
+    pub fn tile_ids(&self) -> Result<Vec<TileId>, ArchiveError> {
+        let numbered_entries = |path: &Path, suffix: &str| -> Result<Vec<(u32, PathBuf)>, ArchiveError> {
+            let mut entries = Vec::new();
+
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                let name = entry.file_name();
+
+                let number = name
+                    .to_str()
+                    .and_then(|name| name.strip_suffix(suffix))
+                    .and_then(|name| name.parse().ok());
+
+                if let Some(number) = number {
+                    entries.push((number, entry.path()));
+                }
+            }
+
+            Ok(entries)
+        };
+
+        let mut ids = Vec::new();
+        let tile_suffix = format!(".{}", EXTENSION);
+
+        for (z, z_path) in numbered_entries(&self.root, "")? {
+            if !z_path.is_dir() {
+                continue;
+            }
+
+            for (x, x_path) in numbered_entries(&z_path, "")? {
+                if !x_path.is_dir() {
+                    continue;
+                }
+
+                for (y, _) in numbered_entries(&x_path, &tile_suffix)? {
+                    if z <= TileId::MAX_ZOOM as u32 {
+                        ids.extend(TileId::new(z as u8, x, y));
+                    }
+                }
+            }
+        }
+
+        ids.sort_unstable();
+
+        Ok(ids)
+    }
+}
+
+impl TileStore for DirectoryStore {
+    /// Stores already encoded tile data as is.
+    fn put(&mut self, id: TileId, data: &[u8]) -> Result<(), ArchiveError> {
+        let path = self.tile_path(id);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, data)?;
+
+        Ok(())
+    }
+
+    fn get(&mut self, id: TileId) -> Result<Option<Vec<u8>>, ArchiveError> {
+        match fs::read(self.tile_path(id)) {
+            Ok(data) => Ok(Some(data)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn iter(&mut self) -> Result<Box<dyn Iterator<Item = TileId>>, ArchiveError> {
+        Ok(Box::new(self.tile_ids()?.into_iter()))
+    }
+
+    /// Writes the metadata as TileJSON pointing to the tiles relative to `metadata.json`.
+    fn set_metadata(&mut self, metadata: &Metadata) -> Result<(), ArchiveError> {
+        let tiles = vec![format!("{{z}}/{{x}}/{{y}}.{}", EXTENSION)];
+        let tilejson = metadata.to_tilejson(&tiles);
+
+        fs::write(self.root.join(METADATA_FILE), tilejson.to_string())?;
+
+        Ok(())
+    }
+
+    fn metadata(&mut self) -> Result<Metadata, ArchiveError> {
+        let json = match fs::read(self.root.join(METADATA_FILE)) {
+            Ok(json) => json,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Metadata::default()),
+            Err(error) => return Err(error.into()),
+        };
+
+        let json = serde_json::from_slice(&json)
+            .map_err(|error| ArchiveError::InvalidArchive(format!("invalid {}: {}", METADATA_FILE, error)))?;
+
+        Ok(Metadata::from_json(&json))
+    }
+
+    /// Files are written immediately, so there is nothing left to do.
+    fn finalize(&mut self) -> Result<(), ArchiveError> {
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod directory_test {
+    use super::*;
+    use crate::common::Value;
+    use crate::write::EncodableGeometry;
+
+    fn create_test_tile() -> write::Tile {
+        let mut feature = write::Feature::new(write::Geometry::Point((10, 20)).encode().unwrap());
+        feature.add_tag("name", Value::String("test".into()));
+        write::Tile::new(vec![write::Layer::new("poi", vec![feature]).unwrap()]).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let ids = [TileId::new(2, 1, 3).unwrap(), TileId::new(14, 8800, 5760).unwrap()];
+
+        let mut store = DirectoryStore::create(dir.path()).unwrap().with_gzip(true);
+        for id in ids.iter() {
+            store.put_tile(*id, create_test_tile()).unwrap();
+        }
+
+        let metadata = Metadata {
+            name: Some("test".into()),
+            ..Default::default()
+        };
+        store.set_metadata(&metadata).unwrap();
+        store.finalize().unwrap();
+
+        assert!(dir.path().join("14/8800/5760.pbf").is_file());
+        fs::write(dir.path().join("14/8800/README"), "not a tile").unwrap();
+
+        let mut store = DirectoryStore::open(dir.path()).unwrap();
+        assert_eq!(store.tile_ids().unwrap(), ids.to_vec());
+        assert_eq!(store.metadata().unwrap(), metadata);
+
+        let tile = store.read_tile(ids[1]).unwrap().unwrap();
+        assert_eq!(tile.layer("poi").unwrap().features.len(), 1);
+        assert!(store.read_tile(TileId::new(2, 0, 0).unwrap()).unwrap().is_none());
+
+        let tilejson: serde_json::Value =
+            serde_json::from_slice(&fs::read(dir.path().join("metadata.json")).unwrap()).unwrap();
+        assert_eq!(tilejson["tilejson"], "3.0.0");
+        assert_eq!(tilejson["tiles"][0], "{z}/{x}/{y}.pbf");
+    }
+}
@@ -1,3 +1,6 @@
+use super::compression::Compression;
+use super::tile::TileId;
+
 use std::error;
 use std::fmt;
 
@@ -80,3 +83,344 @@ impl error::Error for SpecViolation {
         None
     }
 }
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReadError {
+    Protobuf(String),
+    InvalidTagIndex(u32),
+    InvalidValue,
+    InvalidGeometry,
+    Decompression(String),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::Protobuf(message) => write!(f, "Protobuf error: {}", message),
+            ReadError::InvalidTagIndex(idx) => write!(f, "Tag index {} is out of range", idx),
+            ReadError::InvalidValue => write!(f, "A value should contain exactly one field"),
+            ReadError::InvalidGeometry => write!(f, "Invalid geometry command sequence"),
+            ReadError::Decompression(message) => write!(f, "Decompression error: {}", message),
+        }
+    }
+}
+
+impl error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+impl From<quick_protobuf::Error> for ReadError {
+    fn from(error: quick_protobuf::Error) -> ReadError {
+        ReadError::Protobuf(error.to_string())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WktError {
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    UnsupportedGeometry(String),
+    InvalidCoordinate(String),
+    NestingTooDeep,
+}
+
+impl fmt::Display for WktError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WktError::UnexpectedEnd => write!(f, "Unexpected end of WKT input"),
+            WktError::UnexpectedToken(token) => write!(f, "Unexpected WKT token: {}", token),
+            WktError::UnsupportedGeometry(name) => write!(f, "Unsupported WKT geometry type: {}", name),
+            WktError::InvalidCoordinate(value) => write!(f, "Invalid WKT coordinate: {}", value),
+            WktError::NestingTooDeep => write!(f, "WKT geometry collections are nested too deeply"),
+        }
+    }
+}
+
+impl error::Error for WktError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WkbError {
+    UnexpectedEnd,
+    InvalidByteOrder(u8),
+    UnsupportedGeometry(u32),
+    InvalidCoordinate,
+    TrailingBytes(usize),
+    NestingTooDeep,
+}
+
+impl fmt::Display for WkbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WkbError::UnexpectedEnd => write!(f, "Unexpected end of WKB input"),
+            WkbError::InvalidByteOrder(order) => write!(f, "Invalid WKB byte order marker: {}", order),
+            WkbError::UnsupportedGeometry(code) => write!(f, "Unsupported WKB geometry type: {}", code),
+            WkbError::InvalidCoordinate => write!(f, "WKB coordinate does not fit into tile coordinates"),
+            WkbError::TrailingBytes(count) => write!(f, "{} bytes left after the WKB geometry", count),
+            WkbError::NestingTooDeep => write!(f, "WKB geometry collections are nested too deeply"),
+        }
+    }
+}
+
+impl error::Error for WkbError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io(std::io::Error),
+    #[cfg(feature = "mbtiles")]
+    Sqlite(rusqlite::Error),
+    InvalidArchive(String),
+    /// The store cannot be written, either because of its format or because it has been finalized.
+    ReadOnly,
+    /// Tile data put into a store is not compressed the way the store expects.
+    CompressionMismatch {
+        expected: Compression,
+        found: Compression,
+    },
+    Read(ReadError),
+    Write(SpecViolation),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveError::Io(error) => write!(f, "I/O error: {}", error),
+            #[cfg(feature = "mbtiles")]
+            ArchiveError::Sqlite(error) => write!(f, "SQLite error: {}", error),
+            ArchiveError::InvalidArchive(reason) => write!(f, "Invalid archive: {}", reason),
+            ArchiveError::ReadOnly => write!(f, "Archive is read-only"),
+            ArchiveError::CompressionMismatch { expected, found } => write!(
+                f,
+                "Tile data is compressed with {:?}, but the archive expects {:?}",
+                found, expected
+            ),
+            ArchiveError::Read(error) => write!(f, "Invalid tile: {}", error),
+            ArchiveError::Write(error) => write!(f, "Cannot encode tile: {}", error),
+        }
+    }
+}
+
+impl error::Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ArchiveError::Io(error) => Some(error),
+            #[cfg(feature = "mbtiles")]
+            ArchiveError::Sqlite(error) => Some(error),
+            ArchiveError::InvalidArchive(_) | ArchiveError::ReadOnly | ArchiveError::CompressionMismatch { .. } => None,
+            ArchiveError::Read(error) => Some(error),
+            ArchiveError::Write(error) => Some(error),
+        }
+    }
+}
+
+impl From<std::io::Error> for ArchiveError {
+    fn from(error: std::io::Error) -> ArchiveError {
+        ArchiveError::Io(error)
+    }
+}
+
+#[cfg(feature = "mbtiles")]
+impl From<rusqlite::Error> for ArchiveError {
+    fn from(error: rusqlite::Error) -> ArchiveError {
+        ArchiveError::Sqlite(error)
+    }
+}
+
+impl From<ReadError> for ArchiveError {
+    fn from(error: ReadError) -> ArchiveError {
+        ArchiveError::Read(error)
+    }
+}
+
+impl From<SpecViolation> for ArchiveError {
+    fn from(error: SpecViolation) -> ArchiveError {
+        ArchiveError::Write(error)
+    }
+}
+
+impl From<JoinError> for ArchiveError {
+    fn from(error: JoinError) -> ArchiveError {
+        match error {
+            JoinError::Read(error) => ArchiveError::Read(error),
+            JoinError::Write(error) => ArchiveError::Write(error),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ZoomError {
+    Read(ReadError),
+    Write(SpecViolation),
+    /// The tile to derive is not a descendant (or ancestor) of the given tile.
+    UnrelatedTiles(TileId, TileId),
+}
+
+impl fmt::Display for ZoomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZoomError::Read(error) => write!(f, "Invalid tile: {}", error),
+            ZoomError::Write(error) => write!(f, "Cannot encode tile: {}", error),
+            ZoomError::UnrelatedTiles(from, to) => write!(f, "Tile {} cannot be derived from tile {}", to, from),
+        }
+    }
+}
+
+impl error::Error for ZoomError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ZoomError::Read(error) => Some(error),
+            ZoomError::Write(error) => Some(error),
+            ZoomError::UnrelatedTiles(..) => None,
+        }
+    }
+}
+
+impl From<ReadError> for ZoomError {
+    fn from(error: ReadError) -> ZoomError {
+        ZoomError::Read(error)
+    }
+}
+
+impl From<SpecViolation> for ZoomError {
+    fn from(error: SpecViolation) -> ZoomError {
+        ZoomError::Write(error)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum JoinError {
+    Read(ReadError),
+    Write(SpecViolation),
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinError::Read(error) => write!(f, "Invalid tile: {}", error),
+            JoinError::Write(error) => write!(f, "Cannot encode tile: {}", error),
+        }
+    }
+}
+
+impl error::Error for JoinError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            JoinError::Read(error) => Some(error),
+            JoinError::Write(error) => Some(error),
+        }
+    }
+}
+
+impl From<ReadError> for JoinError {
+    fn from(error: ReadError) -> JoinError {
+        JoinError::Read(error)
+    }
+}
+
+impl From<SpecViolation> for JoinError {
+    fn from(error: SpecViolation) -> JoinError {
+        JoinError::Write(error)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RewriteError {
+    Read(ReadError),
+    Write(SpecViolation),
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RewriteError::Read(error) => write!(f, "Invalid tile: {}", error),
+            RewriteError::Write(error) => write!(f, "Cannot encode tile: {}", error),
+        }
+    }
+}
+
+impl error::Error for RewriteError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            RewriteError::Read(error) => Some(error),
+            RewriteError::Write(error) => Some(error),
+        }
+    }
+}
+
+impl From<ReadError> for RewriteError {
+    fn from(error: ReadError) -> RewriteError {
+        RewriteError::Read(error)
+    }
+}
+
+impl From<quick_protobuf::Error> for RewriteError {
+    fn from(error: quick_protobuf::Error) -> RewriteError {
+        RewriteError::Read(error.into())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FilterError {
+    InvalidJson(String),
+    InvalidExpression(String),
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterError::InvalidJson(error) => write!(f, "Invalid filter JSON: {}", error),
+            FilterError::InvalidExpression(expression) => write!(f, "Invalid filter expression: {}", expression),
+        }
+    }
+}
+
+impl error::Error for FilterError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+impl From<serde_json::Error> for FilterError {
+    fn from(error: serde_json::Error) -> FilterError {
+        FilterError::InvalidJson(error.to_string())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum GeoJsonError {
+    InvalidGeoJson(String),
+    Write(SpecViolation),
+}
+
+impl fmt::Display for GeoJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GeoJsonError::InvalidGeoJson(value) => write!(f, "Invalid GeoJSON object: {}", value),
+            GeoJsonError::Write(error) => write!(f, "Cannot encode tile: {}", error),
+        }
+    }
+}
+
+impl error::Error for GeoJsonError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            GeoJsonError::Write(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<SpecViolation> for GeoJsonError {
+    fn from(error: SpecViolation) -> GeoJsonError {
+        GeoJsonError::Write(error)
+    }
+}
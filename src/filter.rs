@@ -0,0 +1,389 @@
+use super::error::FilterError;
+
+use super::read;
+use super::rewrite::FeatureRef;
+use super::stats::value_to_json;
+use super::write;
+
+use serde_json::{json, Value as JsonValue};
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expression {
+    Literal(JsonValue),
+    Get(String),
+    Has(String),
+    GeometryType,
+    Id,
+    Zoom,
+    Not(Box<Expression>),
+    All(Vec<Expression>),
+    Any(Vec<Expression>),
+    Compare(Comparison, Box<Expression>, Box<Expression>),
+    In(Box<Expression>, Vec<JsonValue>),
+    /// Input, labels with their output and the fallback output.
+    Match(Box<Expression>, Vec<(Vec<JsonValue>, Expression)>, Box<Expression>),
+}
+
+/// A filter in the syntax of MapLibre style layers, e.g. `["==", ["get", "class"], "primary"]`.
+///
+/// Both the legacy syntax (`["==", "class", "primary"]`, `["in", "class", "primary", "secondary"]`,
+/// `["!has", "name"]`, `$type`, `$id`, `none`) and the following expressions are supported: `get`, `has`,
+/// `!`, `all`, `any`, `==`, `!=`, `<`, `<=`, `>`, `>=`, `in`, `match`, `literal`, `geometry-type`, `id`
+/// and `zoom`. Comparing values of different types is false, except for `!=`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    expression: Expression,
+}
+
+impl Filter {
+    pub fn from_json(json: &JsonValue) -> Result<Filter, FilterError> {
+        Ok(Filter {
+            expression: parse(json)?,
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Filter, FilterError> {
+        Filter::from_json(&serde_json::from_str(text)?)
+    }
+
+    /// True if the feature passes the filter at `zoom`. A `Rewriter` is given a filter with
+    /// `rewriter.with_filter(move |feature| filter.evaluate(feature, zoom))`.
+    pub fn evaluate(&self, feature: &FeatureRef, zoom: f64) -> bool {
+        is_true(&evaluate(&self.expression, feature, zoom))
+    }
+
+    /// Removes the features of a decoded layer not passing the filter.
+    pub fn filter_layer(&self, layer: &mut read::Layer, zoom: f64) {
+        let name = layer.name.as_str();
+        layer.features.retain(|feature| {
+            let feature = FeatureRef {
+                layer: name,
+                id: feature.id,
+                geometry_type: feature.geometry.geometry_type(),
+                tags: feature.tags.iter().map(|(key, value)| (key.as_str(), value)).collect(),
+            };
+            self.evaluate(&feature, zoom)
+        });
+    }
+
+    /// Keeps the features passing the filter, to be used before building the `write::Layer` named `layer`.
+    pub fn filter_features(&self, layer: &str, features: Vec<write::Feature>, zoom: f64) -> Vec<write::Feature> {
+        features
+            .into_iter()
+            .filter(|feature| {
+                let feature_ref = FeatureRef {
+                    layer,
+                    id: feature.id,
+                    geometry_type: feature.geometry_type(),
+                    tags: feature.tags.iter().map(|(key, value)| (key.as_str(), value)).collect(),
+                };
+                self.evaluate(&feature_ref, zoom)
+            })
+            .collect()
+    }
+}
+
+fn invalid(json: &JsonValue) -> FilterError {
+    FilterError::InvalidExpression(json.to_string())
+}
+
+/// Key of the legacy syntax, with the special `$type` and `$id` keys.
+fn legacy_key(json: &JsonValue) -> Result<Expression, FilterError> {
+    match json.as_str() {
+        Some("$type") => Ok(Expression::GeometryType),
+        Some("$id") => Ok(Expression::Id),
+        Some(key) => Ok(Expression::Get(key.to_string())),
+        None => Err(invalid(json)),
+    }
+}
+
+fn key_argument(json: &JsonValue, args: &[JsonValue]) -> Result<String, FilterError> {
+    match args {
+        [JsonValue::String(key)] => Ok(key.clone()),
+        _ => Err(invalid(json)),
+    }
+}
+
+fn literal_value(json: &JsonValue) -> Result<JsonValue, FilterError> {
+    match json {
+        JsonValue::Array(_) | JsonValue::Object(_) => Err(invalid(json)),
+        value => Ok(value.clone()),
+    }
+}
+
+fn parse(json: &JsonValue) -> Result<Expression, FilterError> {
+    let (op, args) = match json {
+        JsonValue::Array(array) => match array.split_first() {
+            Some((JsonValue::String(op), args)) => (op.as_str(), args),
+            _ => return Err(invalid(json)),
+        },
+        JsonValue::Object(_) => return Err(invalid(json)),
+        value => return Ok(Expression::Literal(value.clone())),
+    };
+
+    let all = |args: &[JsonValue]| args.iter().map(parse).collect::<Result<Vec<_>, _>>();
+
+    let expression = match op {
+        "literal" => match args {
+            [value] => Expression::Literal(value.clone()),
+            _ => return Err(invalid(json)),
+        },
+        "get" => Expression::Get(key_argument(json, args)?),
+        "has" => Expression::Has(key_argument(json, args)?),
+        "!has" => Expression::Not(Box::new(Expression::Has(key_argument(json, args)?))),
+        "geometry-type" | "id" | "zoom" if !args.is_empty() => return Err(invalid(json)),
+        "geometry-type" => Expression::GeometryType,
+        "id" => Expression::Id,
+        "zoom" => Expression::Zoom,
+        "!" => match args {
+            [arg] => Expression::Not(Box::new(parse(arg)?)),
+            _ => return Err(invalid(json)),
+        },
+        "all" => Expression::All(all(args)?),
+        "any" => Expression::Any(all(args)?),
+        "none" => Expression::Not(Box::new(Expression::Any(all(args)?))),
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => {
+            let comparison = match op {
+                "==" => Comparison::Equal,
+                "!=" => Comparison::NotEqual,
+                "<" => Comparison::Less,
+                "<=" => Comparison::LessOrEqual,
+                ">" => Comparison::Greater,
+                _ => Comparison::GreaterOrEqual,
+            };
+            let (left, right) = match args {
+                // Legacy filters compare a key to a value
+                [key, value] if !key.is_array() && !value.is_array() => {
+                    (legacy_key(key)?, Expression::Literal(value.clone()))
+                }
+                [left, right] => (parse(left)?, parse(right)?),
+                _ => return Err(invalid(json)),
+            };
+            Expression::Compare(comparison, Box::new(left), Box::new(right))
+        }
+        "in" => match args {
+            [needle, haystack] if haystack.is_array() => {
+                let values = match parse(haystack)? {
+                    Expression::Literal(JsonValue::Array(values)) => values,
+                    _ => return Err(invalid(json)),
+                };
+                Expression::In(Box::new(parse(needle)?), values)
+            }
+            [key, values @ ..] => Expression::In(
+                Box::new(legacy_key(key)?),
+                values.iter().map(literal_value).collect::<Result<_, _>>()?,
+            ),
+            _ => return Err(invalid(json)),
+        },
+        "!in" => match args {
+            [key, values @ ..] => Expression::Not(Box::new(Expression::In(
+                Box::new(legacy_key(key)?),
+                values.iter().map(literal_value).collect::<Result<_, _>>()?,
+            ))),
+            _ => return Err(invalid(json)),
+        },
+        "match" => {
+            if args.len() < 4 || args.len() % 2 != 0 {
+                return Err(invalid(json));
+            }
+            let input = parse(&args[0])?;
+            let arms = args[1..args.len() - 1]
+                .chunks(2)
+                .map(|arm| {
+                    let labels = match &arm[0] {
+                        JsonValue::Array(labels) => labels.iter().map(literal_value).collect::<Result<_, _>>()?,
+                        label => vec![literal_value(label)?],
+                    };
+                    Ok((labels, parse(&arm[1])?))
+                })
+                .collect::<Result<Vec<_>, FilterError>>()?;
+            let fallback = parse(&args[args.len() - 1])?;
+            Expression::Match(Box::new(input), arms, Box::new(fallback))
+        }
+        _ => return Err(invalid(json)),
+    };
+
+    Ok(expression)
+}
+
+fn is_true(value: &JsonValue) -> bool {
+    value.as_bool() == Some(true)
+}
+
+/// Numbers are equal whatever their representation, other values if they are identical.
+fn equal(left: &JsonValue, right: &JsonValue) -> bool {
+    match (left.as_f64(), right.as_f64()) {
+        (Some(left), Some(right)) => left == right,
+        _ => left == right,
+    }
+}
+
+fn compare(left: &JsonValue, right: &JsonValue) -> Option<Ordering> {
+    match (left, right) {
+        (JsonValue::Number(left), JsonValue::Number(right)) => left.as_f64()?.partial_cmp(&right.as_f64()?),
+        (JsonValue::String(left), JsonValue::String(right)) => Some(left.cmp(right)),
+        _ => None,
+    }
+}
+
+fn evaluate<'a>(expression: &'a Expression, feature: &FeatureRef, zoom: f64) -> Cow<'a, JsonValue> {
+    let value = match expression {
+        Expression::Literal(value) => return Cow::Borrowed(value),
+        Expression::Get(key) => feature.tag(key).map_or(JsonValue::Null, value_to_json),
+        Expression::Has(key) => json!(feature.tag(key).is_some()),
+        Expression::GeometryType => json!(feature.geometry_type.as_str()),
+        Expression::Id => feature.id.map_or(JsonValue::Null, |id| json!(id)),
+        Expression::Zoom => json!(zoom),
+        Expression::Not(expression) => json!(!is_true(&evaluate(expression, feature, zoom))),
+        Expression::All(expressions) => json!(expressions
+            .iter()
+            .all(|expression| is_true(&evaluate(expression, feature, zoom)))),
+        Expression::Any(expressions) => json!(expressions
+            .iter()
+            .any(|expression| is_true(&evaluate(expression, feature, zoom)))),
+        Expression::Compare(comparison, left, right) => {
+            let (left, right) = (evaluate(left, feature, zoom), evaluate(right, feature, zoom));
+            let ordering = compare(&left, &right);
+            json!(match comparison {
+                Comparison::Equal => equal(&left, &right),
+                Comparison::NotEqual => !equal(&left, &right),
+                Comparison::Less => ordering == Some(Ordering::Less),
+                Comparison::LessOrEqual => ordering.is_some_and(|ordering| ordering != Ordering::Greater),
+                Comparison::Greater => ordering == Some(Ordering::Greater),
+                Comparison::GreaterOrEqual => ordering.is_some_and(|ordering| ordering != Ordering::Less),
+            })
+        }
+        Expression::In(needle, values) => {
+            let needle = evaluate(needle, feature, zoom);
+            json!(values.iter().any(|value| equal(&needle, value)))
+        }
+        Expression::Match(input, arms, fallback) => {
+            let input = evaluate(input, feature, zoom);
+            let output = arms
+                .iter()
+                .find(|(labels, _)| labels.iter().any(|label| equal(&input, label)))
+                .map_or(&**fallback, |(_, output)| output);
+            return evaluate(output, feature, zoom);
+        }
+    };
+
+    Cow::Owned(value)
+}
+
+#[cfg(test)]
+mod filter_test {
+    use super::*;
+    use crate::common::{GeometryType, Value};
+    use crate::rewrite::Rewriter;
+    use crate::write::EncodableGeometry;
+
+    fn road<'a>(tags: &[(&'a str, &'a Value)]) -> FeatureRef<'a> {
+        FeatureRef {
+            layer: "roads",
+            id: Some(7),
+            geometry_type: GeometryType::LineString,
+            tags: tags.to_vec(),
+        }
+    }
+
+    fn matches(filter: &str, feature: &FeatureRef, zoom: f64) -> bool {
+        Filter::parse(filter).unwrap().evaluate(feature, zoom)
+    }
+
+    #[test]
+    fn expressions() {
+        let (primary, lanes) = (Value::String("primary".to_string()), Value::UInt(2));
+        let feature = road(&[("class", &primary), ("lanes", &lanes)]);
+
+        assert!(matches(r#"["==", ["get", "class"], "primary"]"#, &feature, 10.0));
+        assert!(!matches(r#"["!=", ["get", "class"], "primary"]"#, &feature, 10.0));
+        assert!(matches(
+            r#"["all", ["has", "lanes"], [">=", ["get", "lanes"], 2.0]]"#,
+            &feature,
+            10.0
+        ));
+        assert!(!matches(
+            r#"["any", ["!", ["has", "class"]], ["<", ["get", "lanes"], 2]]"#,
+            &feature,
+            10.0
+        ));
+        assert!(matches(r#"["==", ["geometry-type"], "LineString"]"#, &feature, 10.0));
+        assert!(matches(r#"["==", ["id"], 7]"#, &feature, 10.0));
+        assert!(matches(
+            r#"["in", ["get", "class"], ["literal", ["primary", "trunk"]]]"#,
+            &feature,
+            10.0
+        ));
+        assert!(!matches(r#"[">=", ["zoom"], 12]"#, &feature, 10.0));
+        assert!(matches(
+            r#"["match", ["get", "class"], ["motorway", "trunk"], false, "primary", [">", ["zoom"], 8], false]"#,
+            &feature,
+            10.0
+        ));
+
+        // Values of different types never compare
+        assert!(!matches(r#"["<", ["get", "class"], 3]"#, &feature, 10.0));
+        assert!(!matches(r#"["==", ["get", "lanes"], "2"]"#, &feature, 10.0));
+    }
+
+    #[test]
+    fn legacy() {
+        let primary = Value::String("primary".to_string());
+        let feature = road(&[("class", &primary)]);
+
+        assert!(matches(r#"["==", "class", "primary"]"#, &feature, 0.0));
+        assert!(matches(r#"["==", "$type", "LineString"]"#, &feature, 0.0));
+        assert!(matches(r#"["==", "$id", 7]"#, &feature, 0.0));
+        assert!(matches(r#"["in", "class", "primary", "secondary"]"#, &feature, 0.0));
+        assert!(!matches(r#"["!in", "class", "primary", "secondary"]"#, &feature, 0.0));
+        assert!(matches(r#"["!has", "name"]"#, &feature, 0.0));
+        assert!(matches(r#"["!=", "name", "Main Street"]"#, &feature, 0.0));
+        assert!(matches(
+            r#"["none", ["has", "name"], ["==", "$type", "Point"]]"#,
+            &feature,
+            0.0
+        ));
+
+        assert!(Filter::parse(r#"["==", "class"]"#).is_err());
+        assert!(Filter::parse(r#"["unknown", "class"]"#).is_err());
+        assert!(Filter::parse(r#"{"class": "primary"}"#).is_err());
+        assert!(matches!(Filter::parse("[\"all\""), Err(FilterError::InvalidJson(_))));
+    }
+
+    #[test]
+    fn filtering() {
+        let encode = |class: &str| {
+            let mut feature = write::Feature::new(write::Geometry::Line(&[(0, 0), (10, 10)]).encode().unwrap());
+            feature.add_tag("class", Value::String(class.to_string()));
+            feature
+        };
+        let filter = Filter::parse(r#"["==", ["get", "class"], "primary"]"#).unwrap();
+
+        let features = filter.filter_features("roads", vec![encode("primary"), encode("service")], 14.0);
+        assert_eq!(features.len(), 1);
+
+        let layer = write::Layer::new("roads", vec![encode("primary"), encode("service")]).unwrap();
+        let bytes = write::Tile::new(vec![layer]).unwrap().to_bytes();
+
+        let mut tile = read::Tile::from_bytes(&bytes).unwrap();
+        filter.filter_layer(&mut tile.layers[0], 14.0);
+        assert_eq!(tile.layers[0].features.len(), 1);
+
+        let rewriter = Rewriter::new().with_filter(move |feature| filter.evaluate(feature, 14.0));
+        let rewritten = read::Tile::from_bytes(&rewriter.rewrite(&bytes).unwrap().unwrap()).unwrap();
+        assert_eq!(rewritten.layers[0].features, tile.layers[0].features);
+    }
+}
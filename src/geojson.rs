@@ -0,0 +1,358 @@
+use super::common::{TileCoord, Value};
+
+use super::error::GeoJsonError;
+
+use super::quantize::PointF64;
+use super::read;
+use super::stats::value_to_json;
+use super::tile::{lon_lat_to_unit, unit_to_lon_lat, TileId};
+use super::transform::{GeometryF64, TileTransform};
+use super::write::{self, EncodableGeometry};
+
+use serde_json::{json, Map, Value as JsonValue};
+
+/// Converts a decoded tile to a GeoJSON `FeatureCollection`.
+///
+/// With the `TileId` of the tile, coordinates are WGS84 longitudes and latitudes, otherwise they are left in
+/// tile coordinates. The layer of every feature is written to its `layer` member.
+pub fn to_geojson(tile: &read::Tile, id: Option<TileId>) -> JsonValue {
+    let mut features = Vec::new();
+
+    for layer in &tile.layers {
+        let extent = layer.extent as f64;
+        let position = |point: &TileCoord| -> JsonValue {
+            match id {
+                Some(id) => {
+                    let size = (1u64 << id.z()) as f64;
+                    let (lon, lat) = unit_to_lon_lat(
+                        (id.x() as f64 + point.0 as f64 / extent) / size,
+                        (id.y() as f64 + point.1 as f64 / extent) / size,
+                    );
+                    json!([lon, lat])
+                }
+                None => json!([point.0, point.1]),
+            }
+        };
+
+        for feature in &layer.features {
+            let mut object = Map::new();
+            object.insert("type".to_string(), json!("Feature"));
+            if let Some(id) = feature.id {
+                object.insert("id".to_string(), json!(id));
+            }
+            object.insert("layer".to_string(), json!(layer.name));
+            object.insert(
+                "properties".to_string(),
+                JsonValue::Object(
+                    feature
+                        .tags
+                        .iter()
+                        .map(|(key, value)| (key.clone(), value_to_json(value)))
+                        .collect(),
+                ),
+            );
+            object.insert("geometry".to_string(), geometry_to_json(&feature.geometry, &position));
+            features.push(JsonValue::Object(object));
+        }
+    }
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+fn geometry_to_json(geometry: &read::Geometry, position: &dyn Fn(&TileCoord) -> JsonValue) -> JsonValue {
+    let points = |points: &[TileCoord]| points.iter().map(position).collect::<Vec<_>>();
+    // GeoJSON rings repeat their first position
+    let ring = |ring: &[TileCoord]| {
+        let mut positions = points(ring);
+        positions.extend(ring.first().map(position));
+        positions
+    };
+    let polygon = |exterior_ring: &[TileCoord], interior_rings: &[Vec<TileCoord>]| {
+        std::iter::once(ring(exterior_ring))
+            .chain(interior_rings.iter().map(|interior_ring| ring(interior_ring)))
+            .collect::<Vec<_>>()
+    };
+
+    let (r#type, coordinates) = match geometry {
+        read::Geometry::Point(point) => ("Point", position(point)),
+        read::Geometry::MultiPoint(multi_points) => ("MultiPoint", json!(points(multi_points))),
+        read::Geometry::Line(line) => ("LineString", json!(points(line))),
+        read::Geometry::MultiLine(lines) => (
+            "MultiLineString",
+            json!(lines.iter().map(|line| points(line)).collect::<Vec<_>>()),
+        ),
+        read::Geometry::Polygon(exterior_ring, interior_rings) => {
+            ("Polygon", json!(polygon(exterior_ring, interior_rings)))
+        }
+        read::Geometry::MultiPolygon(polygons) => (
+            "MultiPolygon",
+            json!(polygons
+                .iter()
+                .map(|(exterior_ring, interior_rings)| polygon(exterior_ring, interior_rings))
+                .collect::<Vec<_>>()),
+        ),
+    };
+
+    json!({
+        "type": r#type,
+        "coordinates": coordinates,
+    })
+}
+
+/// Builds the tile `id` from a GeoJSON `FeatureCollection`, `Feature` or geometry in WGS84 coordinates.
+///
+/// Features go to the layer named by their `layer` member, or to `layer`. Geometries are clipped to the
+/// tile and its `buffer` and snapped to the grid with `transform::TileTransform`, and features left without
+/// geometry are dropped. Members of geometry collections become features of their own without an id.
+/// Properties that are arrays or objects are stored as JSON strings and null properties are dropped.
+pub fn from_geojson(
+    json: &JsonValue,
+    id: TileId,
+    extent: u32,
+    buffer: u32,
+    layer: &str,
+) -> Result<write::Tile, GeoJsonError> {
+    let transform = TileTransform::unit(id, extent).with_buffer(buffer);
+    let mut layers: Vec<(String, Vec<write::Feature>)> = Vec::new();
+
+    let objects = match json["type"].as_str() {
+        Some("FeatureCollection") => json["features"]
+            .as_array()
+            .ok_or_else(|| invalid(json))?
+            .iter()
+            .collect(),
+        _ => vec![json],
+    };
+
+    for object in objects {
+        let (geometry, properties, feature_id, name) = match object["type"].as_str() {
+            Some("Feature") => (
+                &object["geometry"],
+                object["properties"].as_object(),
+                object["id"].as_u64(),
+                object["layer"].as_str().unwrap_or(layer),
+            ),
+            _ => (object, None, None, layer),
+        };
+
+        let mut geometries = Vec::new();
+        flatten(geometry, &mut geometries)?;
+        let feature_id = if geometries.len() == 1 { feature_id } else { None };
+
+        for geometry in geometries {
+            let encoded = match transform_geometry(geometry, &transform)?.map(|geometry| geometry.encode()) {
+                Some(Ok(encoded)) => encoded,
+                _ => continue,
+            };
+
+            let mut feature = write::Feature::new(encoded);
+            feature.id = feature_id;
+            for (key, value) in properties.into_iter().flatten() {
+                if let Some(value) = property_value(value) {
+                    feature.add_tag(key.as_str(), value);
+                }
+            }
+
+            match layers.iter_mut().find(|(other, _)| other == name) {
+                Some((_, features)) => features.push(feature),
+                None => layers.push((name.to_string(), vec![feature])),
+            }
+        }
+    }
+
+    let layers = layers
+        .into_iter()
+        .map(|(name, features)| {
+            let mut layer = write::Layer::new(name, features)?;
+            layer.extent = extent;
+            Ok(layer)
+        })
+        .collect::<Result<Vec<_>, GeoJsonError>>()?;
+
+    Ok(write::Tile::new(layers)?)
+}
+
+fn invalid(json: &JsonValue) -> GeoJsonError {
+    let mut text = json.to_string();
+    if text.len() > 80 {
+        let end = (0..=77).rev().find(|idx| text.is_char_boundary(*idx)).unwrap_or(0);
+        text.truncate(end);
+        text.push_str("...");
+    }
+    GeoJsonError::InvalidGeoJson(text)
+}
+
+/// Collects the members of geometry collections, skipping null geometries.
+fn flatten<'a>(geometry: &'a JsonValue, geometries: &mut Vec<&'a JsonValue>) -> Result<(), GeoJsonError> {
+    match geometry["type"].as_str() {
+        _ if geometry.is_null() => {}
+        Some("GeometryCollection") => {
+            for member in geometry["geometries"].as_array().ok_or_else(|| invalid(geometry))? {
+                flatten(member, geometries)?;
+            }
+        }
+        _ => geometries.push(geometry),
+    }
+    Ok(())
+}
+
+fn position(json: &JsonValue) -> Result<PointF64, GeoJsonError> {
+    match json.as_array().map(Vec::as_slice) {
+        Some([lon, lat, ..]) => match (lon.as_f64(), lat.as_f64()) {
+            (Some(lon), Some(lat)) => Ok(lon_lat_to_unit(lon, lat)),
+            _ => Err(invalid(json)),
+        },
+        _ => Err(invalid(json)),
+    }
+}
+
+fn positions(json: &JsonValue) -> Result<Vec<PointF64>, GeoJsonError> {
+    json.as_array()
+        .ok_or_else(|| invalid(json))?
+        .iter()
+        .map(position)
+        .collect()
+}
+
+fn lines(json: &JsonValue) -> Result<Vec<Vec<PointF64>>, GeoJsonError> {
+    json.as_array()
+        .ok_or_else(|| invalid(json))?
+        .iter()
+        .map(positions)
+        .collect()
+}
+
+fn transform_geometry(json: &JsonValue, transform: &TileTransform) -> Result<Option<read::Geometry>, GeoJsonError> {
+    let coordinates = &json["coordinates"];
+
+    let geometry = match json["type"].as_str() {
+        Some("Point") => transform.transform(&GeometryF64::Point(position(coordinates)?)),
+        Some("MultiPoint") => transform.transform(&GeometryF64::MultiPoint(&positions(coordinates)?)),
+        Some("LineString") => transform.transform(&GeometryF64::Line(&positions(coordinates)?)),
+        Some("MultiLineString") => {
+            let lines = lines(coordinates)?;
+            let lines: Vec<&[PointF64]> = lines.iter().map(Vec::as_slice).collect();
+            transform.transform(&GeometryF64::MultiLine(&lines))
+        }
+        Some("Polygon") => {
+            let rings = lines(coordinates)?;
+            let interior_rings: Vec<&[PointF64]> = rings.iter().skip(1).map(Vec::as_slice).collect();
+            match rings.first() {
+                Some(exterior_ring) => transform.transform(&GeometryF64::Polygon(exterior_ring, &interior_rings)),
+                None => None,
+            }
+        }
+        Some("MultiPolygon") => {
+            let polygons = coordinates
+                .as_array()
+                .ok_or_else(|| invalid(coordinates))?
+                .iter()
+                .map(lines)
+                .collect::<Result<Vec<_>, _>>()?;
+            let interior_rings: Vec<Vec<&[PointF64]>> = polygons
+                .iter()
+                .map(|rings| rings.iter().skip(1).map(Vec::as_slice).collect())
+                .collect();
+            let polygons: Vec<(&[PointF64], &[&[PointF64]])> = polygons
+                .iter()
+                .zip(&interior_rings)
+                .filter_map(|(rings, interior_rings)| Some((rings.first()?.as_slice(), interior_rings.as_slice())))
+                .collect();
+            transform.transform(&GeometryF64::MultiPolygon(&polygons))
+        }
+        _ => return Err(invalid(json)),
+    };
+
+    Ok(geometry)
+}
+
+fn property_value(json: &JsonValue) -> Option<Value> {
+    match json {
+        JsonValue::Null => None,
+        JsonValue::Bool(value) => Some(Value::Bool(*value)),
+        JsonValue::Number(number) => Some(match (number.as_u64(), number.as_i64()) {
+            (Some(value), _) => Value::UInt(value),
+            (None, Some(value)) => Value::SInt(value),
+            _ => Value::Double(number.as_f64()?),
+        }),
+        JsonValue::String(value) => Some(Value::String(value.clone())),
+        value => Some(Value::String(value.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod geojson_test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let id = TileId::new(1, 1, 0).unwrap();
+        let (west, south, east, north) = id.bounds();
+        let center = ((west + east) / 2.0, 66.513_260_443_111_86);
+
+        let json = json!({
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "id": 7,
+                    "properties": {"name": "center", "rank": -1, "tags": ["a"], "missing": null},
+                    "geometry": {"type": "Point", "coordinates": [center.0, center.1]},
+                },
+                {
+                    "type": "Feature",
+                    "layer": "areas",
+                    "properties": {},
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [[[west, south], [east, south], [east, north], [west, north], [west, south]]],
+                    },
+                },
+                {
+                    "type": "Feature",
+                    "properties": {},
+                    "geometry": {"type": "Point", "coordinates": [-90.0, 45.0]},
+                },
+            ],
+        });
+
+        let tile = from_geojson(&json, id, 4096, 16, "points").unwrap();
+        let tile = read::Tile::from_bytes(&tile.to_bytes()).unwrap();
+
+        let points = tile.layer("points").unwrap();
+        assert_eq!(points.features.len(), 1);
+        assert_eq!(points.features[0].id, Some(7));
+        assert_eq!(points.features[0].geometry, read::Geometry::Point((2048, 2048)));
+        assert_eq!(
+            points.features[0].tags,
+            vec![
+                ("name".to_string(), Value::String("center".to_string())),
+                ("rank".to_string(), Value::SInt(-1)),
+                ("tags".to_string(), Value::String("[\"a\"]".to_string())),
+            ]
+        );
+        assert_eq!(
+            tile.layer("areas").unwrap().features[0].geometry,
+            read::Geometry::Polygon(vec![(0, 0), (4096, 0), (4096, 4096), (0, 4096)], vec![])
+        );
+
+        let geojson = to_geojson(&tile, Some(id));
+        let feature = &geojson["features"][0];
+        assert_eq!(feature["layer"], json!("points"));
+        assert_eq!(feature["id"], json!(7));
+        let coordinates = feature["geometry"]["coordinates"].as_array().unwrap();
+        assert!((coordinates[0].as_f64().unwrap() - center.0).abs() < 1e-9);
+        assert!((coordinates[1].as_f64().unwrap() - center.1).abs() < 1e-6);
+
+        let ring = &to_geojson(&tile, None)["features"][1]["geometry"]["coordinates"][0];
+        assert_eq!(ring, &json!([[0, 0], [4096, 0], [4096, 4096], [0, 4096], [0, 0]]));
+
+        assert!(matches!(
+            from_geojson(&json!({"type": "Circle"}), id, 4096, 16, "points"),
+            Err(GeoJsonError::InvalidGeoJson(_))
+        ));
+    }
+}
@@ -0,0 +1,304 @@
+use super::compression::{compress, decompress, Compression};
+use super::error::{ArchiveError, JoinError, ReadError, SpecViolation};
+use super::metadata::MetadataCollector;
+use super::read::{decode_tags, decode_value};
+use super::store::TileStore;
+use super::tile::TileId;
+use super::write::{self, EncodedGeometry};
+
+use super::proto::vector_tile as pbf;
+
+use quick_protobuf::{BytesReader, MessageRead};
+
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashSet};
+
+/// What `join` does with layers of the same name found in several tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerConflict {
+    /// Puts the features of all of them into one layer. Feature ids already used in the layer are dropped,
+    /// and layers of different extents are renamed instead.
+    Merge,
+    /// Renames the later layers by appending `_2`, `_3`, ... to their name.
+    Rename,
+    /// Fails with `SpecViolation::IdenticalLayerNames`.
+    Fail,
+}
+
+/// Combines the layers of several encoded, possibly compressed, tiles of the same `TileId` into one
+/// uncompressed tile, in the order of the tiles. Geometry commands are copied as they are.
+pub fn join(tiles: &[&[u8]], conflict: LayerConflict) -> Result<Vec<u8>, JoinError> {
+    let tiles = tiles
+        .iter()
+        .map(|bytes| uncompressed(bytes))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(join_tiles(&tiles, conflict)?.to_bytes())
+}
+
+fn uncompressed(bytes: &[u8]) -> Result<Cow<'_, [u8]>, ReadError> {
+    match Compression::detect(bytes) {
+        Compression::None => Ok(Cow::Borrowed(bytes)),
+        compression => decompress(bytes, compression)
+            .map(Cow::Owned)
+            .map_err(|error| ReadError::Decompression(error.to_string())),
+    }
+}
+
+fn join_tiles<T: AsRef<[u8]>>(tiles: &[T], conflict: LayerConflict) -> Result<write::Tile, JoinError> {
+    // Name, extent, features and ids of the joined layers
+    let mut layers: Vec<(String, u32, Vec<write::Feature>, HashSet<u64>)> = Vec::new();
+
+    for bytes in tiles {
+        let bytes = bytes.as_ref();
+        let tile = pbf::Tile::from_reader(&mut BytesReader::from_bytes(bytes), bytes).map_err(ReadError::from)?;
+
+        for layer in tile.layers {
+            let existing = layers.iter().position(|(name, _, _, _)| *name == layer.name);
+
+            let idx = match (existing, conflict) {
+                (None, _) => None,
+                (Some(idx), LayerConflict::Merge) if layers[idx].1 == layer.extent => Some(idx),
+                (Some(_), LayerConflict::Merge) | (Some(_), LayerConflict::Rename) => {
+                    let name = (2..)
+                        .map(|suffix| format!("{}_{}", layer.name, suffix))
+                        .find(|name| layers.iter().all(|(other, _, _, _)| other != name))
+                        .unwrap();
+                    layers.push((name, layer.extent, Vec::new(), HashSet::new()));
+                    Some(layers.len() - 1)
+                }
+                (Some(_), LayerConflict::Fail) => {
+                    return Err(SpecViolation::IdenticalLayerNames(layer.name.into_owned()).into())
+                }
+            };
+
+            let idx = idx.unwrap_or_else(|| {
+                layers.push((layer.name.to_string(), layer.extent, Vec::new(), HashSet::new()));
+                layers.len() - 1
+            });
+            let (_, _, features, ids) = &mut layers[idx];
+
+            let values = layer.values.iter().map(decode_value).collect::<Result<Vec<_>, _>>()?;
+
+            for feature in layer.features {
+                let tags = decode_tags(&feature.tags, &layer.keys, &values)?;
+
+                let mut joined = write::Feature::new(EncodedGeometry::from_commands(feature.type_pb, feature.geometry));
+                joined.id = Some(feature.id).filter(|id| *id != 0 && ids.insert(*id));
+                joined.tags = tags;
+                features.push(joined);
+            }
+        }
+    }
+
+    let layers = layers
+        .into_iter()
+        .filter(|(_, _, features, _)| !features.is_empty())
+        .map(|(name, extent, features, _)| {
+            let mut layer = write::Layer::new(name, features)?;
+            layer.extent = extent;
+            Ok(layer)
+        })
+        .collect::<Result<Vec<_>, SpecViolation>>()?;
+
+    Ok(write::Tile::new(layers)?)
+}
+
+/// Joins every tile found in any of the `sources` into `destination`, returning the number of tiles written.
+///
+/// Tiles are compressed as the destination requires, or like the tile of the first source they were found in
+/// if it has no requirement. The metadata is the one of
+/// the first source with the `vector_layers` of the joined tiles. The destination is not finalized.
+pub fn join_stores<D>(
+    sources: &mut [&mut dyn TileStore],
+    destination: &mut D,
+    conflict: LayerConflict,
+) -> Result<usize, ArchiveError>
+where
+    D: TileStore + ?Sized,
+{
+    let mut ids: BTreeSet<TileId> = BTreeSet::new();
+    for source in sources.iter_mut() {
+        ids.extend(source.iter()?);
+    }
+
+    let mut collector = MetadataCollector::new();
+    let mut count = 0;
+
+    for id in ids {
+        let mut tiles = Vec::with_capacity(sources.len());
+        let mut compression = None;
+
+        for source in sources.iter_mut() {
+            if let Some(data) = source.get(id)? {
+                compression.get_or_insert_with(|| Compression::detect(&data));
+                tiles.push(uncompressed(&data)?.into_owned());
+            }
+        }
+
+        let tile = join_tiles(&tiles, conflict)?;
+        collector.add_tile(id.z(), &tile);
+
+        let compression = destination
+            .tile_compression()
+            .or(compression)
+            .unwrap_or(Compression::None);
+        let data = compress(&tile.to_bytes(), compression)?;
+        destination.put(id, &data)?;
+        count += 1;
+    }
+
+    let metadata = match sources.first_mut() {
+        Some(source) => source.metadata()?,
+        None => Default::default(),
+    };
+    destination.set_metadata(&collector.to_metadata(&metadata))?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod join_test {
+    use super::*;
+    use crate::common::Value;
+    use crate::read;
+    use crate::store::MemoryStore;
+    use crate::write::EncodableGeometry;
+
+    fn encode(layers: &[(&str, &[u64])]) -> Vec<u8> {
+        let layers = layers
+            .iter()
+            .map(|(name, ids)| {
+                let features = ids
+                    .iter()
+                    .map(|id| {
+                        let mut feature =
+                            write::Feature::new(write::Geometry::Point((*id as i32, 0)).encode().unwrap());
+                        feature.id = Some(*id);
+                        feature.add_tag("source", Value::String(name.to_string()));
+                        feature
+                    })
+                    .collect();
+                write::Layer::new(*name, features).unwrap()
+            })
+            .collect();
+        write::Tile::new(layers).unwrap().to_bytes()
+    }
+
+    fn layers(bytes: &[u8]) -> Vec<(String, Vec<Option<u64>>)> {
+        read::Tile::from_bytes(bytes)
+            .unwrap()
+            .layers
+            .into_iter()
+            .map(|layer| (layer.name, layer.features.iter().map(|feature| feature.id).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn joining() {
+        let base = encode(&[("roads", &[1, 2]), ("pois", &[3])]);
+        let overlay = encode(&[("transit", &[1]), ("pois", &[3, 4])]);
+        let tiles = [base.as_slice(), overlay.as_slice()];
+
+        assert_eq!(
+            layers(&join(&tiles, LayerConflict::Merge).unwrap()),
+            vec![
+                ("roads".to_string(), vec![Some(1), Some(2)]),
+                ("pois".to_string(), vec![Some(3), None, Some(4)]),
+                ("transit".to_string(), vec![Some(1)]),
+            ]
+        );
+        assert_eq!(
+            layers(&join(&tiles, LayerConflict::Rename).unwrap()),
+            vec![
+                ("roads".to_string(), vec![Some(1), Some(2)]),
+                ("pois".to_string(), vec![Some(3)]),
+                ("transit".to_string(), vec![Some(1)]),
+                ("pois_2".to_string(), vec![Some(3), Some(4)]),
+            ]
+        );
+        assert_eq!(
+            join(&tiles, LayerConflict::Fail),
+            Err(JoinError::Write(SpecViolation::IdenticalLayerNames("pois".to_string())))
+        );
+    }
+
+    #[test]
+    fn geometry_commands() {
+        // A line with a zero-length segment, copied as it is
+        let commands = vec![9, 10, 10, 10, 0, 0];
+        let geometry = EncodedGeometry::from_commands(pbf::mod_Tile::GeomType::LINESTRING, commands.clone());
+        let layer = write::Layer::new("roads", vec![write::Feature::new(geometry)]).unwrap();
+        let line = write::Tile::new(vec![layer]).unwrap().to_bytes();
+        let pois = encode(&[("pois", &[1])]);
+
+        let bytes = join(&[line.as_slice(), pois.as_slice()], LayerConflict::Merge).unwrap();
+        let tile = pbf::Tile::from_reader(&mut BytesReader::from_bytes(&bytes), &bytes).unwrap();
+
+        assert_eq!(tile.layers.len(), 2);
+        assert_eq!(tile.layers[0].features[0].geometry, commands);
+
+        // Invalid tag indices are reported instead of dropping the feature
+        let mut feature = write::Feature::new(write::Geometry::Point((1, 1)).encode().unwrap());
+        feature.add_tag("name", Value::String("poi".to_string()));
+        let mut message: pbf::Tile = write::Tile::new(vec![write::Layer::new("pois", vec![feature]).unwrap()])
+            .unwrap()
+            .into();
+        message.layers[0].features[0].tags = vec![0, 1];
+        let mut invalid = Vec::new();
+        quick_protobuf::MessageWrite::write_message(&message, &mut quick_protobuf::Writer::new(&mut invalid)).unwrap();
+
+        assert_eq!(
+            join(&[invalid.as_slice()], LayerConflict::Merge),
+            Err(JoinError::Read(ReadError::InvalidTagIndex(1)))
+        );
+    }
+
+    #[test]
+    fn stores() {
+        let (first, second) = (TileId::new(1, 0, 0).unwrap(), TileId::new(1, 1, 1).unwrap());
+
+        let mut base = MemoryStore::new();
+        base.put(first, &encode(&[("roads", &[1])])).unwrap();
+        base.metadata.name = Some("base".to_string());
+
+        let mut overlay = MemoryStore::new();
+        overlay.put(first, &encode(&[("transit", &[1])])).unwrap();
+        overlay.put(second, &encode(&[("transit", &[2])])).unwrap();
+
+        let mut joined = MemoryStore::new();
+        let count = join_stores(&mut [&mut base, &mut overlay], &mut joined, LayerConflict::Merge).unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(joined.read_tile(first).unwrap().unwrap().layers.len(), 2);
+        assert_eq!(joined.metadata.name, Some("base".to_string()));
+        assert_eq!(
+            joined
+                .metadata
+                .vector_layers
+                .iter()
+                .map(|layer| layer.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["roads", "transit"]
+        );
+    }
+
+    #[cfg(feature = "pmtiles")]
+    #[test]
+    fn destination_compression() {
+        let id = TileId::new(0, 0, 0).unwrap();
+
+        let mut source = MemoryStore::new();
+        source.put(id, &encode(&[("roads", &[1])])).unwrap();
+
+        let mut joined = crate::pmtiles::PmTilesWriter::new(Vec::new());
+        assert_eq!(
+            join_stores(&mut [&mut source], &mut joined, LayerConflict::Merge).unwrap(),
+            1
+        );
+        assert_eq!(
+            Compression::detect(&joined.get(id).unwrap().unwrap()),
+            Compression::Gzip
+        );
+    }
+}
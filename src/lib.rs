@@ -1,5 +1,34 @@
+pub mod budget;
+pub mod cluster;
 pub mod common;
+pub mod compression;
+pub mod cover;
+pub mod directory;
 pub mod error;
+pub mod filter;
+pub mod geojson;
+pub mod join;
+pub mod linemerge;
+#[cfg(feature = "mbtiles")]
+pub mod mbtiles;
+pub mod metadata;
+#[cfg(feature = "pmtiles")]
+pub mod pmtiles;
+pub mod quantize;
+pub mod read;
+pub mod rewrite;
+pub mod simplify;
+pub mod stats;
+pub mod store;
+pub mod thin;
+pub mod tile;
+pub mod tiny;
+pub mod transform;
+pub mod union;
+pub mod validate;
+pub mod wkb;
+pub mod wkt;
 pub mod write;
+pub mod zoom;
 
 mod proto;
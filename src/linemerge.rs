@@ -0,0 +1,206 @@
+use super::common::{TileCoord, ValueKey};
+
+use super::read::{Feature, Geometry};
+
+use std::collections::HashMap;
+
+/// Joins lines end-to-end at the points where exactly two line ends meet, reversing lines where needed.
+///
+/// Lines are never joined at junctions of three or more lines, so the result does not depend on which
+/// branch would be picked. Lines are extended in both directions, a chain that comes back to its start
+/// becomes a closed line. The result keeps the order of the first line of every chain.
+pub fn merge_lines(lines: Vec<Vec<TileCoord>>) -> Vec<Vec<TileCoord>> {
+    let lines: Vec<Vec<TileCoord>> = lines.into_iter().filter(|line| line.len() >= 2).collect();
+
+    let mut ends: HashMap<TileCoord, Vec<usize>> = HashMap::new();
+    for (idx, line) in lines.iter().enumerate() {
+        ends.entry(line[0]).or_default().push(idx);
+        ends.entry(line[line.len() - 1]).or_default().push(idx);
+    }
+
+    // The other line ending at `point`, if it is the only one
+    let next = |point: TileCoord, idx: usize| match ends[&point].as_slice() {
+        [a, b] if *a == idx && *b != idx => Some(*b),
+        [a, b] if *b == idx && *a != idx => Some(*a),
+        _ => None,
+    };
+
+    let mut used = vec![false; lines.len()];
+    let mut merged = Vec::new();
+
+    for start in 0..lines.len() {
+        if used[start] {
+            continue;
+        }
+        used[start] = true;
+
+        let mut chain = lines[start].clone();
+
+        for backwards in [false, true].iter() {
+            let mut current = start;
+
+            loop {
+                let point = if *backwards { chain[0] } else { chain[chain.len() - 1] };
+
+                let idx = match next(point, current) {
+                    Some(idx) if !used[idx] => idx,
+                    _ => break,
+                };
+                used[idx] = true;
+                current = idx;
+
+                let mut line = lines[idx].clone();
+                if (line[0] == point) == *backwards {
+                    line.reverse();
+                }
+
+                if *backwards {
+                    line.pop();
+                    line.extend(chain);
+                    chain = line;
+                } else {
+                    chain.extend(line.into_iter().skip(1));
+                }
+            }
+        }
+
+        merged.push(chain);
+    }
+
+    merged
+}
+
+/// Joins line features with identical tags into the longest possible lines, see `merge_lines`.
+///
+/// Each set of tags gives one feature in the place of the first of its lines, a multiline where the lines do
+/// not form a single chain. Merged features lose their ids, other geometries are kept as they are.
+pub fn linemerge(features: Vec<Feature>) -> Vec<Feature> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_tags: HashMap<Vec<(&str, ValueKey)>, usize> = HashMap::new();
+    let mut feature_groups = vec![None; features.len()];
+
+    for (idx, feature) in features.iter().enumerate() {
+        if !matches!(feature.geometry, Geometry::Line(_) | Geometry::MultiLine(_)) {
+            continue;
+        }
+
+        let mut tags: Vec<(&str, ValueKey)> = feature
+            .tags
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.key()))
+            .collect();
+        tags.sort();
+
+        let group = *group_tags.entry(tags).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group].push(idx);
+        feature_groups[idx] = Some(group);
+    }
+
+    let mut features: Vec<Option<Feature>> = features.into_iter().map(Some).collect();
+    let mut merged: Vec<Option<Feature>> = vec![None; groups.len()];
+
+    for (group, members) in groups.iter().enumerate() {
+        if members.len() < 2 {
+            continue;
+        }
+
+        let mut lines = Vec::new();
+        let mut tags = None;
+
+        for idx in members {
+            let feature = features[*idx].take().unwrap();
+            tags.get_or_insert(feature.tags);
+
+            match feature.geometry {
+                Geometry::Line(line) => lines.push(line),
+                Geometry::MultiLine(more_lines) => lines.extend(more_lines),
+                _ => unreachable!(),
+            }
+        }
+
+        let mut lines = merge_lines(lines);
+        let geometry = match lines.len() {
+            0 => continue,
+            1 => Geometry::Line(lines.remove(0)),
+            _ => Geometry::MultiLine(lines),
+        };
+
+        merged[group] = Some(Feature {
+            id: None,
+            tags: tags.unwrap_or_default(),
+            geometry,
+        });
+    }
+
+    features
+        .iter_mut()
+        .zip(feature_groups)
+        .filter_map(|(feature, group)| match (feature.take(), group) {
+            (Some(feature), _) => Some(feature),
+            (None, Some(group)) => merged[group].take(),
+            (None, None) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod linemerge_test {
+    use super::*;
+    use crate::common::Value;
+
+    #[test]
+    fn merging() {
+        // Chain given out of order, with a reversed line
+        let lines = vec![vec![(10, 0), (20, 0)], vec![(0, 0), (10, 0)], vec![(30, 0), (20, 0)]];
+        assert_eq!(merge_lines(lines), vec![vec![(0, 0), (10, 0), (20, 0), (30, 0)]]);
+
+        // Junction of three lines
+        let lines = vec![vec![(0, 0), (10, 0)], vec![(10, 0), (20, 0)], vec![(10, 0), (10, 10)]];
+        assert_eq!(merge_lines(lines.clone()), lines);
+
+        // Closed chain
+        let lines = vec![vec![(0, 0), (10, 0), (10, 10)], vec![(10, 10), (0, 0)]];
+        assert_eq!(merge_lines(lines), vec![vec![(0, 0), (10, 0), (10, 10), (0, 0)]]);
+    }
+
+    #[test]
+    fn features() {
+        let feature = |id: u64, geometry: Geometry, tags: &[(&str, &str)]| Feature {
+            id: Some(id),
+            tags: tags
+                .iter()
+                .map(|(key, value)| (key.to_string(), Value::String(value.to_string())))
+                .collect(),
+            geometry,
+        };
+
+        let primary = [("highway", "primary"), ("name", "Main Street")];
+        let reordered = [("name", "Main Street"), ("highway", "primary")];
+
+        let features = vec![
+            feature(1, Geometry::Line(vec![(0, 0), (10, 0)]), &primary),
+            feature(2, Geometry::Point((5, 5)), &primary),
+            feature(3, Geometry::Line(vec![(20, 0), (10, 0)]), &reordered),
+            feature(4, Geometry::Line(vec![(20, 0), (30, 0)]), &[("highway", "secondary")]),
+            feature(5, Geometry::MultiLine(vec![vec![(50, 0), (60, 0)]]), &primary),
+        ];
+
+        let merged = linemerge(features);
+        let merged: Vec<(Option<u64>, Geometry)> = merged.into_iter().map(|f| (f.id, f.geometry)).collect();
+
+        assert_eq!(
+            merged,
+            vec![
+                (
+                    None,
+                    Geometry::MultiLine(vec![vec![(0, 0), (10, 0), (20, 0)], vec![(50, 0), (60, 0)]])
+                ),
+                (Some(2), Geometry::Point((5, 5))),
+                (Some(4), Geometry::Line(vec![(20, 0), (30, 0)])),
+            ]
+        );
+    }
+}
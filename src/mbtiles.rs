@@ -0,0 +1,463 @@
+use super::compression::{gzip, Compression};
+
+use super::error::ArchiveError;
+
+use super::metadata::Metadata;
+
+use super::store::TileStore;
+use super::tile::TileId;
+use super::write;
+
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+
+use std::path::Path;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS metadata (name TEXT, value TEXT);
+    CREATE UNIQUE INDEX IF NOT EXISTS name ON metadata (name);
+    CREATE TABLE IF NOT EXISTS tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+    CREATE UNIQUE INDEX IF NOT EXISTS tile_index ON tiles (zoom_level, tile_column, tile_row);
+";
+
+const DEDUPLICATED_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS metadata (name TEXT, value TEXT);
+    CREATE UNIQUE INDEX IF NOT EXISTS name ON metadata (name);
+    CREATE TABLE IF NOT EXISTS map (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_id TEXT);
+    CREATE UNIQUE INDEX IF NOT EXISTS map_index ON map (zoom_level, tile_column, tile_row);
+    CREATE TABLE IF NOT EXISTS images (tile_data BLOB, tile_id TEXT);
+    CREATE UNIQUE INDEX IF NOT EXISTS images_id ON images (tile_id);
+    CREATE VIEW IF NOT EXISTS tiles AS
+        SELECT map.zoom_level AS zoom_level, map.tile_column AS tile_column, map.tile_row AS tile_row,
+            images.tile_data AS tile_data
+        FROM map JOIN images ON images.tile_id = map.tile_id;
+";
+
+/// An MBTiles 1.3 archive of gzip compressed vector tiles.
+///
+/// Writes are batched into a single transaction which is committed by `finalize`. Dropping the archive also
+/// commits, but any error is lost, so call `finalize` to find out whether the writes were stored.
+pub struct MbTiles {
+    connection: Connection,
+    deduplicated: bool,
+    in_transaction: bool,
+}
+
+impl MbTiles {
+    /// Creates a new archive. With `deduplicated` set, identical tiles are stored once in an `images` table
+    /// and referenced from a `map` table, and `tiles` becomes a view joining the two.
+    pub fn create<P: AsRef<Path>>(path: P, deduplicated: bool) -> Result<MbTiles, ArchiveError> {
+        let connection = Connection::open(path)?;
+
+        connection.execute_batch(if deduplicated { DEDUPLICATED_SCHEMA } else { SCHEMA })?;
+
+        Ok(MbTiles {
+            connection,
+            deduplicated,
+            in_transaction: false,
+        })
+    }
+
+    /// Opens an existing archive. Fails if the file does not exist rather than creating it.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MbTiles, ArchiveError> {
+        let flags = OpenFlags::default().difference(OpenFlags::SQLITE_OPEN_CREATE);
+        let connection = Connection::open_with_flags(path, flags)?;
+
+        let table_exists = |name: &str| -> Result<bool, ArchiveError> {
+            let count: i64 = connection.query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )?;
+            Ok(count > 0)
+        };
+
+        if !table_exists("tiles")? || !table_exists("metadata")? {
+            return Err(ArchiveError::InvalidArchive("missing tiles or metadata table".into()));
+        }
+
+        let deduplicated = table_exists("map")? && table_exists("images")?;
+
+        Ok(MbTiles {
+            connection,
+            deduplicated,
+            in_transaction: false,
+        })
+    }
+
+    fn begin(&mut self) -> Result<(), ArchiveError> {
+        if !self.in_transaction {
+            self.connection.execute_batch("BEGIN")?;
+            self.in_transaction = true;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), ArchiveError> {
+        if self.in_transaction {
+            self.connection.execute_batch("COMMIT")?;
+            self.in_transaction = false;
+        }
+        Ok(())
+    }
+
+    /// Encodes and gzip compresses a tile, then stores it.
+    pub fn put_tile(&mut self, id: TileId, tile: write::Tile) -> Result<(), ArchiveError> {
+        let data = gzip(&tile.to_bytes())?;
+        self.put(id, &data)
+    }
+
+    /// Returns the `images` id of `data`, inserting it if no identical image is stored yet. Ids are derived
+    /// from a content hash; on a collision with different bytes a numbered suffix is appended.
+    fn image_id(&mut self, data: &[u8]) -> Result<String, ArchiveError> {
+        let hash = content_hash(data);
+
+        for n in 0u32.. {
+            let tile_id = if n == 0 {
+                hash.clone()
+            } else {
+                format!("{}-{}", hash, n)
+            };
+
+            let stored: Option<Vec<u8>> = self
+                .connection
+                .query_row(
+                    "SELECT tile_data FROM images WHERE tile_id = ?1",
+                    params![tile_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            match stored {
+                Some(stored) if stored == data => return Ok(tile_id),
+                Some(_) => continue,
+                None => {
+                    self.connection.execute(
+                        "INSERT INTO images (tile_data, tile_id) VALUES (?1, ?2)",
+                        params![data, tile_id],
+                    )?;
+                    return Ok(tile_id);
+                }
+            }
+        }
+
+        unreachable!()
+    }
+
+    /// All tiles in the archive, in XYZ addressing.
+    pub fn tile_ids(&self) -> Result<Vec<TileId>, ArchiveError> {
+        let mut statement = self.connection.prepare(
+            "SELECT zoom_level, tile_column, tile_row FROM tiles ORDER BY zoom_level, tile_column, tile_row",
+        )?;
+
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, u8>(0)?, row.get::<_, u32>(1)?, row.get::<_, u32>(2)?))
+        })?;
+
+        let mut ids = Vec::new();
+        for row in rows {
+            let (z, x, y) = row?;
+            let tms = TileId::new(z, x, y)
+                .ok_or_else(|| ArchiveError::InvalidArchive(format!("invalid tile {}/{}/{}", z, x, y)))?;
+            ids.push(tms.flip_y());
+        }
+
+        ids.sort_unstable();
+
+        Ok(ids)
+    }
+}
+
+/// 64-bit FNV-1a hash of `data` followed by its length, in hex. Unlike `DefaultHasher` it is stable across
+/// Rust releases, so ids written by one build are found by the next.
+fn content_hash(data: &[u8]) -> String {
+    let hash = data.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+
+    format!("{:016x}{:08x}", hash, data.len())
+}
+
+impl TileStore for MbTiles {
+    /// Stores already encoded and gzip compressed tile data as is. MBTiles rows are numbered from the south
+    /// (TMS), so the row of `id` is flipped.
+    fn put(&mut self, id: TileId, data: &[u8]) -> Result<(), ArchiveError> {
+        let found = Compression::detect(data);
+        if found != Compression::Gzip {
+            return Err(ArchiveError::CompressionMismatch {
+                expected: Compression::Gzip,
+                found,
+            });
+        }
+
+        self.begin()?;
+
+        let tms = id.flip_y();
+
+        if self.deduplicated {
+            let tile_id = self.image_id(data)?;
+
+            self.connection.execute(
+                "INSERT OR REPLACE INTO map (zoom_level, tile_column, tile_row, tile_id) VALUES (?1, ?2, ?3, ?4)",
+                params![tms.z(), tms.x(), tms.y(), tile_id],
+            )?;
+        } else {
+            self.connection.execute(
+                "INSERT OR REPLACE INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)",
+                params![tms.z(), tms.x(), tms.y(), data],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the stored tile data, which is usually gzip compressed.
+    fn get(&mut self, id: TileId) -> Result<Option<Vec<u8>>, ArchiveError> {
+        let tms = id.flip_y();
+
+        let data = self
+            .connection
+            .query_row(
+                "SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+                params![tms.z(), tms.x(), tms.y()],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(data)
+    }
+
+    fn iter(&mut self) -> Result<Box<dyn Iterator<Item = TileId>>, ArchiveError> {
+        Ok(Box::new(self.tile_ids()?.into_iter()))
+    }
+
+    /// Replaces the contents of the `metadata` table. The layer descriptions go into the `json` entry.
+    fn set_metadata(&mut self, metadata: &Metadata) -> Result<(), ArchiveError> {
+        self.begin()?;
+
+        self.connection.execute("DELETE FROM metadata", [])?;
+
+        let mut entries = vec![
+            ("format".to_string(), "pbf".to_string()),
+            ("type".to_string(), "overlay".to_string()),
+        ];
+
+        let strings = [
+            ("name", &metadata.name),
+            ("description", &metadata.description),
+            ("attribution", &metadata.attribution),
+            ("version", &metadata.version),
+        ];
+
+        for (key, value) in strings.iter() {
+            if let Some(value) = value {
+                entries.push((key.to_string(), value.clone()));
+            }
+        }
+
+        if let Some((west, south, east, north)) = metadata.bounds {
+            entries.push(("bounds".into(), format!("{},{},{},{}", west, south, east, north)));
+        }
+        if let Some((lon, lat, zoom)) = metadata.center {
+            entries.push(("center".into(), format!("{},{},{}", lon, lat, zoom)));
+        }
+        if let Some(minzoom) = metadata.minzoom {
+            entries.push(("minzoom".into(), minzoom.to_string()));
+        }
+        if let Some(maxzoom) = metadata.maxzoom {
+            entries.push(("maxzoom".into(), maxzoom.to_string()));
+        }
+
+        let json = serde_json::json!({ "vector_layers": metadata.vector_layers_json() });
+        entries.push(("json".into(), json.to_string()));
+
+        for (name, value) in entries {
+            self.connection.execute(
+                "INSERT INTO metadata (name, value) VALUES (?1, ?2)",
+                params![name, value],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn metadata(&mut self) -> Result<Metadata, ArchiveError> {
+        let mut statement = self.connection.prepare("SELECT name, value FROM metadata")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+
+        let mut object = serde_json::Map::new();
+
+        for row in rows {
+            let (name, value) = row?;
+
+            if name == "json" {
+                if let Ok(serde_json::Value::Object(json)) = serde_json::from_str(&value) {
+                    object.extend(json);
+                }
+            } else {
+                object.insert(name, serde_json::Value::String(value));
+            }
+        }
+
+        Ok(Metadata::from_json(&serde_json::Value::Object(object)))
+    }
+
+    /// Commits all pending writes.
+    fn finalize(&mut self) -> Result<(), ArchiveError> {
+        self.commit()
+    }
+
+    /// MBTiles archives of vector tiles hold gzip compressed tiles.
+    fn tile_compression(&self) -> Option<Compression> {
+        Some(Compression::Gzip)
+    }
+}
+
+impl Drop for MbTiles {
+    fn drop(&mut self) {
+        let _ = self.commit();
+    }
+}
+
+#[cfg(test)]
+mod mbtiles_test {
+    use super::*;
+    use crate::common::Value;
+    use crate::metadata::VectorLayer;
+    use crate::store::{copy, MemoryStore};
+    use crate::write::EncodableGeometry;
+
+    fn create_test_tile(name: &str) -> write::Tile {
+        let mut feature = write::Feature::new(write::Geometry::Point((10, 20)).encode().unwrap());
+        feature.add_tag("name", Value::String(name.into()));
+        write::Tile::new(vec![write::Layer::new("poi", vec![feature]).unwrap()]).unwrap()
+    }
+
+    fn round_trip(deduplicated: bool) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.mbtiles");
+
+        let ids = [TileId::new(3, 1, 1).unwrap(), TileId::new(3, 2, 1).unwrap()];
+
+        let metadata = Metadata {
+            name: Some("test".into()),
+            minzoom: Some(3),
+            maxzoom: Some(3),
+            vector_layers: vec![VectorLayer {
+                id: "poi".into(),
+                fields: vec![("name".to_string(), "String".to_string())].into_iter().collect(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        {
+            let mut archive = MbTiles::create(&path, deduplicated).unwrap();
+            for id in ids.iter() {
+                archive.put_tile(*id, create_test_tile("same")).unwrap();
+            }
+            archive.set_metadata(&metadata).unwrap();
+            archive.finalize().unwrap();
+        }
+
+        let mut archive = MbTiles::open(&path).unwrap();
+        assert_eq!(archive.deduplicated, deduplicated);
+        assert_eq!(archive.tile_ids().unwrap(), ids.to_vec());
+        assert_eq!(archive.metadata().unwrap(), metadata);
+
+        let tile = archive.read_tile(ids[0]).unwrap().unwrap();
+        let feature = &tile.layer("poi").unwrap().features[0];
+        assert_eq!(feature.tag("name"), Some(&Value::String("same".into())));
+
+        assert!(archive.read_tile(TileId::new(3, 0, 0).unwrap()).unwrap().is_none());
+
+        // Rows are stored in TMS order
+        let row: u32 = archive
+            .connection
+            .query_row("SELECT MAX(tile_row) FROM tiles", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(row, 6);
+
+        if deduplicated {
+            let images: u32 = archive
+                .connection
+                .query_row("SELECT COUNT(*) FROM images", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(images, 1);
+        }
+    }
+
+    #[test]
+    fn simple_round_trip() {
+        round_trip(false);
+    }
+
+    #[test]
+    fn deduplicated_round_trip() {
+        round_trip(true);
+    }
+
+    #[test]
+    fn copy_compresses() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = MbTiles::create(dir.path().join("test.mbtiles"), false).unwrap();
+
+        let id = TileId::new(2, 1, 3).unwrap();
+        let data = create_test_tile("raw").to_bytes();
+
+        assert!(matches!(
+            archive.put(id, &data),
+            Err(ArchiveError::CompressionMismatch { .. })
+        ));
+
+        let mut memory = MemoryStore::new();
+        memory.put(id, &data).unwrap();
+        copy(&mut memory, &mut archive).unwrap();
+        archive.finalize().unwrap();
+
+        let stored = archive.get(id).unwrap().unwrap();
+        assert_eq!(Compression::detect(&stored), Compression::Gzip);
+        assert_eq!(crate::compression::gunzip(&stored).unwrap(), data);
+    }
+
+    #[test]
+    fn open_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.mbtiles");
+
+        assert!(matches!(MbTiles::open(&path), Err(ArchiveError::Sqlite(_))));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn hash_collision() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = MbTiles::create(dir.path().join("test.mbtiles"), true).unwrap();
+
+        let data = gzip(&create_test_tile("a").to_bytes()).unwrap();
+        let other = gzip(&create_test_tile("b").to_bytes()).unwrap();
+
+        assert_eq!(content_hash(b"a"), "af63dc4c8601ec8c00000001");
+
+        // An image with different bytes already uses the id of `data`
+        archive
+            .connection
+            .execute(
+                "INSERT INTO images (tile_data, tile_id) VALUES (?1, ?2)",
+                params![other, content_hash(&data)],
+            )
+            .unwrap();
+
+        let id = TileId::new(1, 0, 0).unwrap();
+        archive.put(id, &data).unwrap();
+        archive.put(TileId::new(1, 1, 0).unwrap(), &data).unwrap();
+        archive.finalize().unwrap();
+
+        assert_eq!(archive.get(id).unwrap(), Some(data));
+
+        let images: u32 = archive
+            .connection
+            .query_row("SELECT COUNT(*) FROM images", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(images, 2);
+    }
+}
@@ -0,0 +1,313 @@
+use super::common::Value;
+
+use super::write;
+
+use serde_json::{json, Map, Value as JsonValue};
+
+use std::collections::BTreeMap;
+
+/// Tileset level metadata, stored next to the tiles by the archive formats.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub attribution: Option<String>,
+    pub version: Option<String>,
+    /// `(west, south, east, north)` in degrees.
+    pub bounds: Option<(f64, f64, f64, f64)>,
+    /// `(longitude, latitude, zoom)`.
+    pub center: Option<(f64, f64, u8)>,
+    pub minzoom: Option<u8>,
+    pub maxzoom: Option<u8>,
+    pub vector_layers: Vec<VectorLayer>,
+}
+
+/// Description of a layer as listed in the `vector_layers` metadata entry.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VectorLayer {
+    pub id: String,
+    pub description: Option<String>,
+    /// Attribute names mapped to their type: `String`, `Number` or `Boolean`.
+    pub fields: BTreeMap<String, String>,
+    pub minzoom: Option<u8>,
+    pub maxzoom: Option<u8>,
+}
+
+impl Metadata {
+    /// The metadata as a single JSON object, the way PMTiles stores it.
+    pub fn to_json(&self) -> JsonValue {
+        let mut object = Map::new();
+
+        let strings = [
+            ("name", &self.name),
+            ("description", &self.description),
+            ("attribution", &self.attribution),
+            ("version", &self.version),
+        ];
+
+        for (key, value) in strings.iter() {
+            if let Some(value) = value {
+                object.insert(key.to_string(), json!(value));
+            }
+        }
+
+        if let Some((west, south, east, north)) = self.bounds {
+            object.insert("bounds".into(), json!([west, south, east, north]));
+        }
+        if let Some((lon, lat, zoom)) = self.center {
+            object.insert("center".into(), json!([lon, lat, zoom]));
+        }
+        if let Some(minzoom) = self.minzoom {
+            object.insert("minzoom".into(), json!(minzoom));
+        }
+        if let Some(maxzoom) = self.maxzoom {
+            object.insert("maxzoom".into(), json!(maxzoom));
+        }
+
+        object.insert("vector_layers".into(), self.vector_layers_json());
+
+        JsonValue::Object(object)
+    }
+
+    /// The metadata as a TileJSON 3.0.0 document serving tiles from the given URL templates.
+    pub fn to_tilejson(&self, tiles: &[String]) -> JsonValue {
+        let mut tilejson = self.to_json();
+        tilejson["tilejson"] = json!("3.0.0");
+        tilejson["tiles"] = json!(tiles);
+        tilejson
+    }
+
+    pub fn vector_layers_json(&self) -> JsonValue {
+        JsonValue::Array(self.vector_layers.iter().map(VectorLayer::to_json).collect())
+    }
+
+    /// Reads metadata from a JSON object. Unknown and malformed entries are ignored.
+    pub fn from_json(json: &JsonValue) -> Metadata {
+        let string = |key: &str| json.get(key).and_then(JsonValue::as_str).map(String::from);
+        let zoom = |value: Option<&JsonValue>| value.and_then(JsonValue::as_u64).map(|zoom| zoom as u8);
+        let numbers = |key: &str| -> Vec<f64> {
+            match json.get(key) {
+                Some(JsonValue::Array(values)) => values.iter().filter_map(JsonValue::as_f64).collect(),
+                // MBTiles stores these as comma separated strings
+                Some(JsonValue::String(values)) => values.split(',').filter_map(|v| v.trim().parse().ok()).collect(),
+                _ => Vec::new(),
+            }
+        };
+
+        let bounds = numbers("bounds");
+        let center = numbers("center");
+
+        Metadata {
+            name: string("name"),
+            description: string("description"),
+            attribution: string("attribution"),
+            version: string("version"),
+            bounds: if bounds.len() == 4 {
+                Some((bounds[0], bounds[1], bounds[2], bounds[3]))
+            } else {
+                None
+            },
+            center: if center.len() == 3 {
+                Some((center[0], center[1], center[2] as u8))
+            } else {
+                None
+            },
+            minzoom: zoom(json.get("minzoom")).or_else(|| string("minzoom").and_then(|z| z.parse().ok())),
+            maxzoom: zoom(json.get("maxzoom")).or_else(|| string("maxzoom").and_then(|z| z.parse().ok())),
+            vector_layers: match json.get("vector_layers") {
+                Some(JsonValue::Array(layers)) => layers.iter().filter_map(VectorLayer::from_json).collect(),
+                _ => Vec::new(),
+            },
+        }
+    }
+}
+
+impl VectorLayer {
+    pub fn to_json(&self) -> JsonValue {
+        let mut object = Map::new();
+
+        object.insert("id".into(), json!(self.id));
+        object.insert("fields".into(), json!(self.fields));
+
+        if let Some(description) = &self.description {
+            object.insert("description".into(), json!(description));
+        }
+        if let Some(minzoom) = self.minzoom {
+            object.insert("minzoom".into(), json!(minzoom));
+        }
+        if let Some(maxzoom) = self.maxzoom {
+            object.insert("maxzoom".into(), json!(maxzoom));
+        }
+
+        JsonValue::Object(object)
+    }
+
+    pub fn from_json(json: &JsonValue) -> Option<VectorLayer> {
+        let zoom = |key: &str| json.get(key).and_then(JsonValue::as_u64).map(|zoom| zoom as u8);
+
+        Some(VectorLayer {
+            id: json.get("id")?.as_str()?.to_string(),
+            description: json.get("description").and_then(JsonValue::as_str).map(String::from),
+            fields: match json.get("fields") {
+                Some(JsonValue::Object(fields)) => fields
+                    .iter()
+                    .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                    .collect(),
+                _ => BTreeMap::new(),
+            },
+            minzoom: zoom("minzoom"),
+            maxzoom: zoom("maxzoom"),
+        })
+    }
+}
+
+/// Collects `vector_layers` metadata from the layers encoded while generating a tileset: the attribute keys
+/// with the type of their values and the zoom range of every layer.
+#[derive(Clone, Debug, Default)]
+pub struct MetadataCollector {
+    layers: BTreeMap<String, VectorLayer>,
+}
+
+fn field_type(value: &Value) -> &'static str {
+    match value {
+        Value::String(_) => "String",
+        Value::Bool(_) => "Boolean",
+        _ => "Number",
+    }
+}
+
+impl MetadataCollector {
+    pub fn new() -> MetadataCollector {
+        MetadataCollector::default()
+    }
+
+    pub fn add_tile(&mut self, zoom: u8, tile: &write::Tile) {
+        for layer in tile.layers() {
+            self.add_layer(zoom, layer);
+        }
+    }
+
+    /// Records a layer encoded at `zoom`. Keys seen with values of different types are typed as `Mixed`.
+    pub fn add_layer(&mut self, zoom: u8, layer: &write::Layer) {
+        let collected = self
+            .layers
+            .entry(layer.name().to_string())
+            .or_insert_with(|| VectorLayer {
+                id: layer.name().to_string(),
+                ..Default::default()
+            });
+
+        collected.minzoom = Some(collected.minzoom.map_or(zoom, |minzoom| minzoom.min(zoom)));
+        collected.maxzoom = Some(collected.maxzoom.map_or(zoom, |maxzoom| maxzoom.max(zoom)));
+
+        for (key, value) in layer.tags() {
+            let value_type = field_type(value);
+
+            match collected.fields.get_mut(key) {
+                Some(field_type) if field_type != value_type => *field_type = "Mixed".into(),
+                Some(_) => {}
+                None => {
+                    collected.fields.insert(key.to_string(), value_type.into());
+                }
+            }
+        }
+    }
+
+    /// The collected layers, ordered by id.
+    pub fn vector_layers(&self) -> Vec<VectorLayer> {
+        self.layers.values().cloned().collect()
+    }
+
+    /// Completes `metadata` with the collected layers and, if it has none, the overall zoom range. Layer
+    /// descriptions already present in `metadata` are kept.
+    pub fn to_metadata(&self, metadata: &Metadata) -> Metadata {
+        let mut metadata = metadata.clone();
+
+        let mut vector_layers = self.vector_layers();
+        for layer in vector_layers.iter_mut() {
+            if let Some(existing) = metadata.vector_layers.iter().find(|existing| existing.id == layer.id) {
+                layer.description = existing.description.clone();
+            }
+        }
+        metadata.vector_layers = vector_layers;
+
+        metadata.minzoom = metadata
+            .minzoom
+            .or_else(|| self.layers.values().filter_map(|layer| layer.minzoom).min());
+        metadata.maxzoom = metadata
+            .maxzoom
+            .or_else(|| self.layers.values().filter_map(|layer| layer.maxzoom).max());
+
+        metadata
+    }
+
+    /// A TileJSON 3.0.0 document serving tiles from the given URL templates.
+    pub fn to_tilejson(&self, metadata: &Metadata, tiles: &[String]) -> JsonValue {
+        self.to_metadata(metadata).to_tilejson(tiles)
+    }
+}
+
+#[cfg(test)]
+mod metadata_test {
+    use super::*;
+    use crate::write::{EncodableGeometry, Feature, Geometry, Layer, Tile};
+
+    fn create_layer(name: &str, tags: Vec<(&str, Value)>) -> Layer {
+        let mut feature = Feature::new(Geometry::Point((1, 1)).encode().unwrap());
+        for (key, value) in tags {
+            feature.add_tag(key, value);
+        }
+        Layer::new(name, vec![feature]).unwrap()
+    }
+
+    #[test]
+    fn collect_vector_layers() {
+        let mut collector = MetadataCollector::new();
+
+        let roads = create_layer(
+            "roads",
+            vec![("name", Value::String("Main".into())), ("lanes", Value::UInt(2))],
+        );
+        let poi = create_layer("poi", vec![("open", Value::Bool(true))]);
+        collector.add_tile(5, &Tile::new(vec![roads, poi]).unwrap());
+
+        let roads = create_layer(
+            "roads",
+            vec![
+                ("name", Value::String("High".into())),
+                ("lanes", Value::String("2;3".into())),
+            ],
+        );
+        collector.add_layer(9, &roads);
+
+        let metadata = Metadata {
+            name: Some("test".into()),
+            vector_layers: vec![VectorLayer {
+                id: "roads".into(),
+                description: Some("Road network".into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let metadata = collector.to_metadata(&metadata);
+
+        assert_eq!(metadata.minzoom, Some(5));
+        assert_eq!(metadata.maxzoom, Some(9));
+        assert_eq!(metadata.vector_layers.len(), 2);
+
+        let poi = &metadata.vector_layers[0];
+        assert_eq!(poi.id, "poi");
+        assert_eq!((poi.minzoom, poi.maxzoom), (Some(5), Some(5)));
+        assert_eq!(poi.fields["open"], "Boolean");
+
+        let roads = &metadata.vector_layers[1];
+        assert_eq!(roads.description, Some("Road network".into()));
+        assert_eq!((roads.minzoom, roads.maxzoom), (Some(5), Some(9)));
+        assert_eq!(roads.fields["name"], "String");
+        assert_eq!(roads.fields["lanes"], "Mixed");
+
+        let tilejson = collector.to_tilejson(&metadata, &["https://example.com/{z}/{x}/{y}.pbf".into()]);
+        assert_eq!(tilejson["vector_layers"][1]["fields"]["lanes"], "Mixed");
+        assert_eq!(tilejson["tilejson"], "3.0.0");
+    }
+}
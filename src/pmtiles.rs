@@ -0,0 +1,972 @@
+use super::compression::{self, gzip, Compression};
+
+use super::error::ArchiveError;
+
+use super::metadata::Metadata;
+
+use super::store::TileStore;
+use super::tile::TileId;
+use super::write;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const MAGIC: &[u8; 7] = b"PMTiles";
+const VERSION: u8 = 3;
+const HEADER_LEN: usize = 127;
+/// The header and the root directory must fit into the first 16 KiB of the archive.
+const ROOT_MAX_LEN: usize = 16384 - HEADER_LEN;
+
+const COMPRESSION_NONE: u8 = 1;
+const COMPRESSION_GZIP: u8 = 2;
+const COMPRESSION_BROTLI: u8 = 3;
+const COMPRESSION_ZSTD: u8 = 4;
+const TILE_TYPE_MVT: u8 = 1;
+/// Leaf directories are followed at most this deep below the root directory.
+const MAX_LEAF_DEPTH: usize = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Entry {
+    tile_id: u64,
+    offset: u64,
+    length: u32,
+    /// Number of consecutive tile ids sharing the data, or 0 for entries pointing to a leaf directory.
+    run_length: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Header {
+    root_offset: u64,
+    root_length: u64,
+    metadata_offset: u64,
+    metadata_length: u64,
+    leaves_offset: u64,
+    leaves_length: u64,
+    data_offset: u64,
+    data_length: u64,
+    addressed_tiles: u64,
+    tile_entries: u64,
+    tile_contents: u64,
+    clustered: bool,
+    internal_compression: u8,
+    tile_compression: u8,
+    tile_type: u8,
+    min_zoom: u8,
+    max_zoom: u8,
+    bounds: (f64, f64, f64, f64),
+    center: (f64, f64, u8),
+}
+
+fn to_e7(degrees: f64) -> [u8; 4] {
+    ((degrees * 1e7).round() as i32).to_le_bytes()
+}
+
+fn from_e7(bytes: &[u8]) -> f64 {
+    i32::from_le_bytes(bytes.try_into().unwrap()) as f64 / 1e7
+}
+
+impl Header {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+
+        let numbers = [
+            self.root_offset,
+            self.root_length,
+            self.metadata_offset,
+            self.metadata_length,
+            self.leaves_offset,
+            self.leaves_length,
+            self.data_offset,
+            self.data_length,
+            self.addressed_tiles,
+            self.tile_entries,
+            self.tile_contents,
+        ];
+
+        for number in numbers.iter() {
+            bytes.extend_from_slice(&number.to_le_bytes());
+        }
+
+        bytes.extend_from_slice(&[
+            self.clustered as u8,
+            self.internal_compression,
+            self.tile_compression,
+            self.tile_type,
+            self.min_zoom,
+            self.max_zoom,
+        ]);
+
+        let (west, south, east, north) = self.bounds;
+        for degrees in [west, south, east, north].iter() {
+            bytes.extend_from_slice(&to_e7(*degrees));
+        }
+
+        let (lon, lat, zoom) = self.center;
+        bytes.push(zoom);
+        bytes.extend_from_slice(&to_e7(lon));
+        bytes.extend_from_slice(&to_e7(lat));
+
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Header, ArchiveError> {
+        if bytes.len() < HEADER_LEN || &bytes[0..7] != MAGIC {
+            return Err(ArchiveError::InvalidArchive("not a PMTiles archive".into()));
+        }
+        if bytes[7] != VERSION {
+            return Err(ArchiveError::InvalidArchive(format!(
+                "unsupported PMTiles version {}",
+                bytes[7]
+            )));
+        }
+
+        let number = |idx: usize| u64::from_le_bytes(bytes[8 + idx * 8..16 + idx * 8].try_into().unwrap());
+
+        Ok(Header {
+            root_offset: number(0),
+            root_length: number(1),
+            metadata_offset: number(2),
+            metadata_length: number(3),
+            leaves_offset: number(4),
+            leaves_length: number(5),
+            data_offset: number(6),
+            data_length: number(7),
+            addressed_tiles: number(8),
+            tile_entries: number(9),
+            tile_contents: number(10),
+            clustered: bytes[96] == 1,
+            internal_compression: bytes[97],
+            tile_compression: bytes[98],
+            tile_type: bytes[99],
+            min_zoom: bytes[100],
+            max_zoom: bytes[101],
+            bounds: (
+                from_e7(&bytes[102..106]),
+                from_e7(&bytes[106..110]),
+                from_e7(&bytes[110..114]),
+                from_e7(&bytes[114..118]),
+            ),
+            center: (from_e7(&bytes[119..123]), from_e7(&bytes[123..127]), bytes[118]),
+        })
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> Result<u64, ArchiveError> {
+    let mut value = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes
+            .get(*position)
+            .ok_or_else(|| ArchiveError::InvalidArchive("truncated directory".into()))?;
+        *position += 1;
+
+        value |= ((byte & 0x7f) as u64) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(ArchiveError::InvalidArchive("invalid varint in directory".into()))
+}
+
+fn serialize_directory(entries: &[Entry]) -> Result<Vec<u8>, ArchiveError> {
+    let mut bytes = Vec::new();
+
+    write_varint(&mut bytes, entries.len() as u64);
+
+    let mut last_id = 0;
+    for entry in entries {
+        write_varint(&mut bytes, entry.tile_id - last_id);
+        last_id = entry.tile_id;
+    }
+
+    for entry in entries {
+        write_varint(&mut bytes, entry.run_length as u64);
+    }
+
+    for entry in entries {
+        write_varint(&mut bytes, entry.length as u64);
+    }
+
+    for (idx, entry) in entries.iter().enumerate() {
+        // Offsets directly following the previous entry are stored as 0
+        if idx > 0 && entry.offset == entries[idx - 1].offset + entries[idx - 1].length as u64 {
+            write_varint(&mut bytes, 0);
+        } else {
+            write_varint(&mut bytes, entry.offset + 1);
+        }
+    }
+
+    Ok(gzip(&bytes)?)
+}
+
+fn deserialize_directory(bytes: &[u8]) -> Result<Vec<Entry>, ArchiveError> {
+    let mut position = 0;
+    let count = read_varint(bytes, &mut position)? as usize;
+
+    if count > bytes.len() {
+        return Err(ArchiveError::InvalidArchive("invalid directory length".into()));
+    }
+
+    let mut entries = vec![
+        Entry {
+            tile_id: 0,
+            offset: 0,
+            length: 0,
+            run_length: 0,
+        };
+        count
+    ];
+
+    let mut last_id = 0;
+    for entry in entries.iter_mut() {
+        last_id = checked_add(last_id, read_varint(bytes, &mut position)?)?;
+        entry.tile_id = last_id;
+    }
+
+    for entry in entries.iter_mut() {
+        entry.run_length = read_varint(bytes, &mut position)? as u32;
+    }
+
+    for entry in entries.iter_mut() {
+        entry.length = read_varint(bytes, &mut position)? as u32;
+    }
+
+    for idx in 0..count {
+        let offset = read_varint(bytes, &mut position)?;
+        entries[idx].offset = if offset == 0 && idx > 0 {
+            checked_add(entries[idx - 1].offset, entries[idx - 1].length as u64)?
+        } else {
+            offset.saturating_sub(1)
+        };
+    }
+
+    Ok(entries)
+}
+
+/// Adds offsets or tile ids read from an archive, which may be arbitrarily large.
+fn checked_add(a: u64, b: u64) -> Result<u64, ArchiveError> {
+    a.checked_add(b)
+        .ok_or_else(|| ArchiveError::InvalidArchive("offset or tile id out of range".into()))
+}
+
+/// Finds the entry holding `tile_id`: either a tile run containing it or a leaf directory that may contain it.
+fn find_entry(entries: &[Entry], tile_id: u64) -> Option<Entry> {
+    let idx = entries.partition_point(|entry| entry.tile_id <= tile_id);
+    let entry = *entries.get(idx.checked_sub(1)?)?;
+
+    if entry.run_length == 0 || tile_id - entry.tile_id < entry.run_length as u64 {
+        Some(entry)
+    } else {
+        None
+    }
+}
+
+/// Writes a PMTiles v3 archive.
+///
+/// All tiles must be compressed with the tile compression of the archive, gzip unless created with
+/// `with_compression`. Tiles can be added in any order. Their data is kept in memory until `finalize`, which writes the
+/// archive with tiles clustered in Hilbert order, identical tiles stored once and runs of identical
+/// consecutive tiles collapsed into a single directory entry.
+pub struct PmTilesWriter<W: Write> {
+    writer: W,
+    tiles: Vec<(u64, usize)>,
+    contents: Vec<Vec<u8>>,
+    content_lookup: HashMap<u64, Vec<usize>>,
+    metadata: Metadata,
+    compression: Compression,
+    finalized: bool,
+}
+
+impl<W: Write> PmTilesWriter<W> {
+    /// Creates a writer for an archive of gzip compressed tiles.
+    pub fn new(writer: W) -> PmTilesWriter<W> {
+        PmTilesWriter::with_compression(writer, Compression::Gzip)
+    }
+
+    /// Creates a writer for an archive of tiles compressed with `compression`, which is recorded in the header.
+    pub fn with_compression(writer: W, compression: Compression) -> PmTilesWriter<W> {
+        PmTilesWriter {
+            writer,
+            tiles: Vec::new(),
+            contents: Vec::new(),
+            content_lookup: HashMap::new(),
+            metadata: Metadata::default(),
+            compression,
+            finalized: false,
+        }
+    }
+
+    /// Encodes and compresses a tile with the tile compression of the archive, then adds it to the archive.
+    pub fn put_tile(&mut self, id: TileId, tile: write::Tile) -> Result<(), ArchiveError> {
+        let data = compression::compress(&tile.to_bytes(), self.compression)?;
+        self.put(id, &data)
+    }
+
+    /// Returns the underlying writer, which only holds a complete archive after `finalize`.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> TileStore for PmTilesWriter<W> {
+    /// Returns data added so far. The last data put for a tile wins.
+    fn get(&mut self, id: TileId) -> Result<Option<Vec<u8>>, ArchiveError> {
+        let tile_id = id.hilbert_index();
+
+        Ok(self
+            .tiles
+            .iter()
+            .rev()
+            .find(|(id, _)| *id == tile_id)
+            .map(|(_, content)| self.contents[*content].clone()))
+    }
+
+    /// Adds tile data compressed with the tile compression of the archive. Adding the same tile twice replaces
+    /// its data.
+    fn put(&mut self, id: TileId, data: &[u8]) -> Result<(), ArchiveError> {
+        if self.finalized {
+            return Err(ArchiveError::ReadOnly);
+        }
+
+        if !self.compression.matches(data) {
+            return Err(ArchiveError::CompressionMismatch {
+                expected: self.compression,
+                found: Compression::detect(data),
+            });
+        }
+
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+
+        let contents = &mut self.contents;
+        let candidates = self.content_lookup.entry(hasher.finish()).or_default();
+
+        let content = match candidates.iter().find(|idx| contents[**idx] == data) {
+            Some(idx) => *idx,
+            None => {
+                contents.push(data.to_vec());
+                candidates.push(contents.len() - 1);
+                contents.len() - 1
+            }
+        };
+
+        self.tiles.push((id.hilbert_index(), content));
+
+        Ok(())
+    }
+
+    fn iter(&mut self) -> Result<Box<dyn Iterator<Item = TileId>>, ArchiveError> {
+        let mut tile_ids: Vec<u64> = self.tiles.iter().map(|(tile_id, _)| *tile_id).collect();
+        tile_ids.sort_unstable();
+        tile_ids.dedup();
+
+        Ok(Box::new(tile_ids.into_iter().filter_map(TileId::from_hilbert_index)))
+    }
+
+    fn metadata(&mut self) -> Result<Metadata, ArchiveError> {
+        Ok(self.metadata.clone())
+    }
+
+    fn set_metadata(&mut self, metadata: &Metadata) -> Result<(), ArchiveError> {
+        self.metadata = metadata.clone();
+        Ok(())
+    }
+
+    /// Writes the archive. Missing bounds, center and zoom range in the metadata are derived from the tiles.
+    fn finalize(&mut self) -> Result<(), ArchiveError> {
+        if self.finalized {
+            return Ok(());
+        }
+
+        if self.tiles.is_empty() {
+            return Err(ArchiveError::InvalidArchive(
+                "a PMTiles archive needs at least one tile".into(),
+            ));
+        }
+
+        // Keep the last data put for a tile
+        self.tiles.reverse();
+        self.tiles.sort_by_key(|(tile_id, _)| *tile_id);
+        self.tiles.dedup_by_key(|(tile_id, _)| *tile_id);
+
+        let mut offsets = vec![None; self.contents.len()];
+        let mut data_order = Vec::new();
+        let mut data_length = 0u64;
+        let mut entries: Vec<Entry> = Vec::new();
+
+        for (tile_id, content) in &self.tiles {
+            let offset = *offsets[*content].get_or_insert_with(|| {
+                let offset = data_length;
+                data_length += self.contents[*content].len() as u64;
+                data_order.push(*content);
+                offset
+            });
+
+            match entries.last_mut() {
+                Some(last) if last.offset == offset && last.tile_id + last.run_length as u64 == *tile_id => {
+                    last.run_length += 1;
+                }
+                _ => entries.push(Entry {
+                    tile_id: *tile_id,
+                    offset,
+                    length: self.contents[*content].len() as u32,
+                    run_length: 1,
+                }),
+            }
+        }
+
+        let (root, leaves) = build_directories(&entries)?;
+
+        let mut metadata = self.metadata.clone();
+        let ids: Vec<TileId> = self
+            .tiles
+            .iter()
+            .filter_map(|(tile_id, _)| TileId::from_hilbert_index(*tile_id))
+            .collect();
+
+        let min_zoom = ids.iter().map(|id| id.z()).min().unwrap_or(0);
+        let max_zoom = ids.iter().map(|id| id.z()).max().unwrap_or(0);
+
+        metadata.minzoom = metadata.minzoom.or(Some(min_zoom));
+        metadata.maxzoom = metadata.maxzoom.or(Some(max_zoom));
+
+        let bounds = metadata.bounds.unwrap_or_else(|| {
+            ids.iter().map(TileId::bounds).fold(
+                (180.0, 85.0, -180.0, -85.0),
+                |(west, south, east, north), (w, s, e, n)| (west.min(w), south.min(s), east.max(e), north.max(n)),
+            )
+        });
+        let center = metadata
+            .center
+            .unwrap_or(((bounds.0 + bounds.2) / 2.0, (bounds.1 + bounds.3) / 2.0, min_zoom));
+
+        let metadata_bytes = gzip(metadata.to_json().to_string().as_bytes())?;
+
+        let root_offset = HEADER_LEN as u64;
+        let metadata_offset = root_offset + root.len() as u64;
+        let leaves_offset = metadata_offset + metadata_bytes.len() as u64;
+        let data_offset = leaves_offset + leaves.len() as u64;
+
+        let header = Header {
+            root_offset,
+            root_length: root.len() as u64,
+            metadata_offset,
+            metadata_length: metadata_bytes.len() as u64,
+            leaves_offset,
+            leaves_length: leaves.len() as u64,
+            data_offset,
+            data_length,
+            addressed_tiles: self.tiles.len() as u64,
+            tile_entries: entries.len() as u64,
+            tile_contents: data_order.len() as u64,
+            clustered: true,
+            internal_compression: COMPRESSION_GZIP,
+            tile_compression: match self.compression {
+                Compression::None => COMPRESSION_NONE,
+                Compression::Gzip => COMPRESSION_GZIP,
+                Compression::Brotli => COMPRESSION_BROTLI,
+                Compression::Zstd => COMPRESSION_ZSTD,
+            },
+            tile_type: TILE_TYPE_MVT,
+            min_zoom,
+            max_zoom,
+            bounds,
+            center,
+        };
+
+        self.writer.write_all(&header.to_bytes())?;
+        self.writer.write_all(&root)?;
+        self.writer.write_all(&metadata_bytes)?;
+        self.writer.write_all(&leaves)?;
+        for content in data_order {
+            self.writer.write_all(&self.contents[content])?;
+        }
+        self.writer.flush()?;
+
+        self.finalized = true;
+
+        Ok(())
+    }
+
+    fn tile_compression(&self) -> Option<Compression> {
+        Some(self.compression)
+    }
+}
+
+/// Serializes the root directory and, if the entries do not fit into it, a single level of leaf directories.
+fn build_directories(entries: &[Entry]) -> Result<(Vec<u8>, Vec<u8>), ArchiveError> {
+    let root = serialize_directory(entries)?;
+
+    if root.len() <= ROOT_MAX_LEN {
+        return Ok((root, Vec::new()));
+    }
+
+    let mut leaf_size = 4096;
+
+    loop {
+        let mut leaves = Vec::new();
+        let mut root_entries = Vec::new();
+
+        for chunk in entries.chunks(leaf_size) {
+            let leaf = serialize_directory(chunk)?;
+
+            root_entries.push(Entry {
+                tile_id: chunk[0].tile_id,
+                offset: leaves.len() as u64,
+                length: leaf.len() as u32,
+                run_length: 0,
+            });
+
+            leaves.extend(leaf);
+        }
+
+        let root = serialize_directory(&root_entries)?;
+
+        if root.len() <= ROOT_MAX_LEN {
+            return Ok((root, leaves));
+        }
+
+        leaf_size *= 2;
+    }
+}
+
+/// Reads tiles from a PMTiles v3 archive.
+pub struct PmTiles<R: Read + Seek> {
+    reader: R,
+    header: Header,
+    root: Vec<Entry>,
+}
+
+impl<R: Read + Seek> PmTiles<R> {
+    pub fn open(mut reader: R) -> Result<PmTiles<R>, ArchiveError> {
+        let mut header = vec![0; HEADER_LEN];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut header)?;
+
+        let header = Header::from_bytes(&header)?;
+
+        let mut archive = PmTiles {
+            reader,
+            header,
+            root: Vec::new(),
+        };
+
+        archive.root = archive.read_directory(archive.header.root_offset, archive.header.root_length)?;
+
+        Ok(archive)
+    }
+
+    pub fn min_zoom(&self) -> u8 {
+        self.header.min_zoom
+    }
+
+    pub fn max_zoom(&self) -> u8 {
+        self.header.max_zoom
+    }
+
+    /// Reads `length` bytes at `offset`. Offsets and lengths come from the archive, so the buffer only grows
+    /// as data is actually read rather than being allocated up front.
+    fn read_range(&mut self, offset: u64, length: u64) -> Result<Vec<u8>, ArchiveError> {
+        let mut bytes = Vec::new();
+        self.reader.seek(SeekFrom::Start(offset))?;
+        (&mut self.reader).take(length).read_to_end(&mut bytes)?;
+
+        if bytes.len() as u64 != length {
+            return Err(ArchiveError::InvalidArchive(format!(
+                "range of {} bytes at {} extends past the end of the archive",
+                length, offset
+            )));
+        }
+
+        Ok(bytes)
+    }
+
+    fn decompress(&self, bytes: Vec<u8>) -> Result<Vec<u8>, ArchiveError> {
+        let compression = match self.header.internal_compression {
+            COMPRESSION_NONE => return Ok(bytes),
+            COMPRESSION_GZIP => Compression::Gzip,
+            COMPRESSION_BROTLI => Compression::Brotli,
+            COMPRESSION_ZSTD => Compression::Zstd,
+            compression => {
+                return Err(ArchiveError::InvalidArchive(format!(
+                    "unsupported internal compression {}",
+                    compression
+                )))
+            }
+        };
+
+        Ok(compression::decompress(&bytes, compression)?)
+    }
+
+    fn read_directory(&mut self, offset: u64, length: u64) -> Result<Vec<Entry>, ArchiveError> {
+        let bytes = self.read_range(offset, length)?;
+        deserialize_directory(&self.decompress(bytes)?)
+    }
+
+    /// All tiles in the archive, in Hilbert order. Fails if a directory is nested too deep or referenced
+    /// twice, or if tile ids are beyond the maximum zoom level of the header.
+    pub fn tile_ids(&mut self) -> Result<Vec<TileId>, ArchiveError> {
+        // Tile ids of all zoom levels up to the maximum one, there are 4^z tiles at level z
+        let max_zoom = self.header.max_zoom.min(TileId::MAX_ZOOM) as u32;
+        let id_limit = (((1u128 << (2 * max_zoom + 2)) - 1) / 3) as u64;
+
+        let mut ids = Vec::new();
+        let mut leaves = HashSet::new();
+        let mut directories = vec![(self.root.clone(), 0)];
+
+        while let Some((directory, depth)) = directories.pop() {
+            for entry in directory.iter().rev() {
+                if entry.run_length == 0 {
+                    if depth == MAX_LEAF_DEPTH {
+                        return Err(ArchiveError::InvalidArchive("too many directory levels".into()));
+                    }
+                    if !leaves.insert((entry.offset, entry.length)) {
+                        return Err(ArchiveError::InvalidArchive("leaf directory referenced twice".into()));
+                    }
+
+                    let offset = checked_add(self.header.leaves_offset, entry.offset)?;
+                    directories.push((self.read_directory(offset, entry.length as u64)?, depth + 1));
+                } else {
+                    let end = checked_add(entry.tile_id, entry.run_length as u64)?;
+                    if end > id_limit {
+                        return Err(ArchiveError::InvalidArchive(format!(
+                            "tile ids up to {} beyond zoom level {}",
+                            end - 1,
+                            max_zoom
+                        )));
+                    }
+
+                    ids.extend((entry.tile_id..end).filter_map(TileId::from_hilbert_index));
+                }
+            }
+        }
+
+        ids.sort_unstable_by_key(TileId::hilbert_index);
+
+        Ok(ids)
+    }
+}
+
+/// Archives are read-only, use `PmTilesWriter` to create them.
+impl<R: Read + Seek> TileStore for PmTiles<R> {
+    /// Returns the stored tile data, compressed as the archive's tile compression says (usually gzip).
+    fn get(&mut self, id: TileId) -> Result<Option<Vec<u8>>, ArchiveError> {
+        let tile_id = id.hilbert_index();
+        let mut directory = self.root.clone();
+
+        for _ in 0..=MAX_LEAF_DEPTH {
+            let entry = match find_entry(&directory, tile_id) {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+
+            if entry.run_length > 0 {
+                let data = self.read_range(checked_add(self.header.data_offset, entry.offset)?, entry.length as u64)?;
+                return Ok(Some(data));
+            }
+
+            let offset = checked_add(self.header.leaves_offset, entry.offset)?;
+            directory = self.read_directory(offset, entry.length as u64)?;
+        }
+
+        Err(ArchiveError::InvalidArchive("too many directory levels".into()))
+    }
+
+    fn put(&mut self, _id: TileId, _data: &[u8]) -> Result<(), ArchiveError> {
+        Err(ArchiveError::ReadOnly)
+    }
+
+    fn iter(&mut self) -> Result<Box<dyn Iterator<Item = TileId>>, ArchiveError> {
+        Ok(Box::new(self.tile_ids()?.into_iter()))
+    }
+
+    /// The JSON metadata of the archive, completed with the zoom range, bounds and center from the header.
+    fn metadata(&mut self) -> Result<Metadata, ArchiveError> {
+        let bytes = self.read_range(self.header.metadata_offset, self.header.metadata_length)?;
+        let bytes = self.decompress(bytes)?;
+
+        let json = serde_json::from_slice(&bytes)
+            .map_err(|error| ArchiveError::InvalidArchive(format!("invalid metadata: {}", error)))?;
+
+        let mut metadata = Metadata::from_json(&json);
+        metadata.minzoom = metadata.minzoom.or(Some(self.header.min_zoom));
+        metadata.maxzoom = metadata.maxzoom.or(Some(self.header.max_zoom));
+        metadata.bounds = metadata.bounds.or(Some(self.header.bounds));
+        metadata.center = metadata.center.or(Some(self.header.center));
+
+        Ok(metadata)
+    }
+
+    fn set_metadata(&mut self, _metadata: &Metadata) -> Result<(), ArchiveError> {
+        Err(ArchiveError::ReadOnly)
+    }
+
+    fn finalize(&mut self) -> Result<(), ArchiveError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod pmtiles_test {
+    use super::*;
+    use crate::common::Value;
+    use crate::store::{copy, MemoryStore};
+    use crate::write::EncodableGeometry;
+
+    use std::fs::File;
+
+    fn create_test_tile(name: &str) -> write::Tile {
+        let mut feature = write::Feature::new(write::Geometry::Point((10, 20)).encode().unwrap());
+        feature.add_tag("name", Value::String(name.into()));
+        write::Tile::new(vec![write::Layer::new("poi", vec![feature]).unwrap()]).unwrap()
+    }
+
+    #[test]
+    fn directory_round_trip() {
+        let entries = vec![
+            Entry {
+                tile_id: 0,
+                offset: 0,
+                length: 10,
+                run_length: 1,
+            },
+            Entry {
+                tile_id: 1,
+                offset: 10,
+                length: 20,
+                run_length: 3,
+            },
+            Entry {
+                tile_id: 9,
+                offset: 0,
+                length: 10,
+                run_length: 1,
+            },
+        ];
+
+        let bytes = compression::gunzip(&serialize_directory(&entries).unwrap()).unwrap();
+        assert_eq!(deserialize_directory(&bytes).unwrap(), entries);
+
+        assert_eq!(find_entry(&entries, 3), Some(entries[1]));
+        assert_eq!(find_entry(&entries, 4), None);
+        assert_eq!(find_entry(&entries, 100), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.pmtiles");
+
+        let mut writer = PmTilesWriter::new(File::create(&path).unwrap());
+
+        writer
+            .put_tile(TileId::new(0, 0, 0).unwrap(), create_test_tile("root"))
+            .unwrap();
+        for child in TileId::new(0, 0, 0).unwrap().children().unwrap().iter() {
+            writer.put_tile(*child, create_test_tile("same")).unwrap();
+        }
+
+        let metadata = Metadata {
+            name: Some("test".into()),
+            ..Default::default()
+        };
+        writer.set_metadata(&metadata).unwrap();
+        writer.finalize().unwrap();
+        assert!(matches!(
+            writer.put(TileId::new(0, 0, 0).unwrap(), &[]),
+            Err(ArchiveError::ReadOnly)
+        ));
+
+        let mut archive = PmTiles::open(File::open(&path).unwrap()).unwrap();
+
+        assert_eq!(archive.header.tile_contents, 2);
+        // The children have consecutive Hilbert indices, so they collapse into one run
+        assert_eq!(archive.header.tile_entries, 2);
+        assert_eq!(archive.tile_ids().unwrap().len(), 5);
+
+        let tile = archive.read_tile(TileId::new(1, 1, 0).unwrap()).unwrap().unwrap();
+        let feature = &tile.layer("poi").unwrap().features[0];
+        assert_eq!(feature.tag("name"), Some(&Value::String("same".into())));
+
+        assert!(archive.get(TileId::new(2, 0, 0).unwrap()).unwrap().is_none());
+
+        let read_metadata = archive.metadata().unwrap();
+        assert_eq!(read_metadata.name, Some("test".into()));
+        assert_eq!(read_metadata.minzoom, Some(0));
+        assert_eq!(read_metadata.maxzoom, Some(1));
+    }
+
+    #[test]
+    fn leaf_directories() {
+        let mut writer = PmTilesWriter::with_compression(Vec::new(), Compression::None);
+
+        // Distinct contents of varying length prevent run-length encoding and compress poorly, forcing
+        // leaf directories
+        let mut seed = 1u32;
+        for x in 0..256 {
+            for y in 0..256 {
+                let id = TileId::new(8, x, y).unwrap();
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let padding = " ".repeat((seed >> 16) as usize % 200);
+                writer.put(id, format!("{}{}", id, padding).as_bytes()).unwrap();
+            }
+        }
+
+        writer.finalize().unwrap();
+        let mut archive = PmTiles::open(std::io::Cursor::new(writer.into_inner())).unwrap();
+
+        assert!(archive.header.leaves_length > 0);
+        assert_eq!(archive.header.tile_compression, COMPRESSION_NONE);
+        assert_eq!(archive.tile_ids().unwrap().len(), 256 * 256);
+
+        let data = archive.get(TileId::new(8, 100, 27).unwrap()).unwrap().unwrap();
+        assert!(data.starts_with(b"8/100/27"));
+    }
+
+    #[test]
+    fn tile_compression() {
+        let id = TileId::new(0, 0, 0).unwrap();
+        let data = create_test_tile("root").to_bytes();
+
+        let mut writer = PmTilesWriter::new(Vec::new());
+        assert!(matches!(
+            writer.put(id, &data),
+            Err(ArchiveError::CompressionMismatch {
+                expected: Compression::Gzip,
+                found: Compression::None
+            })
+        ));
+
+        // Copying recompresses tiles to the compression of the archive
+        let mut memory = MemoryStore::new();
+        memory.put(id, &data).unwrap();
+        copy(&mut memory, &mut writer).unwrap();
+        writer.finalize().unwrap();
+
+        let mut archive = PmTiles::open(std::io::Cursor::new(writer.into_inner())).unwrap();
+        assert_eq!(archive.header.tile_compression, COMPRESSION_GZIP);
+        assert_eq!(compression::gunzip(&archive.get(id).unwrap().unwrap()).unwrap(), data);
+    }
+
+    /// An archive with the given directories and a single byte of tile data.
+    fn crafted_archive(header: Header, root: &[Entry], leaf: &[Entry]) -> PmTiles<std::io::Cursor<Vec<u8>>> {
+        let root = serialize_directory(root).unwrap();
+        let leaf = if leaf.is_empty() {
+            Vec::new()
+        } else {
+            serialize_directory(leaf).unwrap()
+        };
+
+        let header = Header {
+            root_offset: HEADER_LEN as u64,
+            root_length: root.len() as u64,
+            leaves_offset: (HEADER_LEN + root.len()) as u64,
+            leaves_length: leaf.len() as u64,
+            data_offset: (HEADER_LEN + root.len() + leaf.len()) as u64,
+            data_length: 1,
+            ..header
+        };
+
+        let mut bytes = header.to_bytes();
+        bytes.extend(root);
+        bytes.extend(leaf);
+        bytes.push(0);
+
+        PmTiles::open(std::io::Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn crafted_directories() {
+        let header = Header {
+            internal_compression: COMPRESSION_GZIP,
+            tile_compression: COMPRESSION_GZIP,
+            max_zoom: 1,
+            ..Default::default()
+        };
+        let tile = |tile_id, run_length| Entry {
+            tile_id,
+            offset: 0,
+            length: 1,
+            run_length,
+        };
+        let leaf = |length| Entry {
+            tile_id: 0,
+            offset: 0,
+            length,
+            run_length: 0,
+        };
+
+        // The header claims more tiles than fit into memory
+        let claimed = Header {
+            addressed_tiles: u64::MAX,
+            ..header.clone()
+        };
+        let mut archive = crafted_archive(claimed, &[tile(0, 5)], &[]);
+        assert_eq!(archive.tile_ids().unwrap().len(), 5);
+
+        // Runs beyond the maximum zoom level
+        let mut archive = crafted_archive(header.clone(), &[tile(0, 6)], &[]);
+        assert!(matches!(archive.tile_ids(), Err(ArchiveError::InvalidArchive(_))));
+        let mut archive = crafted_archive(header.clone(), &[tile(u64::MAX, 1)], &[]);
+        assert!(matches!(archive.tile_ids(), Err(ArchiveError::InvalidArchive(_))));
+
+        // A leaf directory pointing to itself, whose length depends on the length it stores
+        let mut length = 1;
+        while serialize_directory(&[leaf(length)]).unwrap().len() as u32 != length {
+            length = serialize_directory(&[leaf(length)]).unwrap().len() as u32;
+        }
+        let mut archive = crafted_archive(header.clone(), &[leaf(length)], &[leaf(length)]);
+        assert!(matches!(archive.tile_ids(), Err(ArchiveError::InvalidArchive(_))));
+        assert!(matches!(
+            archive.get(TileId::new(0, 0, 0).unwrap()),
+            Err(ArchiveError::InvalidArchive(_))
+        ));
+
+        // Offsets overflowing when added to the start of the data section
+        let mut archive = crafted_archive(
+            header,
+            &[Entry {
+                offset: u64::MAX - 1,
+                ..tile(0, 1)
+            }],
+            &[],
+        );
+        assert!(matches!(
+            archive.get(TileId::new(0, 0, 0).unwrap()),
+            Err(ArchiveError::InvalidArchive(_))
+        ));
+    }
+
+    #[test]
+    fn truncated_archive() {
+        let mut writer = PmTilesWriter::new(Vec::new());
+        writer
+            .put_tile(TileId::new(0, 0, 0).unwrap(), create_test_tile("root"))
+            .unwrap();
+        writer.finalize().unwrap();
+
+        let mut bytes = writer.into_inner();
+        let mut header = Header::from_bytes(&bytes).unwrap();
+        header.metadata_length = u64::MAX;
+        bytes.splice(0..HEADER_LEN, header.to_bytes());
+
+        let mut archive = PmTiles::open(std::io::Cursor::new(bytes)).unwrap();
+        assert!(matches!(archive.metadata(), Err(ArchiveError::InvalidArchive(_))));
+    }
+}
@@ -1,5 +1,6 @@
 #![allow(non_snake_case)]
 #![allow(unused_imports)]
+#![allow(clippy::all)]
 
 pub mod vector_tile {
     include!(concat!(env!("OUT_DIR"), "/proto/vector_tile.rs"));
@@ -0,0 +1,217 @@
+use super::common::TileCoord;
+
+use super::read::ring_area;
+use super::union::{is_valid, union, Polygon};
+
+/// A point in tile coordinates before quantization.
+pub type PointF64 = (f64, f64);
+
+/// An exterior ring with its interior rings in tile coordinates before quantization, in any orientation.
+pub type PolygonF64 = (Vec<PointF64>, Vec<Vec<PointF64>>);
+
+/// Rounds a point to the closest grid point, halfway cases away from zero. The same input always snaps to
+/// the same grid point, so edges shared by several geometries stay shared.
+pub fn snap(point: PointF64) -> TileCoord {
+    (point.0.round() as i32, point.1.round() as i32)
+}
+
+/// Snaps every point, keeping duplicates.
+pub fn quantize_points(points: &[PointF64]) -> Vec<TileCoord> {
+    points.iter().copied().map(snap).collect()
+}
+
+/// Snaps a line and removes the segments that collapsed, `None` if less than two points remain.
+pub fn quantize_line(line: &[PointF64]) -> Option<Vec<TileCoord>> {
+    let mut line = quantize_points(line);
+    line.dedup();
+
+    if line.len() < 2 {
+        None
+    } else {
+        Some(line)
+    }
+}
+
+/// Snaps a ring, removes collapsed segments and spikes, and orients it as an exterior ring if `exterior`
+/// is set and as an interior ring otherwise. `None` if the ring collapsed.
+fn quantize_ring(ring: &[PointF64], exterior: bool) -> Option<Vec<TileCoord>> {
+    let mut ring = quantize_points(ring);
+    ring.dedup();
+    while ring.len() > 1 && ring.first() == ring.last() {
+        ring.pop();
+    }
+
+    // Spikes going back and forth along the same segment
+    let mut idx = 0;
+    while ring.len() >= 3 && idx < ring.len() {
+        let len = ring.len();
+        if ring[(idx + len - 1) % len] == ring[(idx + 1) % len] {
+            ring.remove(idx);
+            ring.remove(idx % ring.len());
+            idx = 0;
+        } else {
+            idx += 1;
+        }
+    }
+
+    let area = if ring.len() >= 3 { ring_area(&ring) } else { 0 };
+
+    if area == 0 {
+        return None;
+    }
+    if (area > 0) != exterior {
+        ring.reverse();
+    }
+
+    Some(ring)
+}
+
+/// Snaps polygons to the grid so that they can always be encoded.
+///
+/// Rings are oriented as the tile format expects, collapsed segments, spikes, rings and holes are removed,
+/// and the result is checked with `union::is_valid`. Polygons that rounding made invalid, e.g. because
+/// sides of a narrow part now cross or two polygons now overlap, are repaired with `union::union`, which
+/// may split or merge them. The repair is checked again; if it failed, the polygons are repaired one by one
+/// instead, and those that still are not valid or overlap the ones kept before them are dropped. The result
+/// is always valid.
+pub fn quantize_polygons(polygons: &[PolygonF64]) -> Vec<Polygon> {
+    let polygons: Vec<Polygon> = polygons
+        .iter()
+        .filter_map(|(exterior_ring, interior_rings)| {
+            let exterior_ring = quantize_ring(exterior_ring, true)?;
+            let interior_rings = interior_rings
+                .iter()
+                .filter_map(|ring| quantize_ring(ring, false))
+                .collect();
+            Some((exterior_ring, interior_rings))
+        })
+        .collect();
+
+    if is_valid(&polygons) {
+        return polygons;
+    }
+
+    let repaired = union(&polygons);
+    if is_valid(&repaired) {
+        repaired
+    } else {
+        repair_each(polygons)
+    }
+}
+
+/// Keeps every polygon that is valid, or can be repaired, on its own and together with the polygons kept so
+/// far.
+fn repair_each(polygons: Vec<Polygon>) -> Vec<Polygon> {
+    let mut kept: Vec<Polygon> = Vec::new();
+
+    for polygon in polygons {
+        let candidates = if is_valid(std::slice::from_ref(&polygon)) {
+            vec![polygon]
+        } else {
+            union(&[polygon])
+        };
+
+        let count = kept.len();
+        kept.extend(candidates);
+        if !is_valid(&kept) {
+            kept.truncate(count);
+        }
+    }
+
+    kept
+}
+
+#[cfg(test)]
+mod quantize_test {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        assert_eq!(
+            quantize_line(&[(0.2, 0.4), (0.4, -0.2), (1.5, 2.49), (-1.5, 0.0)]),
+            Some(vec![(0, 0), (2, 2), (-2, 0)])
+        );
+        assert_eq!(quantize_line(&[(0.2, 0.4), (0.4, -0.2)]), None);
+    }
+
+    #[test]
+    fn polygons() {
+        // Orientation is fixed, collapsed holes and spikes are removed
+        let polygon = (
+            vec![
+                (0.0, 0.0),
+                (0.0, 10.0),
+                (10.0, 10.0),
+                (10.0, 0.0),
+                (20.2, 0.0),
+                (10.0, 0.0),
+            ],
+            vec![
+                vec![(2.0, 2.0), (2.2, 2.2), (2.4, 2.0)],
+                vec![(4.0, 4.0), (6.0, 4.0), (6.0, 6.0), (4.0, 6.0)],
+            ],
+        );
+        assert_eq!(
+            quantize_polygons(&[polygon]),
+            vec![(
+                vec![(10, 0), (10, 10), (0, 10), (0, 0)],
+                vec![vec![(4, 6), (6, 6), (6, 4), (4, 4)]]
+            )]
+        );
+
+        // Slivers collapse
+        assert!(quantize_polygons(&[(vec![(0.0, 0.0), (10.0, 0.2), (20.0, 0.0)], vec![])]).is_empty());
+
+        // The sides of a narrow part cross after rounding
+        let narrow = (
+            vec![
+                (0.0, 0.0),
+                (10.0, 0.5),
+                (20.0, 0.0),
+                (20.0, 2.0),
+                (11.0, 0.49),
+                (0.0, 2.0),
+            ],
+            vec![],
+        );
+        let quantized = quantize_polygons(&[narrow]);
+        assert!(!quantized.is_empty());
+        assert!(is_valid(&quantized));
+
+        // Polygons sharing an edge snap it the same way and get merged
+        let left = (vec![(0.0, 0.0), (5.4, 0.0), (5.6, 10.0), (0.0, 10.0)], vec![]);
+        let right = (vec![(5.4, 0.0), (10.0, 0.0), (10.0, 10.0), (5.6, 10.0)], vec![]);
+        assert_eq!(
+            quantize_polygons(&[left, right]),
+            vec![(vec![(0, 0), (10, 0), (10, 10), (0, 10)], vec![])]
+        );
+
+        // Repairing polygons one by one keeps the valid ones
+        let square = |x: i32, size: i32| vec![(x, 0), (x + size, 0), (x + size, size), (x, size)];
+        let bow_tie = vec![(20, 0), (30, 10), (30, 0), (20, 10)];
+        let repaired = repair_each(vec![
+            (square(0, 10), vec![]),
+            (bow_tie, vec![]),
+            (square(5, 10), vec![]),
+        ]);
+        assert!(is_valid(&repaired));
+        assert_eq!(repaired.len(), 3);
+        assert_eq!(repaired[0], (square(0, 10), vec![]));
+
+        // Overlapping triangles, repaired by a union that has to round crossings
+        let mut seed = 11u64;
+        let mut next = || {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            ((seed >> 33) % 4000) as f64 / 100.0
+        };
+
+        for _ in 0..300 {
+            let triangles: Vec<PolygonF64> = (0..3)
+                .map(|_| (vec![(next(), next()), (next(), next()), (next(), next())], vec![]))
+                .collect();
+            assert!(is_valid(&quantize_polygons(&triangles)));
+        }
+    }
+}
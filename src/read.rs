@@ -0,0 +1,418 @@
+use super::common::{GeometryType, TileCoord, Value};
+
+use super::compression::{decompress, Compression};
+
+use super::error::{InvalidGeometry, ReadError};
+
+use super::write::{self, EncodableGeometry, EncodedGeometry};
+
+use super::proto::vector_tile as pbf;
+use pbf::mod_Tile as pbf_tile;
+
+use quick_protobuf::{BytesReader, MessageRead};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tile {
+    pub layers: Vec<Layer>,
+}
+
+impl Tile {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Tile, ReadError> {
+        let mut reader = BytesReader::from_bytes(bytes);
+        let message = pbf::Tile::from_reader(&mut reader, bytes)?;
+
+        let layers = message
+            .layers
+            .iter()
+            .map(Layer::decode)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Tile { layers })
+    }
+
+    /// Decodes a tile that may be gzip or zstd compressed, detecting the compression from its magic bytes.
+    pub fn from_compressed_bytes(bytes: &[u8]) -> Result<Tile, ReadError> {
+        match Compression::detect(bytes) {
+            Compression::None => Tile::from_bytes(bytes),
+            compression => {
+                let bytes =
+                    decompress(bytes, compression).map_err(|error| ReadError::Decompression(error.to_string()))?;
+                Tile::from_bytes(&bytes)
+            }
+        }
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layer {
+    pub name: String,
+    pub version: u32,
+    pub extent: u32,
+    pub features: Vec<Feature>,
+}
+
+impl Layer {
+    fn decode(layer: &pbf_tile::Layer) -> Result<Layer, ReadError> {
+        let values = layer.values.iter().map(decode_value).collect::<Result<Vec<_>, _>>()?;
+
+        let mut features = Vec::with_capacity(layer.features.len());
+
+        for feature in &layer.features {
+            let geometry = match decode_geometry(feature.type_pb, &feature.geometry)? {
+                Some(geometry) => geometry,
+                // Decoders MAY ignore features with an unknown geometry type (4.3.4.)
+                None => continue,
+            };
+
+            features.push(Feature {
+                id: if feature.id == 0 { None } else { Some(feature.id) },
+                tags: decode_tags(&feature.tags, &layer.keys, &values)?,
+                geometry,
+            });
+        }
+
+        Ok(Layer {
+            name: layer.name.to_string(),
+            version: layer.version,
+            extent: layer.extent,
+            features,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Feature {
+    pub id: Option<u64>,
+    pub tags: Vec<(String, Value)>,
+    pub geometry: Geometry,
+}
+
+impl Feature {
+    pub fn tag(&self, key: &str) -> Option<&Value> {
+        self.tags.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+}
+
+/// Owned counterpart of `write::Geometry`, as decoded from a tile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Geometry {
+    Point(TileCoord),
+    MultiPoint(Vec<TileCoord>),
+    Line(Vec<TileCoord>),
+    MultiLine(Vec<Vec<TileCoord>>),
+    Polygon(Vec<TileCoord>, Vec<Vec<TileCoord>>),
+    MultiPolygon(Vec<(Vec<TileCoord>, Vec<Vec<TileCoord>>)>),
+}
+
+impl Geometry {
+    pub fn geometry_type(&self) -> GeometryType {
+        match self {
+            Geometry::Point(_) | Geometry::MultiPoint(_) => GeometryType::Point,
+            Geometry::Line(_) | Geometry::MultiLine(_) => GeometryType::LineString,
+            Geometry::Polygon(_, _) | Geometry::MultiPolygon(_) => GeometryType::Polygon,
+        }
+    }
+}
+
+impl EncodableGeometry for Geometry {
+    fn encode(&self) -> Result<EncodedGeometry, InvalidGeometry> {
+        match self {
+            Geometry::Point(point) => write::Geometry::Point(*point).encode(),
+            Geometry::MultiPoint(points) => write::Geometry::MultiPoint(points).encode(),
+            Geometry::Line(line) => write::Geometry::Line(line).encode(),
+            Geometry::MultiLine(lines) => {
+                let lines: Vec<&[TileCoord]> = lines.iter().map(Vec::as_slice).collect();
+                write::Geometry::MultiLine(&lines).encode()
+            }
+            Geometry::Polygon(exterior_ring, interior_rings) => {
+                let interior_rings: Vec<&[TileCoord]> = interior_rings.iter().map(Vec::as_slice).collect();
+                write::Geometry::Polygon(exterior_ring, &interior_rings).encode()
+            }
+            Geometry::MultiPolygon(polygons) => {
+                let interior_rings: Vec<Vec<&[TileCoord]>> = polygons
+                    .iter()
+                    .map(|(_, rings)| rings.iter().map(Vec::as_slice).collect())
+                    .collect();
+                let polygons: Vec<(&[TileCoord], &[&[TileCoord]])> = polygons
+                    .iter()
+                    .zip(&interior_rings)
+                    .map(|((exterior_ring, _), interior_rings)| (exterior_ring.as_slice(), interior_rings.as_slice()))
+                    .collect();
+                write::Geometry::MultiPolygon(&polygons).encode()
+            }
+        }
+    }
+}
+
+impl<'a> From<&write::Geometry<'a>> for Geometry {
+    fn from(geometry: &write::Geometry<'a>) -> Geometry {
+        let to_rings = |rings: &[&[TileCoord]]| rings.iter().map(|ring| ring.to_vec()).collect();
+
+        match geometry {
+            write::Geometry::Point(point) => Geometry::Point(*point),
+            write::Geometry::MultiPoint(points) => Geometry::MultiPoint(points.to_vec()),
+            write::Geometry::Line(line) => Geometry::Line(line.to_vec()),
+            write::Geometry::MultiLine(lines) => Geometry::MultiLine(to_rings(lines)),
+            write::Geometry::Polygon(exterior_ring, interior_rings) => {
+                Geometry::Polygon(exterior_ring.to_vec(), to_rings(interior_rings))
+            }
+            write::Geometry::MultiPolygon(polygons) => Geometry::MultiPolygon(
+                polygons
+                    .iter()
+                    .map(|(exterior_ring, interior_rings)| (exterior_ring.to_vec(), to_rings(interior_rings)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Twice the signed area of a ring, positive for exterior rings in tile coordinates.
+pub(crate) fn ring_area(ring: &[TileCoord]) -> i64 {
+    let mut area = 0i64;
+
+    for (idx, point) in ring.iter().enumerate() {
+        let next = ring[(idx + 1) % ring.len()];
+        area += point.0 as i64 * next.1 as i64 - next.0 as i64 * point.1 as i64;
+    }
+
+    area
+}
+
+pub(crate) fn decode_value(value: &pbf_tile::Value) -> Result<Value, ReadError> {
+    let decoded = [
+        value.string_value.as_ref().map(|v| Value::String(v.to_string())),
+        value.float_value.map(Value::Float),
+        value.double_value.map(Value::Double),
+        value.int_value.map(Value::Int),
+        value.uint_value.map(Value::UInt),
+        value.sint_value.map(Value::SInt),
+        value.bool_value.map(Value::Bool),
+    ];
+
+    let mut fields = decoded.iter().flatten();
+
+    match (fields.next(), fields.next()) {
+        (Some(value), None) => Ok(value.clone()),
+        _ => Err(ReadError::InvalidValue),
+    }
+}
+
+pub(crate) fn decode_tags<K: AsRef<str>>(
+    tags: &[u32],
+    keys: &[K],
+    values: &[Value],
+) -> Result<Vec<(String, Value)>, ReadError> {
+    if tags.len() % 2 != 0 {
+        return Err(ReadError::InvalidTagIndex(tags.len() as u32));
+    }
+
+    tags.chunks(2)
+        .map(|pair| {
+            let key = keys.get(pair[0] as usize).ok_or(ReadError::InvalidTagIndex(pair[0]))?;
+            let value = values
+                .get(pair[1] as usize)
+                .ok_or(ReadError::InvalidTagIndex(pair[1]))?;
+            Ok((key.as_ref().to_string(), value.clone()))
+        })
+        .collect()
+}
+
+fn decode_param(param: u32) -> i32 {
+    ((param >> 1) as i32) ^ -((param & 1) as i32)
+}
+
+pub(crate) fn decode_paths(commands: &[u32]) -> Result<Vec<Vec<TileCoord>>, ReadError> {
+    let mut paths: Vec<Vec<TileCoord>> = Vec::new();
+    let mut cursor: TileCoord = (0, 0);
+    let mut idx = 0;
+
+    while idx < commands.len() {
+        let command = commands[idx];
+        let count = (command >> 3) as usize;
+        idx += 1;
+
+        match command & 0x7 {
+            id @ 1 | id @ 2 => {
+                if count == 0 || commands.len() < idx + count * 2 {
+                    return Err(ReadError::InvalidGeometry);
+                }
+
+                for _ in 0..count {
+                    cursor = (
+                        cursor.0.wrapping_add(decode_param(commands[idx])),
+                        cursor.1.wrapping_add(decode_param(commands[idx + 1])),
+                    );
+                    idx += 2;
+
+                    if id == 1 {
+                        paths.push(vec![cursor]);
+                    } else {
+                        paths.last_mut().ok_or(ReadError::InvalidGeometry)?.push(cursor);
+                    }
+                }
+            }
+            7 => {
+                if count != 1 || paths.is_empty() {
+                    return Err(ReadError::InvalidGeometry);
+                }
+            }
+            _ => return Err(ReadError::InvalidGeometry),
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Decodes a geometry command sequence. Returns `None` for geometries of unknown type.
+pub(crate) fn decode_geometry(r#type: pbf_tile::GeomType, commands: &[u32]) -> Result<Option<Geometry>, ReadError> {
+    let mut paths = decode_paths(commands)?;
+
+    if paths.is_empty() && r#type != pbf_tile::GeomType::UNKNOWN {
+        return Err(ReadError::InvalidGeometry);
+    }
+
+    let geometry = match r#type {
+        pbf_tile::GeomType::UNKNOWN => return Ok(None),
+        pbf_tile::GeomType::POINT => {
+            if paths.iter().any(|path| path.len() != 1) {
+                return Err(ReadError::InvalidGeometry);
+            }
+
+            if paths.len() == 1 {
+                Geometry::Point(paths[0][0])
+            } else {
+                Geometry::MultiPoint(paths.into_iter().map(|path| path[0]).collect())
+            }
+        }
+        pbf_tile::GeomType::LINESTRING => {
+            if paths.iter().any(|path| path.len() < 2) {
+                return Err(ReadError::InvalidGeometry);
+            }
+
+            if paths.len() == 1 {
+                Geometry::Line(paths.remove(0))
+            } else {
+                Geometry::MultiLine(paths)
+            }
+        }
+        pbf_tile::GeomType::POLYGON => {
+            let mut polygons: Vec<(Vec<TileCoord>, Vec<Vec<TileCoord>>)> = Vec::new();
+
+            for ring in paths {
+                let area = ring_area(&ring);
+
+                if ring.len() < 3 || area == 0 {
+                    return Err(ReadError::InvalidGeometry);
+                } else if area > 0 {
+                    polygons.push((ring, Vec::new()));
+                } else {
+                    polygons.last_mut().ok_or(ReadError::InvalidGeometry)?.1.push(ring);
+                }
+            }
+
+            if polygons.len() == 1 {
+                let (exterior_ring, interior_rings) = polygons.remove(0);
+                Geometry::Polygon(exterior_ring, interior_rings)
+            } else {
+                Geometry::MultiPolygon(polygons)
+            }
+        }
+    };
+
+    Ok(Some(geometry))
+}
+
+#[cfg(test)]
+mod mvt_reader_test {
+    use super::*;
+    use crate::write;
+
+    fn encode_tile(geometry: &Geometry) -> Vec<u8> {
+        let mut feature = write::Feature::new(geometry.encode().unwrap());
+        feature.id = Some(42);
+        feature.add_tag("name", Value::String("test".into()));
+        feature.add_tag("lanes", Value::UInt(2));
+
+        let tile = write::Tile::new(vec![write::Layer::new("layer", vec![feature]).unwrap()]).unwrap();
+
+        let mut out = Vec::new();
+        tile.write(&mut out);
+        out
+    }
+
+    #[test]
+    fn round_trip() {
+        let geometries = vec![
+            Geometry::Point((5, 7)),
+            Geometry::MultiPoint(vec![(5, 7), (3, 2)]),
+            Geometry::Line(vec![(2, 2), (2, 10), (10, 10)]),
+            Geometry::MultiLine(vec![vec![(2, 2), (2, 10), (10, 10)], vec![(1, 1), (3, 5)]]),
+            Geometry::Polygon(vec![(3, 6), (8, 12), (20, 34)], vec![]),
+            Geometry::MultiPolygon(vec![
+                (
+                    vec![(0, 0), (10, 0), (10, 10), (0, 10)],
+                    vec![vec![(1, 1), (1, 2), (2, 2), (2, 1)]],
+                ),
+                (vec![(11, 11), (20, 11), (20, 20), (11, 20)], vec![]),
+            ]),
+        ];
+
+        for geometry in geometries {
+            let tile = Tile::from_bytes(&encode_tile(&geometry)).unwrap();
+            let layer = tile.layer("layer").unwrap();
+
+            assert_eq!(layer.extent, 4096);
+            assert_eq!(layer.features.len(), 1);
+
+            let feature = &layer.features[0];
+
+            assert_eq!(feature.id, Some(42));
+            assert_eq!(feature.tag("name"), Some(&Value::String("test".into())));
+            assert_eq!(feature.tag("lanes"), Some(&Value::UInt(2)));
+            assert_eq!(feature.geometry, geometry);
+        }
+    }
+
+    #[test]
+    fn invalid_geometry() {
+        assert_eq!(
+            decode_geometry(pbf_tile::GeomType::POINT, &[]),
+            Err(ReadError::InvalidGeometry)
+        );
+        assert_eq!(
+            decode_geometry(pbf_tile::GeomType::LINESTRING, &[9, 4, 4]),
+            Err(ReadError::InvalidGeometry)
+        );
+        assert_eq!(
+            decode_geometry(pbf_tile::GeomType::POLYGON, &[9, 4, 4, 18, 0, 16, 16, 0, 15]),
+            Err(ReadError::InvalidGeometry)
+        );
+        assert_eq!(decode_geometry(pbf_tile::GeomType::UNKNOWN, &[]), Ok(None));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compressed_tile() {
+        let mut feature = write::Feature::new(write::Geometry::Point((5, 7)).encode().unwrap());
+        feature.add_tag("name", Value::String("test".into()));
+        let tile = write::Tile::new(vec![write::Layer::new("layer", vec![feature]).unwrap()]).unwrap();
+
+        let mut compressed = Vec::new();
+        tile.write_compressed(&mut compressed, Compression::Gzip).unwrap();
+        assert_eq!(Compression::detect(&compressed), Compression::Gzip);
+
+        let tile = Tile::from_compressed_bytes(&compressed).unwrap();
+        assert_eq!(tile.layer("layer").unwrap().features.len(), 1);
+        assert_eq!(
+            Tile::from_compressed_bytes(&encode_tile(&Geometry::Point((5, 7))))
+                .unwrap()
+                .layers
+                .len(),
+            1
+        );
+    }
+}
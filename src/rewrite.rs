@@ -0,0 +1,277 @@
+use super::common::{GeometryType, Value};
+
+use super::error::{ReadError, RewriteError, SpecViolation};
+
+use super::read::decode_value;
+use super::write::geometry_type;
+
+use super::proto::vector_tile as pbf;
+use pbf::mod_Tile as pbf_tile;
+
+use quick_protobuf::{BytesReader, MessageRead, MessageWrite, Writer};
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+/// A feature as seen by the filter of a `Rewriter`, with all its tags.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeatureRef<'a> {
+    pub layer: &'a str,
+    pub id: Option<u64>,
+    pub geometry_type: GeometryType,
+    pub tags: Vec<(&'a str, &'a Value)>,
+}
+
+impl<'a> FeatureRef<'a> {
+    pub fn tag(&self, key: &str) -> Option<&'a Value> {
+        self.tags.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+    }
+}
+
+type Filter = Box<dyn Fn(&FeatureRef) -> bool + Send + Sync>;
+
+/// Renames and drops layers, drops attributes and filters features of encoded tiles without decoding their
+/// geometries. Geometry commands are copied as they are and only the key and value tables are rebuilt, so
+/// a rewriter can be set up once and applied to every tile served.
+#[derive(Default)]
+pub struct Rewriter {
+    renames: HashMap<String, String>,
+    dropped_layers: HashSet<String>,
+    dropped_keys: HashSet<String>,
+    filter: Option<Filter>,
+}
+
+impl Rewriter {
+    pub fn new() -> Rewriter {
+        Rewriter::default()
+    }
+
+    pub fn rename_layer<From: Into<String>, To: Into<String>>(mut self, from: From, to: To) -> Rewriter {
+        self.renames.insert(from.into(), to.into());
+        self
+    }
+
+    pub fn drop_layer<Name: Into<String>>(mut self, name: Name) -> Rewriter {
+        self.dropped_layers.insert(name.into());
+        self
+    }
+
+    /// Drops the attribute from the features of every layer.
+    pub fn drop_key<Key: Into<String>>(mut self, key: Key) -> Rewriter {
+        self.dropped_keys.insert(key.into());
+        self
+    }
+
+    /// Keeps only the features for which `filter` returns true. The filter sees the original layer name and
+    /// all tags, including the dropped ones.
+    pub fn with_filter<F>(mut self, filter: F) -> Rewriter
+    where
+        F: Fn(&FeatureRef) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Rewrites an uncompressed tile. Layers left without features are dropped, and `None` is returned if
+    /// no layer is left. Fails if renaming makes two layers share a name.
+    pub fn rewrite(&self, bytes: &[u8]) -> Result<Option<Vec<u8>>, RewriteError> {
+        let mut reader = BytesReader::from_bytes(bytes);
+        let tile = pbf::Tile::from_reader(&mut reader, bytes)?;
+
+        let mut layers = Vec::with_capacity(tile.layers.len());
+        let mut names = HashSet::with_capacity(tile.layers.len());
+
+        for layer in tile.layers {
+            if self.dropped_layers.contains(layer.name.as_ref()) {
+                continue;
+            }
+
+            if let Some(layer) = self.rewrite_layer(layer)? {
+                if !names.insert(layer.name.to_string()) {
+                    return Err(RewriteError::Write(SpecViolation::IdenticalLayerNames(
+                        layer.name.into_owned(),
+                    )));
+                }
+                layers.push(layer);
+            }
+        }
+
+        if layers.is_empty() {
+            return Ok(None);
+        }
+
+        let message = pbf::Tile { layers };
+        let mut out = Vec::with_capacity(message.get_size());
+        message.write_message(&mut Writer::new(&mut out))?;
+
+        Ok(Some(out))
+    }
+
+    fn rewrite_layer<'a>(&self, layer: pbf_tile::Layer<'a>) -> Result<Option<pbf_tile::Layer<'a>>, ReadError> {
+        let pbf_tile::Layer {
+            version,
+            name,
+            features: layer_features,
+            keys: layer_keys,
+            values: layer_values,
+            extent,
+        } = layer;
+
+        let values = match self.filter {
+            Some(_) => layer_values.iter().map(decode_value).collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+
+        let kept_keys: Vec<bool> = layer_keys
+            .iter()
+            .map(|key| !self.dropped_keys.contains(key.as_ref()))
+            .collect();
+
+        let mut key_map: Vec<Option<u32>> = vec![None; layer_keys.len()];
+        let mut value_map: Vec<Option<u32>> = vec![None; layer_values.len()];
+        let mut keys: Vec<Cow<'a, str>> = Vec::new();
+        let mut values_out: Vec<pbf_tile::Value<'a>> = Vec::new();
+        let mut features = Vec::with_capacity(layer_features.len());
+
+        for mut feature in layer_features {
+            if feature.tags.len() % 2 != 0 {
+                return Err(ReadError::InvalidTagIndex(feature.tags.len() as u32));
+            }
+            for pair in feature.tags.chunks(2) {
+                if pair[0] as usize >= layer_keys.len() {
+                    return Err(ReadError::InvalidTagIndex(pair[0]));
+                }
+                if pair[1] as usize >= layer_values.len() {
+                    return Err(ReadError::InvalidTagIndex(pair[1]));
+                }
+            }
+
+            if let Some(filter) = &self.filter {
+                let view = FeatureRef {
+                    layer: &name,
+                    id: if feature.id == 0 { None } else { Some(feature.id) },
+                    geometry_type: geometry_type(feature.type_pb),
+                    tags: feature
+                        .tags
+                        .chunks(2)
+                        .map(|pair| (layer_keys[pair[0] as usize].as_ref(), &values[pair[1] as usize]))
+                        .collect(),
+                };
+                if !filter(&view) {
+                    continue;
+                }
+            }
+
+            let mut tags = Vec::with_capacity(feature.tags.len());
+
+            for pair in feature.tags.chunks(2) {
+                let (key, value) = (pair[0] as usize, pair[1] as usize);
+                if !kept_keys[key] {
+                    continue;
+                }
+
+                let key_idx = *key_map[key].get_or_insert_with(|| {
+                    keys.push(layer_keys[key].clone());
+                    keys.len() as u32 - 1
+                });
+                let value_idx = *value_map[value].get_or_insert_with(|| {
+                    values_out.push(layer_values[value].clone());
+                    values_out.len() as u32 - 1
+                });
+
+                tags.push(key_idx);
+                tags.push(value_idx);
+            }
+
+            feature.tags = tags;
+            features.push(feature);
+        }
+
+        if features.is_empty() {
+            return Ok(None);
+        }
+
+        let name = match self.renames.get(name.as_ref()) {
+            Some(name) => Cow::Owned(name.clone()),
+            None => name,
+        };
+
+        Ok(Some(pbf_tile::Layer {
+            version,
+            name,
+            features,
+            keys,
+            values: values_out,
+            extent,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod rewrite_test {
+    use super::*;
+    use crate::read;
+    use crate::write::{self, EncodableGeometry};
+
+    fn create_test_tile() -> Vec<u8> {
+        let road = |id: u64, class: &str| {
+            let geometry = write::Geometry::Line(&[(0, 0), (10, 10)]).encode().unwrap();
+            let mut feature = write::Feature::new(geometry);
+            feature.id = Some(id);
+            feature.add_tag("class", Value::String(class.to_string()));
+            feature.add_tag("name", Value::String(format!("road {}", id)));
+            feature
+        };
+        let poi = {
+            let mut feature = write::Feature::new(write::Geometry::Point((5, 5)).encode().unwrap());
+            feature.add_tag("name", Value::String("poi".to_string()));
+            feature
+        };
+
+        let layers = vec![
+            write::Layer::new(
+                "roads",
+                vec![road(1, "primary"), road(2, "service"), road(3, "primary")],
+            )
+            .unwrap(),
+            write::Layer::new("pois", vec![poi]).unwrap(),
+        ];
+        write::Tile::new(layers).unwrap().to_bytes()
+    }
+
+    #[test]
+    fn rewriting() {
+        let bytes = create_test_tile();
+
+        let rewriter = Rewriter::new()
+            .rename_layer("roads", "transportation")
+            .drop_layer("pois")
+            .drop_key("name")
+            .with_filter(|feature| feature.tag("class") != Some(&Value::String("service".to_string())));
+
+        let tile = read::Tile::from_bytes(&rewriter.rewrite(&bytes).unwrap().unwrap()).unwrap();
+        let original = read::Tile::from_bytes(&bytes).unwrap();
+
+        assert_eq!(tile.layers.len(), 1);
+        let layer = &tile.layers[0];
+        assert_eq!(layer.name, "transportation");
+        assert_eq!(
+            layer.features.iter().map(|feature| feature.id).collect::<Vec<_>>(),
+            vec![Some(1), Some(3)]
+        );
+        assert_eq!(
+            layer.features[0].tags,
+            vec![("class".to_string(), Value::String("primary".to_string()))]
+        );
+        assert_eq!(layer.features[0].geometry, original.layers[0].features[0].geometry);
+
+        // Nothing left and identical names
+        assert_eq!(Rewriter::new().with_filter(|_| false).rewrite(&bytes), Ok(None));
+        assert_eq!(
+            Rewriter::new().rename_layer("pois", "roads").rewrite(&bytes),
+            Err(RewriteError::Write(SpecViolation::IdenticalLayerNames(
+                "roads".to_string()
+            )))
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use super::common::TileCoord;
+
+use super::read::{ring_area, Geometry};
+
+/// Simplifies a line with the Douglas-Peucker algorithm. Points closer than `tolerance` tile units to the
+/// simplified line are removed; the end points are always kept.
+pub fn simplify_line(line: &[TileCoord], tolerance: f64) -> Vec<TileCoord> {
+    if line.len() < 3 || tolerance <= 0.0 {
+        return line.to_vec();
+    }
+
+    let mut keep = vec![false; line.len()];
+    keep[0] = true;
+    keep[line.len() - 1] = true;
+
+    let mut ranges = vec![(0, line.len() - 1)];
+
+    while let Some((first, last)) = ranges.pop() {
+        let mut max_distance = 0.0;
+        let mut max_idx = first;
+
+        for idx in first + 1..last {
+            let distance = segment_distance(line[idx], line[first], line[last]);
+            if distance > max_distance {
+                max_distance = distance;
+                max_idx = idx;
+            }
+        }
+
+        if max_distance > tolerance {
+            keep[max_idx] = true;
+            ranges.push((first, max_idx));
+            ranges.push((max_idx, last));
+        }
+    }
+
+    line.iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(point, _)| *point)
+        .collect()
+}
+
+/// Simplifies an open ring. Returns `None` if the ring degenerates or its orientation flips.
+pub fn simplify_ring(ring: &[TileCoord], tolerance: f64) -> Option<Vec<TileCoord>> {
+    let mut closed = ring.to_vec();
+    closed.extend(ring.first());
+
+    let mut simplified = simplify_line(&closed, tolerance);
+    simplified.pop();
+
+    let area = ring_area(&simplified);
+
+    if simplified.len() < 3 || area == 0 || area.signum() != ring_area(ring).signum() {
+        None
+    } else {
+        Some(simplified)
+    }
+}
+
+/// Simplifies every line and ring of a geometry. Collapsed lines (all vertices equal), polygons and holes are
+/// removed; returns `None` if nothing is left. Closed lines are kept as long as they do not collapse.
+pub fn simplify(geometry: &Geometry, tolerance: f64) -> Option<Geometry> {
+    let simplify_lines = |lines: &[Vec<TileCoord>]| -> Vec<Vec<TileCoord>> {
+        lines
+            .iter()
+            .map(|line| simplify_line(line, tolerance))
+            .filter(|line| line.len() >= 2 && line.iter().any(|point| *point != line[0]))
+            .collect()
+    };
+
+    let simplify_polygon = |exterior_ring: &[TileCoord], interior_rings: &[Vec<TileCoord>]| {
+        let exterior_ring = simplify_ring(exterior_ring, tolerance)?;
+        let interior_rings = interior_rings
+            .iter()
+            .filter_map(|ring| simplify_ring(ring, tolerance))
+            .collect();
+        Some((exterior_ring, interior_rings))
+    };
+
+    match geometry {
+        Geometry::Point(_) | Geometry::MultiPoint(_) => Some(geometry.clone()),
+        Geometry::Line(line) => simplify_lines(std::slice::from_ref(line)).pop().map(Geometry::Line),
+        Geometry::MultiLine(lines) => {
+            let lines = simplify_lines(lines);
+            if lines.is_empty() {
+                None
+            } else {
+                Some(Geometry::MultiLine(lines))
+            }
+        }
+        Geometry::Polygon(exterior_ring, interior_rings) => simplify_polygon(exterior_ring, interior_rings)
+            .map(|(exterior_ring, interior_rings)| Geometry::Polygon(exterior_ring, interior_rings)),
+        Geometry::MultiPolygon(polygons) => {
+            let polygons: Vec<_> = polygons
+                .iter()
+                .filter_map(|(exterior_ring, interior_rings)| simplify_polygon(exterior_ring, interior_rings))
+                .collect();
+            if polygons.is_empty() {
+                None
+            } else {
+                Some(Geometry::MultiPolygon(polygons))
+            }
+        }
+    }
+}
+
+/// Distance of `point` from the segment between `start` and `end`.
+fn segment_distance(point: TileCoord, start: TileCoord, end: TileCoord) -> f64 {
+    let (px, py) = (point.0 as f64, point.1 as f64);
+    let (sx, sy) = (start.0 as f64, start.1 as f64);
+    let (dx, dy) = (end.0 as f64 - sx, end.1 as f64 - sy);
+
+    let length = dx * dx + dy * dy;
+    let t = if length == 0.0 {
+        0.0
+    } else {
+        (((px - sx) * dx + (py - sy) * dy) / length).clamp(0.0, 1.0)
+    };
+
+    let (cx, cy) = (sx + t * dx, sy + t * dy);
+    ((px - cx).powi(2) + (py - cy).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod simplify_test {
+    use super::*;
+
+    #[test]
+    fn line() {
+        let line = vec![(0, 0), (5, 1), (10, 0), (15, 10), (20, 0)];
+
+        assert_eq!(simplify_line(&line, 0.0), line);
+        assert_eq!(simplify_line(&line, 2.0), vec![(0, 0), (10, 0), (15, 10), (20, 0)]);
+        assert_eq!(simplify_line(&line, 20.0), vec![(0, 0), (20, 0)]);
+    }
+
+    #[test]
+    fn polygon() {
+        let exterior_ring = vec![(0, 0), (100, 0), (100, 100), (50, 101), (0, 100)];
+        let hole = vec![(10, 10), (10, 12), (12, 12), (12, 10)];
+        let polygon = Geometry::Polygon(exterior_ring, vec![hole]);
+
+        assert_eq!(
+            simplify(&polygon, 5.0),
+            Some(Geometry::Polygon(vec![(0, 0), (100, 0), (100, 100), (0, 100)], vec![]))
+        );
+        assert_eq!(simplify(&polygon, 200.0), None);
+    }
+
+    #[test]
+    fn closed_line() {
+        let line = Geometry::Line(vec![(0, 0), (100, 0), (100, 100), (0, 100), (0, 0)]);
+
+        assert_eq!(simplify(&line, 1.0), Some(line.clone()));
+        assert_eq!(simplify(&Geometry::Line(vec![(5, 5), (5, 5), (5, 5)]), 1.0), None);
+
+        let lines = Geometry::MultiLine(vec![
+            vec![(0, 0), (1, 1), (0, 0)],
+            vec![(0, 0), (100, 0), (100, 100), (0, 0)],
+        ]);
+        assert_eq!(
+            simplify(&lines, 5.0),
+            Some(Geometry::MultiLine(vec![vec![(0, 0), (100, 0), (100, 100), (0, 0)]]))
+        );
+    }
+}
@@ -0,0 +1,423 @@
+use super::common::{Value, ValueKey};
+
+use super::compression::{decompress, Compression};
+
+use super::error::{ArchiveError, ReadError};
+
+use super::read::{decode_paths, decode_value};
+use super::store::TileStore;
+use super::tile::TileId;
+
+use super::proto::vector_tile as pbf;
+use pbf::mod_Tile as pbf_tile;
+
+use quick_protobuf::sizeofs::{sizeof_len, sizeof_varint};
+use quick_protobuf::{BytesReader, MessageRead, MessageWrite};
+
+use serde_json::{json, Value as JsonValue};
+
+use std::collections::HashMap;
+
+/// Number of features per geometry type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GeometryCounts {
+    pub points: usize,
+    pub lines: usize,
+    pub polygons: usize,
+    pub unknown: usize,
+}
+
+/// Size and content statistics of a layer. Sizes are in bytes of the encoded protobuf message.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LayerStats {
+    pub name: String,
+    pub size: usize,
+    /// Encoded geometry commands of all features.
+    pub geometry_size: usize,
+    /// Tag indices of all features.
+    pub tags_size: usize,
+    pub keys_size: usize,
+    pub values_size: usize,
+    pub features: usize,
+    pub geometry_counts: GeometryCounts,
+    pub vertices: usize,
+    /// `(min_x, min_y, max_x, max_y)` of all vertices in tile coordinates.
+    pub bounds: Option<(i32, i32, i32, i32)>,
+    key_counts: HashMap<String, usize>,
+    value_counts: HashMap<ValueKey, (Value, usize)>,
+}
+
+impl LayerStats {
+    fn from_layer(layer: &pbf_tile::Layer) -> Result<LayerStats, ReadError> {
+        let mut stats = LayerStats {
+            name: layer.name.to_string(),
+            size: 1 + sizeof_len(layer.get_size()),
+            keys_size: layer.keys.iter().map(|key| 1 + sizeof_len(key.len())).sum(),
+            values_size: layer.values.iter().map(|value| 1 + sizeof_len(value.get_size())).sum(),
+            features: layer.features.len(),
+            ..Default::default()
+        };
+
+        let values = layer.values.iter().map(decode_value).collect::<Result<Vec<_>, _>>()?;
+
+        for feature in &layer.features {
+            stats.geometry_size += packed_size(&feature.geometry);
+            stats.tags_size += packed_size(&feature.tags);
+
+            match feature.type_pb {
+                pbf_tile::GeomType::POINT => stats.geometry_counts.points += 1,
+                pbf_tile::GeomType::LINESTRING => stats.geometry_counts.lines += 1,
+                pbf_tile::GeomType::POLYGON => stats.geometry_counts.polygons += 1,
+                pbf_tile::GeomType::UNKNOWN => stats.geometry_counts.unknown += 1,
+            }
+
+            for point in decode_paths(&feature.geometry)?.iter().flatten() {
+                stats.vertices += 1;
+                stats.bounds = Some(match stats.bounds {
+                    Some((min_x, min_y, max_x, max_y)) => (
+                        min_x.min(point.0),
+                        min_y.min(point.1),
+                        max_x.max(point.0),
+                        max_y.max(point.1),
+                    ),
+                    None => (point.0, point.1, point.0, point.1),
+                });
+            }
+
+            if feature.tags.len() % 2 != 0 {
+                return Err(ReadError::InvalidTagIndex(feature.tags.len() as u32));
+            }
+
+            for tag in feature.tags.chunks(2) {
+                let key = layer
+                    .keys
+                    .get(tag[0] as usize)
+                    .ok_or(ReadError::InvalidTagIndex(tag[0]))?;
+                let value = values.get(tag[1] as usize).ok_or(ReadError::InvalidTagIndex(tag[1]))?;
+
+                *stats.key_counts.entry(key.to_string()).or_insert(0) += 1;
+                stats
+                    .value_counts
+                    .entry(value.key())
+                    .or_insert_with(|| (value.clone(), 0))
+                    .1 += 1;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Adds the statistics of another layer, e.g. the same layer in another tile.
+    pub fn merge(&mut self, other: &LayerStats) {
+        self.size += other.size;
+        self.geometry_size += other.geometry_size;
+        self.tags_size += other.tags_size;
+        self.keys_size += other.keys_size;
+        self.values_size += other.values_size;
+        self.features += other.features;
+        self.geometry_counts.points += other.geometry_counts.points;
+        self.geometry_counts.lines += other.geometry_counts.lines;
+        self.geometry_counts.polygons += other.geometry_counts.polygons;
+        self.geometry_counts.unknown += other.geometry_counts.unknown;
+        self.vertices += other.vertices;
+
+        self.bounds = match (self.bounds, other.bounds) {
+            (Some(a), Some(b)) => Some((a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3))),
+            (a, b) => a.or(b),
+        };
+
+        for (key, count) in &other.key_counts {
+            *self.key_counts.entry(key.clone()).or_insert(0) += count;
+        }
+        for (key, (value, count)) in &other.value_counts {
+            self.value_counts
+                .entry(key.clone())
+                .or_insert_with(|| (value.clone(), 0))
+                .1 += count;
+        }
+    }
+
+    /// The `n` most used keys with the number of features using them.
+    pub fn top_keys(&self, n: usize) -> Vec<(&str, usize)> {
+        let mut keys: Vec<_> = self
+            .key_counts
+            .iter()
+            .map(|(key, count)| (key.as_str(), *count))
+            .collect();
+        keys.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        keys.truncate(n);
+        keys
+    }
+
+    /// The `n` most used values with the number of features using them.
+    pub fn top_values(&self, n: usize) -> Vec<(&Value, usize)> {
+        let mut values: Vec<_> = self.value_counts.iter().collect();
+        values.sort_by(|a, b| (b.1).1.cmp(&(a.1).1).then_with(|| a.0.cmp(b.0)));
+        values
+            .into_iter()
+            .take(n)
+            .map(|(_, (value, count))| (value, *count))
+            .collect()
+    }
+
+    pub fn to_json(&self, top: usize) -> JsonValue {
+        json!({
+            "name": self.name,
+            "size": self.size,
+            "geometry_size": self.geometry_size,
+            "tags_size": self.tags_size,
+            "keys_size": self.keys_size,
+            "values_size": self.values_size,
+            "features": self.features,
+            "points": self.geometry_counts.points,
+            "lines": self.geometry_counts.lines,
+            "polygons": self.geometry_counts.polygons,
+            "unknown": self.geometry_counts.unknown,
+            "vertices": self.vertices,
+            "bounds": self.bounds.map(|(min_x, min_y, max_x, max_y)| vec![min_x, min_y, max_x, max_y]),
+            "top_keys": self.top_keys(top).iter().map(|(key, count)| json!([key, count])).collect::<Vec<_>>(),
+            "top_values": self
+                .top_values(top)
+                .iter()
+                .map(|(value, count)| json!([value_to_json(value), count]))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Size of a packed repeated field, zero if it is empty and therefore omitted.
+fn packed_size(values: &[u32]) -> usize {
+    if values.is_empty() {
+        0
+    } else {
+        1 + sizeof_len(values.iter().map(|value| sizeof_varint(*value as u64)).sum())
+    }
+}
+
+pub(crate) fn value_to_json(value: &Value) -> JsonValue {
+    match value {
+        Value::String(v) => json!(v),
+        Value::Float(v) => json!(v),
+        Value::Double(v) => json!(v),
+        Value::Int(v) => json!(v),
+        Value::UInt(v) => json!(v),
+        Value::SInt(v) => json!(v),
+        Value::Bool(v) => json!(v),
+    }
+}
+
+/// Statistics of an encoded tile.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TileStats {
+    pub size: usize,
+    pub layers: Vec<LayerStats>,
+}
+
+impl TileStats {
+    /// Analyzes an uncompressed tile.
+    pub fn from_bytes(bytes: &[u8]) -> Result<TileStats, ReadError> {
+        let mut reader = BytesReader::from_bytes(bytes);
+        let message = pbf::Tile::from_reader(&mut reader, bytes)?;
+
+        Ok(TileStats {
+            size: bytes.len(),
+            layers: message
+                .layers
+                .iter()
+                .map(LayerStats::from_layer)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&LayerStats> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+
+    pub fn to_json(&self, top: usize) -> JsonValue {
+        json!({
+            "size": self.size,
+            "layers": self.layers.iter().map(|layer| layer.to_json(top)).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Statistics aggregated over all tiles of an archive.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ArchiveStats {
+    pub tiles: usize,
+    /// Total size of the stored, possibly compressed, tile data.
+    pub stored_size: u64,
+    /// Total size of the uncompressed tiles.
+    pub size: u64,
+    /// The largest tiles by uncompressed size, largest first.
+    pub largest: Vec<(TileId, usize)>,
+    /// Layer statistics summed over all tiles, ordered by name.
+    pub layers: Vec<LayerStats>,
+}
+
+impl ArchiveStats {
+    /// Analyzes every tile in `store`, keeping track of the `largest` biggest tiles.
+    pub fn from_store<S: TileStore + ?Sized>(store: &mut S, largest: usize) -> Result<ArchiveStats, ArchiveError> {
+        let mut stats = ArchiveStats::default();
+        let mut layers: HashMap<String, LayerStats> = HashMap::new();
+
+        for id in store.iter()? {
+            let data = match store.get(id)? {
+                Some(data) => data,
+                None => continue,
+            };
+
+            stats.tiles += 1;
+            stats.stored_size += data.len() as u64;
+
+            let data = match Compression::detect(&data) {
+                Compression::None => data,
+                compression => decompress(&data, compression)?,
+            };
+
+            let tile = TileStats::from_bytes(&data)?;
+            stats.size += tile.size as u64;
+
+            stats.largest.push((id, tile.size));
+            stats.largest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            stats.largest.truncate(largest);
+
+            for layer in &tile.layers {
+                match layers.get_mut(&layer.name) {
+                    Some(total) => total.merge(layer),
+                    None => {
+                        layers.insert(layer.name.clone(), layer.clone());
+                    }
+                }
+            }
+        }
+
+        stats.layers = layers.into_values().collect();
+        stats.layers.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(stats)
+    }
+
+    pub fn to_json(&self, top: usize) -> JsonValue {
+        json!({
+            "tiles": self.tiles,
+            "stored_size": self.stored_size,
+            "size": self.size,
+            "largest": self
+                .largest
+                .iter()
+                .map(|(id, size)| json!({ "tile": id.to_string(), "size": size }))
+                .collect::<Vec<_>>(),
+            "layers": self.layers.iter().map(|layer| layer.to_json(top)).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod stats_test {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::write::{EncodableGeometry, Feature, Geometry, Layer, Tile};
+
+    fn create_test_tile(points: usize) -> Vec<u8> {
+        let mut features = Vec::new();
+
+        for idx in 0..points {
+            let mut feature = Feature::new(Geometry::Point((idx as i32, 10)).encode().unwrap());
+            feature.add_tag("kind", Value::String("poi".into()));
+            feature.add_tag("rank", Value::UInt(idx as u64 % 2));
+            features.push(feature);
+        }
+
+        let line = [(0, 0), (100, 50), (200, 0)];
+        let mut feature = Feature::new(Geometry::Line(&line).encode().unwrap());
+        feature.add_tag("kind", Value::String("road".into()));
+
+        let points = Layer::new("points", features).unwrap();
+        let lines = Layer::new("lines", vec![feature]).unwrap();
+
+        Tile::new(vec![points, lines]).unwrap().to_bytes()
+    }
+
+    #[test]
+    fn tile_stats() {
+        let bytes = create_test_tile(3);
+        let stats = TileStats::from_bytes(&bytes).unwrap();
+
+        assert_eq!(stats.size, bytes.len());
+        assert_eq!(stats.layers.iter().map(|layer| layer.size).sum::<usize>(), bytes.len());
+
+        let points = stats.layer("points").unwrap();
+        assert_eq!(points.features, 3);
+        assert_eq!(points.geometry_counts.points, 3);
+        assert_eq!(points.vertices, 3);
+        assert_eq!(points.bounds, Some((0, 10, 2, 10)));
+        assert_eq!(points.top_keys(1), vec![("kind", 3)]);
+        assert_eq!(
+            points.top_values(2),
+            vec![(&Value::String("poi".into()), 3), (&Value::UInt(0), 2)]
+        );
+        assert!(points.geometry_size + points.tags_size + points.keys_size + points.values_size < points.size);
+
+        let lines = stats.layer("lines").unwrap();
+        assert_eq!(lines.geometry_counts.lines, 1);
+        assert_eq!(lines.vertices, 3);
+        assert_eq!(lines.bounds, Some((0, 0, 200, 50)));
+        // [9, 0, 0, 18, 200, 100, 200, 99] takes 10 bytes as varints, plus the field key and length
+        assert_eq!(lines.geometry_size, 12);
+    }
+
+    #[test]
+    fn archive_stats() {
+        let mut store = MemoryStore::new();
+        store.put(TileId::new(1, 0, 0).unwrap(), &create_test_tile(1)).unwrap();
+        store.put(TileId::new(1, 1, 0).unwrap(), &create_test_tile(10)).unwrap();
+        store.put(TileId::new(1, 0, 1).unwrap(), &create_test_tile(5)).unwrap();
+
+        let stats = ArchiveStats::from_store(&mut store, 2).unwrap();
+
+        assert_eq!(stats.tiles, 3);
+        assert_eq!(stats.size, stats.stored_size);
+        assert_eq!(
+            stats.largest.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![TileId::new(1, 1, 0).unwrap(), TileId::new(1, 0, 1).unwrap()]
+        );
+
+        assert_eq!(stats.layers[0].name, "lines");
+        assert_eq!(stats.layers[1].features, 16);
+        assert_eq!(stats.layers[1].top_keys(1), vec![("kind", 16)]);
+
+        let json = stats.to_json(3);
+        assert_eq!(json["largest"][0]["tile"], "1/1/0");
+        assert_eq!(json["layers"][1]["top_values"][0][0], "poi");
+    }
+
+    #[test]
+    fn distinct_values() {
+        let values = [
+            Value::Double(f64::NAN),
+            Value::Double(f64::NAN),
+            Value::Double(1.0),
+            Value::Int(1),
+        ];
+
+        let features = values
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| {
+                let mut feature = Feature::new(Geometry::Point((idx as i32, 0)).encode().unwrap());
+                feature.add_tag("value", value.clone());
+                feature
+            })
+            .collect();
+        let bytes = Tile::new(vec![Layer::new("values", features).unwrap()])
+            .unwrap()
+            .to_bytes();
+
+        let stats = TileStats::from_bytes(&bytes).unwrap();
+        let top_values = stats.layer("values").unwrap().top_values(4);
+
+        assert_eq!(top_values.len(), 3);
+        assert!(matches!(top_values[0], (Value::Double(value), 2) if value.is_nan()));
+    }
+}
@@ -0,0 +1,164 @@
+use super::compression::{self, Compression};
+
+use super::error::ArchiveError;
+
+use super::metadata::Metadata;
+
+use super::read;
+use super::tile::TileId;
+
+use std::collections::HashMap;
+
+/// Common interface of the tile archive formats. Tile data is stored and returned as is, so it is up to the
+/// caller to compress tiles the way the target format expects (gzip for MBTiles, see `tile_compression`).
+pub trait TileStore {
+    fn get(&mut self, id: TileId) -> Result<Option<Vec<u8>>, ArchiveError>;
+
+    fn put(&mut self, id: TileId, data: &[u8]) -> Result<(), ArchiveError>;
+
+    /// All tiles in the store.
+    fn iter(&mut self) -> Result<Box<dyn Iterator<Item = TileId>>, ArchiveError>;
+
+    fn metadata(&mut self) -> Result<Metadata, ArchiveError>;
+
+    fn set_metadata(&mut self, metadata: &Metadata) -> Result<(), ArchiveError>;
+
+    /// Flushes pending writes. Stores must not be written after finalizing them.
+    fn finalize(&mut self) -> Result<(), ArchiveError>;
+
+    /// The compression tile data must have to be put into the store, or `None` if any data is accepted.
+    fn tile_compression(&self) -> Option<Compression> {
+        None
+    }
+
+    /// Reads and decodes a tile, decompressing it if it is gzip or zstd compressed.
+    fn read_tile(&mut self, id: TileId) -> Result<Option<read::Tile>, ArchiveError> {
+        match self.get(id)? {
+            Some(data) => Ok(Some(read::Tile::from_compressed_bytes(&data)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Copies every tile and the metadata of `source` into `destination`, returning the number of tiles copied.
+/// Tiles are recompressed if the destination requires a different tile compression. The destination is not
+/// finalized, so more tiles can be added to it afterwards.
+pub fn copy<S, D>(source: &mut S, destination: &mut D) -> Result<usize, ArchiveError>
+where
+    S: TileStore + ?Sized,
+    D: TileStore + ?Sized,
+{
+    let mut count = 0;
+
+    for id in source.iter()? {
+        if let Some(mut data) = source.get(id)? {
+            if let Some(expected) = destination.tile_compression() {
+                if !expected.matches(&data) {
+                    let decompressed = compression::decompress(&data, Compression::detect(&data))?;
+                    data = compression::compress(&decompressed, expected)?;
+                }
+            }
+
+            destination.put(id, &data)?;
+            count += 1;
+        }
+    }
+
+    destination.set_metadata(&source.metadata()?)?;
+
+    Ok(count)
+}
+
+/// A tile store keeping everything in memory, mostly useful for tests.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    pub tiles: HashMap<TileId, Vec<u8>>,
+    pub metadata: Metadata,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl TileStore for MemoryStore {
+    fn get(&mut self, id: TileId) -> Result<Option<Vec<u8>>, ArchiveError> {
+        Ok(self.tiles.get(&id).cloned())
+    }
+
+    fn put(&mut self, id: TileId, data: &[u8]) -> Result<(), ArchiveError> {
+        self.tiles.insert(id, data.to_vec());
+        Ok(())
+    }
+
+    fn iter(&mut self) -> Result<Box<dyn Iterator<Item = TileId>>, ArchiveError> {
+        let mut ids: Vec<TileId> = self.tiles.keys().copied().collect();
+        ids.sort_unstable();
+        Ok(Box::new(ids.into_iter()))
+    }
+
+    fn metadata(&mut self) -> Result<Metadata, ArchiveError> {
+        Ok(self.metadata.clone())
+    }
+
+    fn set_metadata(&mut self, metadata: &Metadata) -> Result<(), ArchiveError> {
+        self.metadata = metadata.clone();
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<(), ArchiveError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod store_test {
+    use super::*;
+    use crate::common::Value;
+    use crate::directory::DirectoryStore;
+    use crate::write::{self, EncodableGeometry};
+
+    fn create_test_tile() -> Vec<u8> {
+        let mut feature = write::Feature::new(write::Geometry::Point((10, 20)).encode().unwrap());
+        feature.add_tag("name", Value::String("test".into()));
+        write::Tile::new(vec![write::Layer::new("poi", vec![feature]).unwrap()])
+            .unwrap()
+            .to_bytes()
+    }
+
+    fn fill(store: &mut dyn TileStore) {
+        for child in TileId::new(0, 0, 0).unwrap().children().unwrap().iter() {
+            store.put(*child, &create_test_tile()).unwrap();
+        }
+
+        let metadata = Metadata {
+            name: Some("test".into()),
+            ..Default::default()
+        };
+        store.set_metadata(&metadata).unwrap();
+        store.finalize().unwrap();
+    }
+
+    #[test]
+    fn copy_between_stores() {
+        let mut memory = MemoryStore::new();
+        fill(&mut memory);
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut directory = DirectoryStore::create(dir.path()).unwrap();
+
+        assert_eq!(copy(&mut memory, &mut directory).unwrap(), 4);
+        directory.finalize().unwrap();
+
+        let mut copied = MemoryStore::new();
+        copy(&mut directory, &mut copied).unwrap();
+
+        assert_eq!(copied.tiles, memory.tiles);
+        assert_eq!(copied.metadata, memory.metadata);
+
+        let tile = copied.read_tile(TileId::new(1, 1, 1).unwrap()).unwrap().unwrap();
+        assert_eq!(tile.layer("poi").unwrap().features.len(), 1);
+        assert!(copied.read_tile(TileId::new(0, 0, 0).unwrap()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,160 @@
+use super::common::TileCoord;
+
+use super::read::{Feature, Geometry};
+
+use std::collections::HashMap;
+
+struct Candidate {
+    priority: f64,
+    feature: usize,
+    point: usize,
+    position: TileCoord,
+}
+
+/// Keeps at most `max_per_cell` points in every `cell_size` sized cell of a grid aligned to the tile origin.
+///
+/// The points of `Point` and `MultiPoint` features are ranked within their cell by the feature's `priority`
+/// (higher first), then by feature id and position, so that the outcome does not depend on the order of
+/// the features. If both the extent and the tile buffer are multiples of `cell_size`, the cells of
+/// neighbouring tiles line up and no cell is cut off at the edge of the buffer, so points in the buffers are
+/// thinned the same way in both tiles.
+///
+/// Multipoints lose their thinned points and features left without points are removed. Other geometries
+/// are kept as they are, and the order of the features is preserved.
+pub fn thin<P>(features: Vec<Feature>, cell_size: u32, max_per_cell: usize, priority: P) -> Vec<Feature>
+where
+    P: Fn(&Feature) -> f64,
+{
+    let cell_size = cell_size.max(1) as i32;
+    let cell = |point: &TileCoord| (point.0.div_euclid(cell_size), point.1.div_euclid(cell_size));
+
+    let points = |feature: &Feature| -> Vec<TileCoord> {
+        match &feature.geometry {
+            Geometry::Point(point) => vec![*point],
+            Geometry::MultiPoint(points) => points.clone(),
+            _ => Vec::new(),
+        }
+    };
+
+    let mut cells: HashMap<(i32, i32), Vec<Candidate>> = HashMap::new();
+
+    for (feature_idx, feature) in features.iter().enumerate() {
+        let feature_priority = priority(feature);
+
+        for (point_idx, position) in points(feature).into_iter().enumerate() {
+            cells.entry(cell(&position)).or_default().push(Candidate {
+                priority: feature_priority,
+                feature: feature_idx,
+                point: point_idx,
+                position,
+            });
+        }
+    }
+
+    let mut kept: Vec<Vec<bool>> = features
+        .iter()
+        .map(|feature| vec![false; points(feature).len()])
+        .collect();
+
+    for candidates in cells.values_mut() {
+        candidates.sort_by(|a, b| {
+            b.priority
+                .total_cmp(&a.priority)
+                .then_with(|| features[a.feature].id.cmp(&features[b.feature].id))
+                .then_with(|| (a.position.1, a.position.0).cmp(&(b.position.1, b.position.0)))
+                .then_with(|| (a.feature, a.point).cmp(&(b.feature, b.point)))
+        });
+
+        for candidate in candidates.iter().take(max_per_cell) {
+            kept[candidate.feature][candidate.point] = true;
+        }
+    }
+
+    features
+        .into_iter()
+        .zip(kept)
+        .filter_map(|(mut feature, kept)| {
+            feature.geometry = match feature.geometry {
+                Geometry::Point(point) if kept[0] => Geometry::Point(point),
+                Geometry::Point(_) => return None,
+                Geometry::MultiPoint(points) => {
+                    let points: Vec<TileCoord> = points
+                        .into_iter()
+                        .zip(kept)
+                        .filter(|(_, kept)| *kept)
+                        .map(|(point, _)| point)
+                        .collect();
+
+                    if points.is_empty() {
+                        return None;
+                    }
+                    Geometry::MultiPoint(points)
+                }
+                geometry => geometry,
+            };
+
+            Some(feature)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod thin_test {
+    use super::*;
+    use crate::common::Value;
+
+    fn point(id: u64, position: TileCoord, rank: u64) -> Feature {
+        Feature {
+            id: Some(id),
+            tags: vec![("rank".to_string(), Value::UInt(rank))],
+            geometry: Geometry::Point(position),
+        }
+    }
+
+    fn rank(feature: &Feature) -> f64 {
+        feature.tag("rank").and_then(Value::as_f64).unwrap_or(0.0)
+    }
+
+    fn ids(features: &[Feature]) -> Vec<u64> {
+        features.iter().filter_map(|feature| feature.id).collect()
+    }
+
+    #[test]
+    fn thinning() {
+        let features = vec![
+            point(1, (10, 10), 1),
+            point(2, (20, 20), 5),
+            point(3, (30, 30), 3),
+            point(4, (300, 10), 1),
+            point(5, (-10, 10), 1),
+            Feature {
+                id: Some(6),
+                tags: vec![("rank".to_string(), Value::UInt(4))],
+                geometry: Geometry::MultiPoint(vec![(40, 40), (310, 10), (600, 600)]),
+            },
+            Feature {
+                id: Some(7),
+                tags: Vec::new(),
+                geometry: Geometry::Line(vec![(0, 0), (10, 10)]),
+            },
+        ];
+
+        let thinned = thin(features.clone(), 256, 2, rank);
+        assert_eq!(ids(&thinned), vec![2, 4, 5, 6, 7]);
+        assert_eq!(
+            thinned[3].geometry,
+            Geometry::MultiPoint(vec![(40, 40), (310, 10), (600, 600)])
+        );
+
+        let thinned = thin(features.clone(), 256, 1, rank);
+        assert_eq!(ids(&thinned), vec![2, 5, 6, 7]);
+        assert_eq!(thinned[2].geometry, Geometry::MultiPoint(vec![(310, 10), (600, 600)]));
+
+        // The input order does not matter
+        let mut reversed = features;
+        reversed.reverse();
+        let mut thinned_ids = ids(&thin(reversed, 256, 1, rank));
+        thinned_ids.sort_unstable();
+        assert_eq!(thinned_ids, vec![2, 5, 6, 7]);
+    }
+}
@@ -0,0 +1,340 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+/// Address of a tile in the XYZ (slippy map) tiling scheme, where `y` grows southwards.
+///
+/// The fields are only set through the constructors, so a `TileId` always has a zoom level of at most
+/// `MAX_ZOOM` and coordinates within that level.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TileId {
+    z: u8,
+    x: u32,
+    y: u32,
+}
+
+impl TileId {
+    pub const MAX_ZOOM: u8 = 31;
+
+    /// Returns `None` if the zoom level is above `MAX_ZOOM` or the coordinates are out of range.
+    pub fn new(z: u8, x: u32, y: u32) -> Option<TileId> {
+        if z > Self::MAX_ZOOM || x as u64 >= Self::size(z) || y as u64 >= Self::size(z) {
+            None
+        } else {
+            Some(TileId { z, x, y })
+        }
+    }
+
+    pub fn z(&self) -> u8 {
+        self.z
+    }
+
+    pub fn x(&self) -> u32 {
+        self.x
+    }
+
+    pub fn y(&self) -> u32 {
+        self.y
+    }
+
+    /// Number of tiles along one axis at a zoom level.
+    fn size(z: u8) -> u64 {
+        1 << z
+    }
+
+    /// Tile containing the given WGS84 position. Latitudes are clamped to the Web Mercator range.
+    pub fn from_lon_lat(lon: f64, lat: f64, z: u8) -> Option<TileId> {
+        if z > Self::MAX_ZOOM || !lon.is_finite() || !lat.is_finite() {
+            return None;
+        }
+
+        let (x, y) = lon_lat_to_unit(lon, lat);
+        let max = (Self::size(z) - 1) as f64;
+        let scale = Self::size(z) as f64;
+
+        TileId::new(
+            z,
+            (x * scale).floor().clamp(0.0, max) as u32,
+            (y * scale).floor().clamp(0.0, max) as u32,
+        )
+    }
+
+    pub fn parent(&self) -> Option<TileId> {
+        if self.z == 0 {
+            None
+        } else {
+            Some(TileId {
+                z: self.z - 1,
+                x: self.x >> 1,
+                y: self.y >> 1,
+            })
+        }
+    }
+
+    /// Children in quadkey order (north-west, north-east, south-west, south-east).
+    pub fn children(&self) -> Option<[TileId; 4]> {
+        if self.z == Self::MAX_ZOOM {
+            return None;
+        }
+
+        let (z, x, y) = (self.z + 1, self.x << 1, self.y << 1);
+
+        Some([
+            TileId { z, x, y },
+            TileId { z, x: x + 1, y },
+            TileId { z, x, y: y + 1 },
+            TileId { z, x: x + 1, y: y + 1 },
+        ])
+    }
+
+    /// The up to eight surrounding tiles. Neighbours wrap around the antimeridian but not the poles.
+    pub fn neighbours(&self) -> Vec<TileId> {
+        let size = Self::size(self.z) as i64;
+        let mut neighbours = Vec::with_capacity(8);
+
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let x = (self.x as i64 + dx).rem_euclid(size) as u32;
+                let y = self.y as i64 + dy;
+
+                if (dx == 0 && dy == 0) || y < 0 || y >= size {
+                    continue;
+                }
+
+                let neighbour = TileId {
+                    z: self.z,
+                    x,
+                    y: y as u32,
+                };
+
+                if neighbour != *self && !neighbours.contains(&neighbour) {
+                    neighbours.push(neighbour);
+                }
+            }
+        }
+
+        neighbours
+    }
+
+    /// Bing Maps quadkey of the tile. The quadkey of the root tile is empty.
+    pub fn quadkey(&self) -> String {
+        (1..=self.z)
+            .rev()
+            .map(|level| {
+                let mask = 1 << (level - 1);
+                let digit = ((self.x & mask != 0) as u8) | (((self.y & mask != 0) as u8) << 1);
+                (b'0' + digit) as char
+            })
+            .collect()
+    }
+
+    pub fn from_quadkey(quadkey: &str) -> Option<TileId> {
+        if quadkey.len() > Self::MAX_ZOOM as usize {
+            return None;
+        }
+
+        let mut tile = TileId { z: 0, x: 0, y: 0 };
+
+        for digit in quadkey.bytes() {
+            let digit = match digit {
+                b'0'..=b'3' => (digit - b'0') as u32,
+                _ => return None,
+            };
+
+            tile = TileId {
+                z: tile.z + 1,
+                x: (tile.x << 1) | (digit & 1),
+                y: (tile.y << 1) | (digit >> 1),
+            };
+        }
+
+        Some(tile)
+    }
+
+    /// Parses the `z/x/y` form written by `Display`.
+    pub fn parse(text: &str) -> Option<TileId> {
+        let mut parts = text.split('/');
+        let tile = TileId::new(
+            parts.next()?.parse().ok()?,
+            parts.next()?.parse().ok()?,
+            parts.next()?.parse().ok()?,
+        );
+
+        if parts.next().is_some() {
+            None
+        } else {
+            tile
+        }
+    }
+
+    /// Converts between the XYZ and TMS schemes. TMS rows grow northwards, so the conversion is its own inverse.
+    pub fn flip_y(&self) -> TileId {
+        TileId {
+            z: self.z,
+            x: self.x,
+            y: (Self::size(self.z) - 1 - self.y as u64) as u32,
+        }
+    }
+
+    /// WGS84 bounds of the tile as `(west, south, east, north)` in degrees.
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        let size = Self::size(self.z) as f64;
+        let (west, north) = unit_to_lon_lat(self.x as f64 / size, self.y as f64 / size);
+        let (east, south) = unit_to_lon_lat((self.x + 1) as f64 / size, (self.y + 1) as f64 / size);
+        (west, south, east, north)
+    }
+
+    /// Position of the tile on the Hilbert curve, counting all tiles of the lower zoom levels first.
+    /// This is the tile id used by PMTiles.
+    pub fn hilbert_index(&self) -> u64 {
+        let n = Self::size(self.z);
+        let (mut x, mut y) = (self.x as u64, self.y as u64);
+        let mut index = 0;
+
+        let mut s = n / 2;
+        while s > 0 {
+            let rx = (x & s != 0) as u64;
+            let ry = (y & s != 0) as u64;
+            index += s * s * ((3 * rx) ^ ry);
+            rotate(n, &mut x, &mut y, rx, ry);
+            s /= 2;
+        }
+
+        Self::hilbert_offset(self.z) + index
+    }
+
+    pub fn from_hilbert_index(index: u64) -> Option<TileId> {
+        let z = (0..=Self::MAX_ZOOM).find(|z| index < Self::hilbert_offset(z + 1))?;
+
+        let n = Self::size(z);
+        let mut t = index - Self::hilbert_offset(z);
+        let (mut x, mut y) = (0, 0);
+
+        let mut s = 1;
+        while s < n {
+            let rx = 1 & (t / 2);
+            let ry = 1 & (t ^ rx);
+            rotate(s, &mut x, &mut y, rx, ry);
+            x += s * rx;
+            y += s * ry;
+            t /= 4;
+            s *= 2;
+        }
+
+        TileId::new(z, x as u32, y as u32)
+    }
+
+    /// Number of tiles on all zoom levels below `z`.
+    fn hilbert_offset(z: u8) -> u64 {
+        ((1u128 << (2 * z as u32)) / 3) as u64
+    }
+}
+
+impl fmt::Display for TileId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.z, self.x, self.y)
+    }
+}
+
+fn rotate(n: u64, x: &mut u64, y: &mut u64, rx: u64, ry: u64) {
+    if ry == 0 {
+        if rx == 1 {
+            *x = n - 1 - *x;
+            *y = n - 1 - *y;
+        }
+        std::mem::swap(x, y);
+    }
+}
+
+/// Projects a WGS84 position to Web Mercator, normalized to the unit square with the origin in the north-west.
+pub(crate) fn lon_lat_to_unit(lon: f64, lat: f64) -> (f64, f64) {
+    let lat = lat.clamp(-85.051_128_779_806_59, 85.051_128_779_806_59).to_radians();
+    let x = (lon + 180.0) / 360.0;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0;
+    (x, y)
+}
+
+pub(crate) fn unit_to_lon_lat(x: f64, y: f64) -> (f64, f64) {
+    let lon = x * 360.0 - 180.0;
+    let lat = (PI * (1.0 - 2.0 * y)).sinh().atan().to_degrees();
+    (lon, lat)
+}
+
+#[cfg(test)]
+mod tile_test {
+    use super::*;
+
+    #[test]
+    fn invalid_tile() {
+        assert_eq!(TileId::new(0, 1, 0), None);
+        assert_eq!(TileId::new(2, 3, 4), None);
+        assert_eq!(TileId::new(32, 0, 0), None);
+    }
+
+    #[test]
+    fn pyramid() {
+        let tile = TileId::new(3, 5, 2).unwrap();
+
+        assert_eq!(tile.parent(), TileId::new(2, 2, 1));
+        assert_eq!(TileId::new(0, 0, 0).unwrap().parent(), None);
+
+        for child in tile.children().unwrap().iter() {
+            assert_eq!(child.parent(), Some(tile));
+        }
+
+        assert_eq!(tile.neighbours().len(), 8);
+        assert_eq!(TileId::new(1, 0, 0).unwrap().neighbours().len(), 3);
+        assert!(TileId::new(2, 0, 1)
+            .unwrap()
+            .neighbours()
+            .contains(&TileId::new(2, 3, 1).unwrap()));
+    }
+
+    #[test]
+    fn quadkey() {
+        let tile = TileId::new(3, 3, 5).unwrap();
+        assert_eq!(tile.quadkey(), "213");
+        assert_eq!(TileId::from_quadkey("213"), Some(tile));
+        assert_eq!(TileId::from_quadkey(""), TileId::new(0, 0, 0));
+        assert_eq!(TileId::from_quadkey("214"), None);
+    }
+
+    #[test]
+    fn parse() {
+        let tile = TileId::new(3, 3, 5).unwrap();
+        assert_eq!(TileId::parse(&tile.to_string()), Some(tile));
+        assert_eq!(TileId::parse("3/3/8"), None);
+        assert_eq!(TileId::parse("3/3/5/1"), None);
+        assert_eq!(TileId::parse("3/3"), None);
+    }
+
+    #[test]
+    fn flip_y() {
+        let tile = TileId::new(3, 3, 5).unwrap();
+        assert_eq!(tile.flip_y(), TileId::new(3, 3, 2).unwrap());
+        assert_eq!(tile.flip_y().flip_y(), tile);
+    }
+
+    #[test]
+    fn lon_lat() {
+        let (west, south, east, north) = TileId::new(1, 0, 0).unwrap().bounds();
+        assert_eq!((west, south, east), (-180.0, 0.0, 0.0));
+        assert!((north - 85.051_128_779_806_59).abs() < 1e-9);
+
+        assert_eq!(TileId::from_lon_lat(19.04, 47.5, 10), TileId::new(10, 566, 358));
+        assert_eq!(TileId::from_lon_lat(180.0, -90.0, 1), TileId::new(1, 1, 1));
+    }
+
+    #[test]
+    fn hilbert_index() {
+        let tiles = [(0, 0, 0), (1, 0, 0), (1, 0, 1), (1, 1, 1), (1, 1, 0), (2, 0, 0)];
+
+        for (index, (z, x, y)) in tiles.iter().enumerate() {
+            let tile = TileId::new(*z, *x, *y).unwrap();
+            assert_eq!(tile.hilbert_index(), index as u64);
+            assert_eq!(TileId::from_hilbert_index(index as u64), Some(tile));
+        }
+
+        let tile = TileId::new(20, 571_234, 367_890).unwrap();
+        assert_eq!(TileId::from_hilbert_index(tile.hilbert_index()), Some(tile));
+    }
+}
@@ -0,0 +1,167 @@
+use super::common::TileCoord;
+
+use super::read::{ring_area, Feature, Geometry};
+use super::union::Polygon;
+
+/// Drops exterior rings (together with their holes) and interior rings whose area is below `min_area` square
+/// tile units, as well as features without any polygon left. Other geometries are kept as they are.
+///
+/// With `reduce`, the area of the dropped exterior rings is accumulated over the features like tippecanoe's
+/// tiny polygon reduction does: whenever it reaches `min_area`, the ring that got it there is replaced by a
+/// square of `min_area` at its position instead of being dropped. Sparse areas of tiny buildings or lakes
+/// then keep roughly their share of coverage at low zooms.
+///
+/// `budget::Budget::with_min_polygon_area` does the same while encoding a tile.
+pub fn drop_tiny_polygons(features: Vec<Feature>, min_area: f64, reduce: bool) -> Vec<Feature> {
+    let mut filter = TinyPolygonFilter::new(min_area, reduce);
+
+    features
+        .into_iter()
+        .filter_map(|mut feature| {
+            feature.geometry = filter.apply(feature.geometry)?;
+            Some(feature)
+        })
+        .collect()
+}
+
+/// State of `drop_tiny_polygons` over a sequence of geometries, which carries the accumulated area.
+pub(crate) struct TinyPolygonFilter {
+    min_area: f64,
+    reduce: bool,
+    accumulated: f64,
+}
+
+impl TinyPolygonFilter {
+    pub(crate) fn new(min_area: f64, reduce: bool) -> TinyPolygonFilter {
+        TinyPolygonFilter {
+            min_area,
+            reduce,
+            accumulated: 0.0,
+        }
+    }
+
+    /// Removes the tiny polygons and holes of a geometry, `None` if no polygon is left.
+    pub(crate) fn apply(&mut self, geometry: Geometry) -> Option<Geometry> {
+        let polygons = match geometry {
+            Geometry::Polygon(exterior_ring, interior_rings) => vec![(exterior_ring, interior_rings)],
+            Geometry::MultiPolygon(polygons) => polygons,
+            geometry => return Some(geometry),
+        };
+
+        let mut kept: Vec<Polygon> = Vec::with_capacity(polygons.len());
+
+        for (exterior_ring, interior_rings) in polygons {
+            let exterior_area = area(&exterior_ring);
+
+            if exterior_area >= self.min_area {
+                let interior_rings = interior_rings
+                    .into_iter()
+                    .filter(|ring| area(ring) >= self.min_area)
+                    .collect();
+                kept.push((exterior_ring, interior_rings));
+            } else if self.reduce {
+                self.accumulated += exterior_area;
+
+                if self.accumulated >= self.min_area {
+                    self.accumulated -= self.min_area;
+                    kept.push((placeholder(&exterior_ring, self.min_area), Vec::new()));
+                }
+            }
+        }
+
+        match kept.len() {
+            0 => None,
+            1 => {
+                let (exterior_ring, interior_rings) = kept.remove(0);
+                Some(Geometry::Polygon(exterior_ring, interior_rings))
+            }
+            _ => Some(Geometry::MultiPolygon(kept)),
+        }
+    }
+}
+
+/// Absolute area of a ring in square tile units.
+fn area(ring: &[TileCoord]) -> f64 {
+    ring_area(ring).abs() as f64 / 2.0
+}
+
+/// A square of at least `area` centered on the bounding box of the ring.
+fn placeholder(ring: &[TileCoord], area: f64) -> Vec<TileCoord> {
+    let size = (area.sqrt().ceil() as i32).max(1);
+
+    let (min_x, min_y, max_x, max_y) = ring.iter().fold((i32::MAX, i32::MAX, i32::MIN, i32::MIN), |b, p| {
+        (b.0.min(p.0), b.1.min(p.1), b.2.max(p.0), b.3.max(p.1))
+    });
+    let (x, y) = (
+        (min_x + max_x - size).div_euclid(2),
+        (min_y + max_y - size).div_euclid(2),
+    );
+
+    vec![(x, y), (x + size, y), (x + size, y + size), (x, y + size)]
+}
+
+#[cfg(test)]
+mod tiny_test {
+    use super::*;
+
+    fn square(x: i32, y: i32, size: i32) -> Vec<TileCoord> {
+        vec![(x, y), (x + size, y), (x + size, y + size), (x, y + size)]
+    }
+
+    fn hole(x: i32, y: i32, size: i32) -> Vec<TileCoord> {
+        let mut ring = square(x, y, size);
+        ring.reverse();
+        ring
+    }
+
+    fn polygon(id: u64, exterior_ring: Vec<TileCoord>) -> Feature {
+        Feature {
+            id: Some(id),
+            tags: Vec::new(),
+            geometry: Geometry::Polygon(exterior_ring, Vec::new()),
+        }
+    }
+
+    #[test]
+    fn dropping() {
+        let features = vec![
+            Feature {
+                id: Some(1),
+                tags: Vec::new(),
+                geometry: Geometry::MultiPolygon(vec![
+                    (square(0, 0, 100), vec![hole(10, 10, 2), hole(50, 50, 20)]),
+                    (square(200, 200, 3), Vec::new()),
+                ]),
+            },
+            polygon(2, square(300, 300, 4)),
+            Feature {
+                id: Some(3),
+                tags: Vec::new(),
+                geometry: Geometry::Line(vec![(0, 0), (1, 1)]),
+            },
+        ];
+
+        let kept = drop_tiny_polygons(features, 16.0, false);
+        assert_eq!(kept.len(), 3);
+        assert_eq!(
+            kept[0].geometry,
+            Geometry::Polygon(square(0, 0, 100), vec![hole(50, 50, 20)])
+        );
+        assert_eq!(kept[1].geometry, Geometry::Polygon(square(300, 300, 4), Vec::new()));
+
+        let kept = drop_tiny_polygons(kept, 17.0, false);
+        assert_eq!(kept.iter().map(|f| f.id.unwrap()).collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    #[test]
+    fn reduction() {
+        // Ten polygons of 9 square units each add up to two placeholders of 36 square units
+        let features: Vec<Feature> = (0..10)
+            .map(|idx| polygon(idx + 1, square(idx as i32 * 10, 0, 3)))
+            .collect();
+
+        let kept = drop_tiny_polygons(features, 36.0, true);
+        assert_eq!(kept.iter().map(|f| f.id.unwrap()).collect::<Vec<_>>(), vec![4, 8]);
+        assert_eq!(kept[0].geometry, Geometry::Polygon(square(28, -2, 6), Vec::new()));
+    }
+}
@@ -0,0 +1,328 @@
+use super::common::TileCoord;
+
+use super::quantize::{quantize_line, quantize_points, quantize_polygons, PointF64, PolygonF64};
+
+use super::read::Geometry;
+use super::tile::TileId;
+use super::union::Polygon;
+
+type PointsF64<'a> = &'a [PointF64];
+
+/// Counterpart of `write::Geometry` in floating point coordinates, see `TileTransform` for their space.
+pub enum GeometryF64<'a> {
+    Point(PointF64),
+    MultiPoint(PointsF64<'a>),
+    Line(PointsF64<'a>),
+    MultiLine(&'a [PointsF64<'a>]),
+    Polygon(PointsF64<'a>, &'a [PointsF64<'a>]),
+    MultiPolygon(&'a [(PointsF64<'a>, &'a [PointsF64<'a>])]),
+}
+
+/// Half the circumference of the earth in Web Mercator (EPSG:3857) metres.
+const MERCATOR_HALF_SIZE: f64 = 20_037_508.342_789_244;
+
+/// Maps geometries of one tile from a projected space to tile coordinates, clips them to the tile and its
+/// buffer and snaps them to the integer grid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileTransform {
+    /// Input coordinates of the north-west corner of the tile.
+    origin: PointF64,
+    /// Tile units per input unit, negative along y for spaces where y grows northwards.
+    scale: PointF64,
+    pub extent: u32,
+    /// Tile units kept around the tile when clipping.
+    pub buffer: u32,
+    /// Clipping box replacing the tile and its buffer, as `(min_x, min_y, max_x, max_y)` in tile units.
+    clip_box: Option<(f64, f64, f64, f64)>,
+}
+
+impl TileTransform {
+    /// Transform for Web Mercator coordinates normalized to the unit square with the origin in the
+    /// north-west, like `TileId` uses. The buffer defaults to 64 tile units.
+    pub fn unit(tile: TileId, extent: u32) -> TileTransform {
+        let size = (1u64 << tile.z()) as f64;
+        let scale = extent as f64 * size;
+
+        TileTransform {
+            origin: (tile.x() as f64 / size, tile.y() as f64 / size),
+            scale: (scale, scale),
+            extent,
+            buffer: 64,
+            clip_box: None,
+        }
+    }
+
+    /// Transform for Web Mercator (EPSG:3857) coordinates in metres. The buffer defaults to 64 tile units.
+    pub fn mercator(tile: TileId, extent: u32) -> TileTransform {
+        let size = 2.0 * MERCATOR_HALF_SIZE / (1u64 << tile.z()) as f64;
+        let scale = extent as f64 / size;
+
+        TileTransform {
+            origin: (
+                -MERCATOR_HALF_SIZE + tile.x() as f64 * size,
+                MERCATOR_HALF_SIZE - tile.y() as f64 * size,
+            ),
+            scale: (scale, -scale),
+            extent,
+            buffer: 64,
+            clip_box: None,
+        }
+    }
+
+    pub fn with_buffer(mut self, buffer: u32) -> TileTransform {
+        self.buffer = buffer;
+        self
+    }
+
+    /// Clips to a box other than the tile and its buffer, e.g. to a quarter of the tile.
+    pub(crate) fn with_clip_box(mut self, clip_box: (f64, f64, f64, f64)) -> TileTransform {
+        self.clip_box = Some(clip_box);
+        self
+    }
+
+    /// Position of a point in tile units, before clipping and snapping.
+    pub fn to_tile(&self, point: PointF64) -> PointF64 {
+        (
+            (point.0 - self.origin.0) * self.scale.0,
+            (point.1 - self.origin.1) * self.scale.1,
+        )
+    }
+
+    /// Converts a geometry to tile coordinates ready to be encoded, `None` if nothing of it is left within
+    /// the tile and its buffer.
+    ///
+    /// Lines are cut where they leave the clipping box, polygon rings are clipped to it, and the result is
+    /// quantized with the `quantize` module, so polygons are valid and correctly oriented whatever the
+    /// orientation of the input. Polygons that cannot be made valid on the grid are dropped.
+    pub fn transform(&self, geometry: &GeometryF64) -> Option<Geometry> {
+        let points = |points: &[PointF64]| -> Vec<PointF64> {
+            points
+                .iter()
+                .map(|point| self.to_tile(*point))
+                .filter(|point| self.contains(*point))
+                .collect()
+        };
+        let lines = |lines: &mut dyn Iterator<Item = &[PointF64]>| -> Vec<Vec<_>> {
+            lines
+                .flat_map(|line| self.clip_line(line))
+                .filter_map(|line| quantize_line(&line))
+                .collect()
+        };
+        let polygons = |polygons: &mut dyn Iterator<Item = (&[PointF64], &[PointsF64])>| {
+            let polygons: Vec<PolygonF64> = polygons
+                .filter_map(|(exterior_ring, interior_rings)| {
+                    let exterior_ring = self.clip_ring(exterior_ring)?;
+                    let interior_rings = interior_rings.iter().filter_map(|ring| self.clip_ring(ring)).collect();
+                    Some((exterior_ring, interior_rings))
+                })
+                .collect();
+            quantize_polygons(&polygons)
+        };
+
+        let geometry = match geometry {
+            GeometryF64::Point(point) => {
+                let mut points = quantize_points(&points(&[*point]));
+                Geometry::Point(points.pop()?)
+            }
+            GeometryF64::MultiPoint(multi_points) => {
+                let points = quantize_points(&points(multi_points));
+                match points.len() {
+                    0 => return None,
+                    1 => Geometry::Point(points[0]),
+                    _ => Geometry::MultiPoint(points),
+                }
+            }
+            GeometryF64::Line(line) => multi_line(lines(&mut std::iter::once(*line)))?,
+            GeometryF64::MultiLine(multi_lines) => multi_line(lines(&mut multi_lines.iter().copied()))?,
+            GeometryF64::Polygon(exterior_ring, interior_rings) => {
+                multi_polygon(polygons(&mut std::iter::once((*exterior_ring, *interior_rings))))?
+            }
+            GeometryF64::MultiPolygon(multi_polygons) => multi_polygon(polygons(&mut multi_polygons.iter().copied()))?,
+        };
+
+        Some(geometry)
+    }
+
+    fn bounds(&self) -> (f64, f64, f64, f64) {
+        let (min, max) = (-(self.buffer as f64), self.extent as f64 + self.buffer as f64);
+        self.clip_box.unwrap_or((min, min, max, max))
+    }
+
+    fn contains(&self, point: PointF64) -> bool {
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+        point.0 >= min_x && point.0 <= max_x && point.1 >= min_y && point.1 <= max_y
+    }
+
+    /// Parts of a line within the clipping box, in tile units (Liang-Barsky).
+    fn clip_line(&self, line: &[PointF64]) -> Vec<Vec<PointF64>> {
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+        let mut parts = Vec::new();
+        let mut part: Vec<PointF64> = Vec::new();
+
+        for segment in line.windows(2) {
+            let (a, b) = (self.to_tile(segment[0]), self.to_tile(segment[1]));
+            let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+
+            let mut range = Some((0.0f64, 1.0f64));
+            for (p, q) in [
+                (-dx, a.0 - min_x),
+                (dx, max_x - a.0),
+                (-dy, a.1 - min_y),
+                (dy, max_y - a.1),
+            ]
+            .iter()
+            {
+                range = range.and_then(|(t0, t1)| {
+                    if *p == 0.0 {
+                        if *q < 0.0 {
+                            None
+                        } else {
+                            Some((t0, t1))
+                        }
+                    } else {
+                        let t = q / p;
+                        let (t0, t1) = if *p < 0.0 { (t0.max(t), t1) } else { (t0, t1.min(t)) };
+                        if t0 > t1 {
+                            None
+                        } else {
+                            Some((t0, t1))
+                        }
+                    }
+                });
+            }
+
+            let (t0, t1) = match range {
+                Some(range) => range,
+                None => {
+                    parts.push(std::mem::take(&mut part));
+                    continue;
+                }
+            };
+
+            if part.is_empty() || t0 > 0.0 {
+                parts.push(std::mem::take(&mut part));
+                part.push((a.0 + t0 * dx, a.1 + t0 * dy));
+            }
+            part.push((a.0 + t1 * dx, a.1 + t1 * dy));
+
+            if t1 < 1.0 {
+                parts.push(std::mem::take(&mut part));
+            }
+        }
+        parts.push(part);
+
+        parts.retain(|part| part.len() >= 2);
+        parts
+    }
+
+    /// A ring clipped to the clipping box, in tile units (Sutherland-Hodgman). Parts of the ring outside of
+    /// the box are replaced by its sides.
+    fn clip_ring(&self, ring: &[PointF64]) -> Option<Vec<PointF64>> {
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+        let mut ring: Vec<PointF64> = ring.iter().map(|point| self.to_tile(*point)).collect();
+
+        let edges: [(usize, f64, bool); 4] = [(0, min_x, false), (0, max_x, true), (1, min_y, false), (1, max_y, true)];
+
+        for (axis, limit, upper) in edges.iter() {
+            let coord = |point: &PointF64| if *axis == 0 { point.0 } else { point.1 };
+            let inside = |point: &PointF64| {
+                if *upper {
+                    coord(point) <= *limit
+                } else {
+                    coord(point) >= *limit
+                }
+            };
+
+            let mut clipped = Vec::with_capacity(ring.len());
+
+            for idx in 0..ring.len() {
+                let (a, b) = (ring[idx], ring[(idx + 1) % ring.len()]);
+
+                if inside(&a) {
+                    clipped.push(a);
+                }
+                if inside(&a) != inside(&b) {
+                    let t = (limit - coord(&a)) / (coord(&b) - coord(&a));
+                    clipped.push((a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1)));
+                }
+            }
+
+            ring = clipped;
+        }
+
+        if ring.len() >= 3 {
+            Some(ring)
+        } else {
+            None
+        }
+    }
+}
+
+fn multi_line(mut lines: Vec<Vec<TileCoord>>) -> Option<Geometry> {
+    match lines.len() {
+        0 => None,
+        1 => Some(Geometry::Line(lines.remove(0))),
+        _ => Some(Geometry::MultiLine(lines)),
+    }
+}
+
+fn multi_polygon(mut polygons: Vec<Polygon>) -> Option<Geometry> {
+    match polygons.len() {
+        0 => None,
+        1 => {
+            let (exterior_ring, interior_rings) = polygons.remove(0);
+            Some(Geometry::Polygon(exterior_ring, interior_rings))
+        }
+        _ => Some(Geometry::MultiPolygon(polygons)),
+    }
+}
+
+#[cfg(test)]
+mod transform_test {
+    use super::*;
+    use crate::write::EncodableGeometry;
+
+    #[test]
+    fn transforms() {
+        let tile = TileId::new(1, 1, 0).unwrap();
+
+        let unit = TileTransform::unit(tile, 4096);
+        assert_eq!(unit.to_tile((0.5, 0.0)), (0.0, 0.0));
+        assert_eq!(unit.to_tile((0.75, 0.25)), (2048.0, 2048.0));
+
+        let mercator = TileTransform::mercator(tile, 4096);
+        assert_eq!(mercator.to_tile((0.0, MERCATOR_HALF_SIZE)), (0.0, 0.0));
+        let (x, y) = mercator.to_tile((MERCATOR_HALF_SIZE, 0.0));
+        assert!((x - 4096.0).abs() < 1e-6 && (y - 4096.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn clipping() {
+        let transform = TileTransform::unit(TileId::new(0, 0, 0).unwrap(), 100).with_buffer(10);
+
+        assert_eq!(
+            transform.transform(&GeometryF64::MultiPoint(&[(0.5, 0.5), (1.5, 0.5)])),
+            Some(Geometry::Point((50, 50)))
+        );
+        assert_eq!(transform.transform(&GeometryF64::Point((2.0, 2.0))), None);
+
+        // A line leaving and entering the tile again
+        let line = [(0.5, 0.5), (2.0, 0.5), (2.0, 0.6), (0.5, 0.6)];
+        assert_eq!(
+            transform.transform(&GeometryF64::Line(&line)),
+            Some(Geometry::MultiLine(vec![
+                vec![(50, 50), (110, 50)],
+                vec![(110, 60), (50, 60)]
+            ]))
+        );
+
+        // A polygon larger than the tile, given clockwise
+        let ring = [(-1.0, -1.0), (-1.0, 0.5), (0.5, 0.5), (0.5, -1.0)];
+        let polygon = transform.transform(&GeometryF64::Polygon(&ring, &[])).unwrap();
+        assert_eq!(
+            polygon,
+            Geometry::Polygon(vec![(-10, -10), (50, -10), (50, 50), (-10, 50)], vec![])
+        );
+        assert!(polygon.encode().is_ok());
+    }
+}
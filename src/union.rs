@@ -0,0 +1,712 @@
+use super::common::{TileCoord, Value, ValueKey};
+
+use super::read::{ring_area, Feature, Geometry};
+
+use std::collections::HashMap;
+
+/// An exterior ring with its interior rings, all open and oriented like in tiles.
+pub type Polygon = (Vec<TileCoord>, Vec<Vec<TileCoord>>);
+
+/// Distance of the points sampled on both sides of an edge to decide whether it is on the boundary.
+const SAMPLE_OFFSET: f64 = 1e-3;
+
+/// Upper bound of the rounds splitting edges at the crossings created by rounding earlier crossings.
+const MAX_NODING_ROUNDS: usize = 32;
+
+fn cross(o: TileCoord, a: TileCoord, b: TileCoord) -> i64 {
+    (a.0 as i64 - o.0 as i64) * (b.1 as i64 - o.1 as i64) - (a.1 as i64 - o.1 as i64) * (b.0 as i64 - o.0 as i64)
+}
+
+/// Whether `p`, known to be collinear with the segment, lies strictly between its end points.
+fn strictly_inside(p: TileCoord, a: TileCoord, b: TileCoord) -> bool {
+    p != a && p != b && p.0 >= a.0.min(b.0) && p.0 <= a.0.max(b.0) && p.1 >= a.1.min(b.1) && p.1 <= a.1.max(b.1)
+}
+
+/// Collects the points at which the segments `a`-`b` and `c`-`d` have to be split so that they only meet at
+/// end points. Proper crossings are rounded to the integer grid.
+fn split_points(a: TileCoord, b: TileCoord, c: TileCoord, d: TileCoord) -> (Vec<TileCoord>, Vec<TileCoord>) {
+    let (o1, o2, o3, o4) = (cross(a, b, c), cross(a, b, d), cross(c, d, a), cross(c, d, b));
+    let (mut first, mut second) = (Vec::new(), Vec::new());
+
+    if o1.signum() * o2.signum() > 0 || o3.signum() * o4.signum() > 0 {
+        return (first, second);
+    }
+
+    if o1 != 0 && o2 != 0 && o3 != 0 && o4 != 0 {
+        let t = o3 as f64 / (o3 - o4) as f64;
+        let point = (
+            (a.0 as f64 + t * (b.0 - a.0) as f64).round() as i32,
+            (a.1 as f64 + t * (b.1 - a.1) as f64).round() as i32,
+        );
+        if point != a && point != b {
+            first.push(point);
+        }
+        if point != c && point != d {
+            second.push(point);
+        }
+        return (first, second);
+    }
+
+    // Touching or collinear segments
+    for (point, orientation) in [(c, o1), (d, o2)].iter() {
+        if *orientation == 0 && strictly_inside(*point, a, b) {
+            first.push(*point);
+        }
+    }
+    for (point, orientation) in [(a, o3), (b, o4)].iter() {
+        if *orientation == 0 && strictly_inside(*point, c, d) {
+            second.push(*point);
+        }
+    }
+
+    (first, second)
+}
+
+fn ring_edges(ring: &[TileCoord]) -> impl Iterator<Item = (TileCoord, TileCoord)> + '_ {
+    (0..ring.len()).map(move |idx| (ring[idx], ring[(idx + 1) % ring.len()]))
+}
+
+/// Pairs of edges whose bounding boxes intersect, found by sweeping along the x axis.
+fn close_pairs(edges: &[(TileCoord, TileCoord)]) -> Vec<(usize, usize)> {
+    let mut order: Vec<usize> = (0..edges.len()).collect();
+    order.sort_by_key(|idx| edges[*idx].0 .0.min(edges[*idx].1 .0));
+
+    let mut pairs = Vec::new();
+
+    for (position, first) in order.iter().enumerate() {
+        let (a, b) = edges[*first];
+
+        for second in &order[position + 1..] {
+            let (c, d) = edges[*second];
+
+            if c.0.min(d.0) > a.0.max(b.0) {
+                break;
+            }
+            if c.1.min(d.1) <= a.1.max(b.1) && c.1.max(d.1) >= a.1.min(b.1) {
+                pairs.push((*first, *second));
+            }
+        }
+    }
+
+    pairs
+}
+
+/// Even-odd test of a point against all rings of a polygon.
+fn inside(point: (f64, f64), rings: &[Vec<TileCoord>]) -> bool {
+    let mut inside = false;
+
+    for ring in rings {
+        for idx in 0..ring.len() {
+            let (a, b) = (ring[idx], ring[(idx + 1) % ring.len()]);
+            let (ax, ay, bx, by) = (a.0 as f64, a.1 as f64, b.0 as f64, b.1 as f64);
+
+            if (ay > point.1) != (by > point.1) && point.0 < ax + (point.1 - ay) * (bx - ax) / (by - ay) {
+                inside = !inside;
+            }
+        }
+    }
+
+    inside
+}
+
+fn remove_collinear(ring: Vec<TileCoord>) -> Vec<TileCoord> {
+    let mut ring = ring;
+
+    loop {
+        let len = ring.len();
+        if len < 3 {
+            return ring;
+        }
+
+        let keep: Vec<bool> = (0..len)
+            .map(|idx| cross(ring[(idx + len - 1) % len], ring[idx], ring[(idx + 1) % len]) != 0)
+            .collect();
+
+        if keep.iter().all(|keep| *keep) {
+            return ring;
+        }
+
+        ring = ring
+            .into_iter()
+            .zip(keep)
+            .filter(|(_, keep)| *keep)
+            .map(|(p, _)| p)
+            .collect();
+    }
+}
+
+/// Splits the edges of the rings wherever they cross or touch another edge. Returns `false` if no edge had
+/// to be split.
+fn split_rings_once(rings: &mut Vec<(usize, Vec<TileCoord>)>) -> bool {
+    let edges: Vec<(TileCoord, TileCoord)> = rings.iter().flat_map(|(_, ring)| ring_edges(ring)).collect();
+
+    let mut splits: Vec<Vec<TileCoord>> = vec![Vec::new(); edges.len()];
+
+    for (first, second) in close_pairs(&edges) {
+        let ((a, b), (c, d)) = (edges[first], edges[second]);
+        let (first_points, second_points) = split_points(a, b, c, d);
+        splits[first].extend(first_points);
+        splits[second].extend(second_points);
+    }
+
+    if splits.iter().all(Vec::is_empty) {
+        return false;
+    }
+
+    let mut split_rings = Vec::new();
+    let mut edge_idx = 0;
+
+    for (polygon_idx, ring) in rings.iter() {
+        let mut split_ring = Vec::new();
+
+        for a in ring {
+            let a = *a;
+            let mut points = std::mem::take(&mut splits[edge_idx]);
+            points.sort_by_key(|p| (p.0 as i64 - a.0 as i64).pow(2) + (p.1 as i64 - a.1 as i64).pow(2));
+
+            split_ring.push(a);
+            split_ring.extend(points);
+            edge_idx += 1;
+        }
+
+        split_ring.dedup();
+        while split_ring.len() > 1 && split_ring.first() == split_ring.last() {
+            split_ring.pop();
+        }
+
+        if split_ring.len() >= 3 {
+            split_rings.push((*polygon_idx, split_ring));
+        }
+    }
+
+    *rings = split_rings;
+
+    true
+}
+
+/// Computes the union of polygons, merging overlapping and adjacent polygons and removing the seams between
+/// them. The result is sorted by the position of the exterior rings.
+///
+/// Edges are split wherever they cross or touch, and every resulting edge is kept if it has the union on
+/// exactly one side. The kept edges are then linked into rings, taking the leftmost turn at vertices shared
+/// by several rings so that polygons touching at a point stay separate. Crossings are rounded to the integer
+/// grid, which may shift edges by half a unit. Rounding can make edges cross again, so splitting is repeated
+/// until no edge crosses another one. Splitting gives up after a fixed number of rounds, in which case the
+/// result may not be valid, so callers needing valid polygons check it with `is_valid`.
+pub fn union(polygons: &[Polygon]) -> Vec<Polygon> {
+    let mut rings: Vec<(usize, Vec<TileCoord>)> = Vec::new();
+    for (polygon_idx, (exterior_ring, interior_rings)) in polygons.iter().enumerate() {
+        for ring in std::iter::once(exterior_ring).chain(interior_rings) {
+            if ring.len() >= 3 {
+                rings.push((polygon_idx, ring.clone()));
+            }
+        }
+    }
+
+    for _ in 0..MAX_NODING_ROUNDS {
+        if !split_rings_once(&mut rings) {
+            break;
+        }
+    }
+
+    // Rings of every polygon with the split points inserted
+    let mut split_rings: Vec<Vec<Vec<TileCoord>>> = vec![Vec::new(); polygons.len()];
+    for (polygon_idx, ring) in rings {
+        split_rings[polygon_idx].push(ring);
+    }
+
+    let bounds: Vec<Option<(i32, i32, i32, i32)>> = split_rings
+        .iter()
+        .map(|rings| {
+            let mut points = rings.iter().flatten();
+            let first = points.next()?;
+            Some(points.fold((first.0, first.1, first.0, first.1), |b, p| {
+                (b.0.min(p.0), b.1.min(p.1), b.2.max(p.0), b.3.max(p.1))
+            }))
+        })
+        .collect();
+
+    let inside_union = |point: (f64, f64)| {
+        split_rings.iter().zip(&bounds).any(|(rings, bounds)| match bounds {
+            Some((min_x, min_y, max_x, max_y)) => {
+                point.0 >= *min_x as f64
+                    && point.0 <= *max_x as f64
+                    && point.1 >= *min_y as f64
+                    && point.1 <= *max_y as f64
+                    && inside(point, rings)
+            }
+            None => false,
+        })
+    };
+
+    let mut segments: Vec<(TileCoord, TileCoord)> = split_rings
+        .iter()
+        .flatten()
+        .flat_map(|ring| ring_edges(ring))
+        .map(|(a, b)| if a < b { (a, b) } else { (b, a) })
+        .collect();
+    segments.sort_unstable();
+    segments.dedup();
+
+    // Boundary edges directed to have the union on their left
+    let mut boundary: Vec<(TileCoord, TileCoord)> = Vec::new();
+
+    for (a, b) in segments {
+        let (dx, dy) = ((b.0 - a.0) as f64, (b.1 - a.1) as f64);
+        let length = (dx * dx + dy * dy).sqrt();
+        let (nx, ny) = (-dy / length * SAMPLE_OFFSET, dx / length * SAMPLE_OFFSET);
+        let middle = ((a.0 as f64 + b.0 as f64) / 2.0, (a.1 as f64 + b.1 as f64) / 2.0);
+
+        let left = inside_union((middle.0 + nx, middle.1 + ny));
+        let right = inside_union((middle.0 - nx, middle.1 - ny));
+
+        match (left, right) {
+            (true, false) => boundary.push((a, b)),
+            (false, true) => boundary.push((b, a)),
+            _ => {}
+        }
+    }
+
+    let mut outgoing: HashMap<TileCoord, Vec<usize>> = HashMap::new();
+    for (idx, (a, _)) in boundary.iter().enumerate() {
+        outgoing.entry(*a).or_default().push(idx);
+    }
+
+    let mut used = vec![false; boundary.len()];
+    let mut exterior_rings = Vec::new();
+    let mut interior_rings = Vec::new();
+
+    for start in 0..boundary.len() {
+        if used[start] {
+            continue;
+        }
+
+        let mut ring = Vec::new();
+        let mut current = start;
+        let mut closed = false;
+
+        loop {
+            used[current] = true;
+            let (from, to) = boundary[current];
+            ring.push(from);
+
+            let incoming = ((to.0 - from.0) as f64, (to.1 - from.1) as f64);
+
+            let next = outgoing
+                .get(&to)
+                .into_iter()
+                .flatten()
+                .filter(|idx| !used[**idx] || **idx == start)
+                .max_by(|a, b| {
+                    let turn = |idx: usize| {
+                        let (from, to) = boundary[idx];
+                        let out = ((to.0 - from.0) as f64, (to.1 - from.1) as f64);
+                        (incoming.0 * out.1 - incoming.1 * out.0).atan2(incoming.0 * out.0 + incoming.1 * out.1)
+                    };
+                    turn(**a).total_cmp(&turn(**b))
+                });
+
+            match next {
+                Some(idx) if *idx == start => {
+                    closed = true;
+                    break;
+                }
+                Some(idx) => current = *idx,
+                None => break,
+            }
+        }
+
+        if !closed {
+            continue;
+        }
+
+        let ring = remove_collinear(ring);
+        if ring.len() < 3 {
+            continue;
+        }
+
+        match ring_area(&ring) {
+            area if area > 0 => exterior_rings.push((area, ring)),
+            area if area < 0 => interior_rings.push(ring),
+            _ => {}
+        }
+    }
+
+    let mut result: Vec<(i64, Polygon)> = exterior_rings
+        .into_iter()
+        .map(|(area, ring)| (area, (ring, Vec::new())))
+        .collect();
+
+    for ring in interior_rings {
+        // A point just inside the union next to the hole
+        let (a, b) = (ring[0], ring[1]);
+        let (dx, dy) = ((b.0 - a.0) as f64, (b.1 - a.1) as f64);
+        let length = (dx * dx + dy * dy).sqrt();
+        let point = (
+            (a.0 as f64 + b.0 as f64) / 2.0 - dy / length * SAMPLE_OFFSET,
+            (a.1 as f64 + b.1 as f64) / 2.0 + dx / length * SAMPLE_OFFSET,
+        );
+
+        let container = result
+            .iter_mut()
+            .filter(|(_, (exterior_ring, _))| inside(point, std::slice::from_ref(exterior_ring)))
+            .min_by_key(|(area, _)| *area);
+
+        if let Some((_, (_, holes))) = container {
+            holes.push(ring);
+        }
+    }
+
+    let mut result: Vec<Polygon> = result.into_iter().map(|(_, polygon)| polygon).collect();
+    for (exterior_ring, interior_rings) in result.iter_mut() {
+        rotate_to_min(exterior_ring);
+        interior_rings.iter_mut().for_each(|ring| rotate_to_min(ring));
+        interior_rings.sort();
+    }
+    result.sort();
+
+    result
+}
+
+/// Whether polygons are valid in the OGC sense and can be encoded as one multipolygon: rings have at least
+/// three distinct points, exterior rings are positive and interior rings negative (see `encode_ring`), holes
+/// lie inside their exterior ring, no edges cross or overlap and no polygon lies inside another one. Rings may
+/// touch at single points.
+pub fn is_valid(polygons: &[Polygon]) -> bool {
+    for (exterior_ring, interior_rings) in polygons {
+        if exterior_ring.len() < 3 || ring_area(exterior_ring) <= 0 {
+            return false;
+        }
+
+        for ring in interior_rings {
+            if ring.len() < 3 || ring_area(ring) >= 0 {
+                return false;
+            }
+        }
+    }
+
+    let rings: Vec<&Vec<TileCoord>> = polygons
+        .iter()
+        .flat_map(|(exterior_ring, interior_rings)| std::iter::once(exterior_ring).chain(interior_rings))
+        .collect();
+
+    if rings.iter().flat_map(|ring| ring_edges(ring)).any(|(a, b)| a == b) {
+        return false;
+    }
+
+    let edges: Vec<(TileCoord, TileCoord)> = rings.iter().flat_map(|ring| ring_edges(ring)).collect();
+
+    for (first, second) in close_pairs(&edges) {
+        let ((a, b), (c, d)) = (edges[first], edges[second]);
+        let (o1, o2, o3, o4) = (cross(a, b, c), cross(a, b, d), cross(c, d, a), cross(c, d, b));
+
+        let crossing = o1.signum() * o2.signum() < 0 && o3.signum() * o4.signum() < 0;
+        let overlapping = o1 == 0
+            && o2 == 0
+            && (strictly_inside(c, a, b)
+                || strictly_inside(d, a, b)
+                || strictly_inside(a, c, d)
+                || strictly_inside(b, c, d)
+                || (a, b) == (c, d)
+                || (a, b) == (d, c));
+
+        if crossing || overlapping {
+            return false;
+        }
+    }
+
+    // Without crossing edges, the middle of any edge tells on which side of other rings a ring lies
+    let middle = |ring: &[TileCoord]| {
+        let (a, b) = (ring[0], ring[1]);
+        ((a.0 as f64 + b.0 as f64) / 2.0, (a.1 as f64 + b.1 as f64) / 2.0)
+    };
+
+    for (polygon_idx, (exterior_ring, interior_rings)) in polygons.iter().enumerate() {
+        for ring in interior_rings {
+            if !inside(middle(ring), std::slice::from_ref(exterior_ring)) {
+                return false;
+            }
+        }
+
+        for (other_idx, (other_exterior_ring, other_interior_rings)) in polygons.iter().enumerate() {
+            if other_idx != polygon_idx
+                && inside(middle(exterior_ring), std::slice::from_ref(other_exterior_ring))
+                && !other_interior_rings
+                    .iter()
+                    .any(|ring| inside(middle(exterior_ring), std::slice::from_ref(ring)))
+            {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// Unions the polygon features sharing the value of `key` into one feature per value, in the place of the
+/// first of them. Merged features keep the tags common to all of them and lose their ids.
+///
+/// Features without the key and other geometries are kept as they are. Polygons of one value that are not
+/// connected end up in a single multipolygon. Features whose union is not valid (see `is_valid`) are kept
+/// as they are too.
+pub fn dissolve(features: Vec<Feature>, key: &str) -> Vec<Feature> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_values: HashMap<ValueKey, usize> = HashMap::new();
+    let mut feature_groups = vec![None; features.len()];
+
+    for (idx, feature) in features.iter().enumerate() {
+        let value = match (&feature.geometry, feature.tag(key)) {
+            (Geometry::Polygon(..), Some(value)) | (Geometry::MultiPolygon(_), Some(value)) => value,
+            _ => continue,
+        };
+
+        let group = *group_values.entry(value.key()).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group].push(idx);
+        feature_groups[idx] = Some(group);
+    }
+
+    let mut features: Vec<Option<Feature>> = features.into_iter().map(Some).collect();
+    let mut dissolved: Vec<Option<Feature>> = vec![None; groups.len()];
+
+    for (group, members) in groups.iter().enumerate() {
+        if members.len() < 2 {
+            continue;
+        }
+
+        let mut polygons = Vec::new();
+
+        for idx in members {
+            match &features[*idx].as_ref().unwrap().geometry {
+                Geometry::Polygon(exterior_ring, interior_rings) => {
+                    polygons.push((exterior_ring.clone(), interior_rings.clone()))
+                }
+                Geometry::MultiPolygon(more_polygons) => polygons.extend(more_polygons.iter().cloned()),
+                _ => unreachable!(),
+            }
+        }
+
+        let mut polygons = union(&polygons);
+        if !is_valid(&polygons) {
+            continue;
+        }
+
+        let mut tags: Option<Vec<(String, Value)>> = None;
+
+        for idx in members {
+            let feature = features[*idx].take().unwrap();
+
+            tags = Some(match tags {
+                Some(tags) => tags.into_iter().filter(|tag| feature.tags.contains(tag)).collect(),
+                None => feature.tags,
+            });
+        }
+
+        let geometry = match polygons.len() {
+            0 => continue,
+            1 => {
+                let (exterior_ring, interior_rings) = polygons.remove(0);
+                Geometry::Polygon(exterior_ring, interior_rings)
+            }
+            _ => Geometry::MultiPolygon(polygons),
+        };
+
+        dissolved[group] = Some(Feature {
+            id: None,
+            tags: tags.unwrap_or_default(),
+            geometry,
+        });
+    }
+
+    features
+        .iter_mut()
+        .zip(feature_groups)
+        .filter_map(|(feature, group)| match (feature.take(), group) {
+            (Some(feature), _) => Some(feature),
+            (None, Some(group)) => dissolved[group].take(),
+            (None, None) => None,
+        })
+        .collect()
+}
+
+/// Starts a ring at its smallest point, for a deterministic output.
+fn rotate_to_min(ring: &mut [TileCoord]) {
+    if let Some(min_idx) = (0..ring.len()).min_by_key(|idx| ring[*idx]) {
+        ring.rotate_left(min_idx);
+    }
+}
+
+#[cfg(test)]
+mod union_test {
+    use super::*;
+
+    fn square(x: i32, y: i32, size: i32) -> Vec<TileCoord> {
+        vec![(x, y), (x + size, y), (x + size, y + size), (x, y + size)]
+    }
+
+    #[test]
+    fn adjacent_and_overlapping() {
+        // Two squares sharing an edge, with an extra vertex on the shared edge of the second one
+        let first = (square(0, 0, 10), vec![]);
+        let second = (vec![(10, 0), (20, 0), (20, 10), (10, 10), (10, 5)], vec![]);
+        assert_eq!(
+            union(&[first.clone(), second]),
+            vec![(vec![(0, 0), (20, 0), (20, 10), (0, 10)], vec![])]
+        );
+
+        // Overlapping squares
+        let second = (square(5, 5, 10), vec![]);
+        let merged = union(&[first.clone(), second]);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(ring_area(&merged[0].0), 2 * (100 + 100 - 25));
+
+        // Disjoint squares and squares touching at a corner stay separate
+        let second = (square(10, 10, 10), vec![]);
+        let third = (square(50, 50, 10), vec![]);
+        assert_eq!(union(&[first, second, third]).len(), 3);
+    }
+
+    #[test]
+    fn holes() {
+        let hole = vec![(2, 2), (2, 8), (8, 8), (8, 2)];
+        let with_hole = (square(0, 0, 10), vec![hole.clone()]);
+
+        assert_eq!(
+            union(std::slice::from_ref(&with_hole)),
+            vec![(square(0, 0, 10), vec![hole])]
+        );
+
+        // Filling half the hole shrinks it
+        let filler = (square(2, 2, 3), vec![]);
+        let merged = union(&[with_hole.clone(), filler]);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].1.len(), 1);
+        assert_eq!(ring_area(&merged[0].1[0]), -2 * (36 - 9));
+
+        // Ring of four squares around an empty center creates a hole
+        let frame = [
+            (vec![(0, 0), (30, 0), (30, 10), (0, 10)], vec![]),
+            (vec![(0, 20), (30, 20), (30, 30), (0, 30)], vec![]),
+            (square(0, 10, 10), vec![]),
+            (square(20, 10, 10), vec![]),
+        ];
+        let merged = union(&frame);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].0, vec![(0, 0), (30, 0), (30, 30), (0, 30)]);
+        assert_eq!(merged[0].1, vec![vec![(10, 10), (10, 20), (20, 20), (20, 10)]]);
+    }
+
+    #[test]
+    fn validity() {
+        assert!(is_valid(&[(
+            square(0, 0, 10),
+            vec![vec![(2, 2), (2, 8), (8, 8), (8, 2)]]
+        )]));
+        assert!(is_valid(&[(square(0, 0, 10), vec![]), (square(10, 10, 10), vec![])]));
+
+        // Wrong winding
+        assert!(!is_valid(&[(vec![(0, 0), (0, 10), (10, 10), (10, 0)], vec![])]));
+        // Bow tie
+        assert!(!is_valid(&[(vec![(0, 0), (10, 10), (10, 0), (0, 10)], vec![])]));
+        // Shared edge
+        assert!(!is_valid(&[(square(0, 0, 10), vec![]), (square(10, 0, 10), vec![])]));
+        // Nested polygons and holes outside of their polygon
+        assert!(!is_valid(&[(square(0, 0, 10), vec![]), (square(2, 2, 2), vec![])]));
+        assert!(!is_valid(&[(
+            square(0, 0, 10),
+            vec![vec![(20, 20), (20, 30), (30, 30), (30, 20)]]
+        )]));
+    }
+
+    #[test]
+    fn rounded_crossings() {
+        // Rounding the crossings of these triangles moves edges across each other
+        let triangles = [
+            (vec![(3, 10), (14, 28), (4, 38)], vec![]),
+            (vec![(30, 37), (1, 11), (34, 39)], vec![]),
+            (vec![(15, 35), (3, 39), (16, 26)], vec![]),
+        ];
+        let triangles: Vec<Polygon> = triangles
+            .iter()
+            .map(|(ring, holes)| {
+                let mut ring = ring.clone();
+                if ring_area(&ring) < 0 {
+                    ring.reverse();
+                }
+                (ring, holes.clone())
+            })
+            .collect();
+        assert!(is_valid(&union(&triangles)));
+
+        // Random triangles on a small grid, where rounding matters most
+        let mut seed = 7u64;
+        let mut next = || {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            ((seed >> 33) % 41) as i32
+        };
+
+        for _ in 0..1000 {
+            let mut polygons = Vec::new();
+            for _ in 0..3 {
+                let mut ring = vec![(next(), next()), (next(), next()), (next(), next())];
+                match ring_area(&ring) {
+                    0 => continue,
+                    area if area < 0 => ring.reverse(),
+                    _ => {}
+                }
+                polygons.push((ring, vec![]));
+            }
+
+            let merged = union(&polygons);
+            assert!(is_valid(&merged), "invalid union of {:?}: {:?}", polygons, merged);
+        }
+    }
+
+    #[test]
+    fn dissolving() {
+        let feature = |id: u64, geometry: Geometry, landuse: &str, name: &str| Feature {
+            id: Some(id),
+            tags: vec![
+                ("landuse".to_string(), Value::String(landuse.to_string())),
+                ("name".to_string(), Value::String(name.to_string())),
+            ],
+            geometry,
+        };
+
+        let features = vec![
+            feature(1, Geometry::Polygon(square(0, 0, 10), vec![]), "forest", "a"),
+            feature(2, Geometry::Polygon(square(0, 10, 10), vec![]), "farmland", "b"),
+            feature(3, Geometry::Polygon(square(10, 0, 10), vec![]), "forest", "c"),
+            feature(4, Geometry::Line(vec![(0, 0), (10, 10)]), "forest", "d"),
+            feature(5, Geometry::Polygon(square(50, 50, 10), vec![]), "forest", "e"),
+        ];
+
+        let dissolved = dissolve(features, "landuse");
+        assert_eq!(dissolved.len(), 3);
+
+        assert_eq!(dissolved[0].id, None);
+        assert_eq!(
+            dissolved[0].tags,
+            vec![("landuse".to_string(), Value::String("forest".to_string()))]
+        );
+        let polygons = match &dissolved[0].geometry {
+            Geometry::MultiPolygon(polygons) => polygons.clone(),
+            geometry => panic!("unexpected geometry {:?}", geometry),
+        };
+        assert_eq!(
+            polygons,
+            vec![
+                (vec![(0, 0), (20, 0), (20, 10), (0, 10)], vec![]),
+                (square(50, 50, 10), vec![])
+            ]
+        );
+        assert!(is_valid(&polygons));
+
+        assert_eq!(dissolved[1].id, Some(2));
+        assert_eq!(dissolved[2].id, Some(4));
+    }
+}
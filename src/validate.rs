@@ -0,0 +1,641 @@
+use super::compression::{decompress, Compression};
+use super::error::ArchiveError;
+
+use super::read::{self, decode_geometry, decode_value};
+use super::store::TileStore;
+use super::tile::TileId;
+use super::union::is_valid;
+
+use super::proto::vector_tile as pbf;
+use pbf::mod_Tile as pbf_tile;
+
+use quick_protobuf::{BytesReader, MessageRead};
+
+use serde_json::{json, Value as JsonValue};
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::panic;
+use std::thread;
+
+/// Requirement level of a check in the specification. Tiles with `Must` violations are invalid, `Should`
+/// violations are only recommendations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    Should,
+    Must,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Should => "should",
+            Severity::Must => "must",
+        }
+    }
+}
+
+/// The checks of the Mapbox Vector Tile specification 2.1 run by `validate`, followed by the checks of the
+/// tiles of an archive against its metadata run by `validate_store`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ViolationKind {
+    Decompression,
+    InvalidProtobuf,
+    InvalidVersion,
+    EmptyLayerName,
+    IdenticalLayerNames,
+    InvalidExtent,
+    EmptyLayer,
+    IdenticalKeys,
+    IdenticalValues,
+    InvalidValue,
+    InvalidTagIndex,
+    IdenticalAttributeKeys,
+    IdenticalFeatureIds,
+    UnknownGeometryType,
+    InvalidGeometry,
+    InvalidPolygon,
+    UnlistedLayer,
+    OversizedTile,
+}
+
+impl ViolationKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            ViolationKind::EmptyLayer
+            | ViolationKind::IdenticalKeys
+            | ViolationKind::IdenticalValues
+            | ViolationKind::IdenticalAttributeKeys
+            | ViolationKind::IdenticalFeatureIds
+            | ViolationKind::UnknownGeometryType => Severity::Should,
+            _ => Severity::Must,
+        }
+    }
+
+    /// Identifier used in JSON reports.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ViolationKind::Decompression => "decompression",
+            ViolationKind::InvalidProtobuf => "invalid_protobuf",
+            ViolationKind::InvalidVersion => "invalid_version",
+            ViolationKind::EmptyLayerName => "empty_layer_name",
+            ViolationKind::IdenticalLayerNames => "identical_layer_names",
+            ViolationKind::InvalidExtent => "invalid_extent",
+            ViolationKind::EmptyLayer => "empty_layer",
+            ViolationKind::IdenticalKeys => "identical_keys",
+            ViolationKind::IdenticalValues => "identical_values",
+            ViolationKind::InvalidValue => "invalid_value",
+            ViolationKind::InvalidTagIndex => "invalid_tag_index",
+            ViolationKind::IdenticalAttributeKeys => "identical_attribute_keys",
+            ViolationKind::IdenticalFeatureIds => "identical_feature_ids",
+            ViolationKind::UnknownGeometryType => "unknown_geometry_type",
+            ViolationKind::InvalidGeometry => "invalid_geometry",
+            ViolationKind::InvalidPolygon => "invalid_polygon",
+            ViolationKind::UnlistedLayer => "unlisted_layer",
+            ViolationKind::OversizedTile => "oversized_tile",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            ViolationKind::Decompression => "Tile cannot be decompressed",
+            ViolationKind::InvalidProtobuf => "Tile is not a valid protobuf message",
+            ViolationKind::InvalidVersion => "Layer version must be 2",
+            ViolationKind::EmptyLayerName => "Layer must have a name",
+            ViolationKind::IdenticalLayerNames => "Layer names must be unique",
+            ViolationKind::InvalidExtent => "Layer extent must not be zero",
+            ViolationKind::EmptyLayer => "Layer should contain at least one feature",
+            ViolationKind::IdenticalKeys => "Layer keys should be unique",
+            ViolationKind::IdenticalValues => "Layer values should be unique",
+            ViolationKind::InvalidValue => "A value must contain exactly one field",
+            ViolationKind::InvalidTagIndex => "Tags must be pairs of valid key and value indices",
+            ViolationKind::IdenticalAttributeKeys => "Attribute keys of a feature should be unique",
+            ViolationKind::IdenticalFeatureIds => "Feature ids should be unique within a layer",
+            ViolationKind::UnknownGeometryType => "Feature should have a known geometry type",
+            ViolationKind::InvalidGeometry => "Invalid geometry command sequence",
+            ViolationKind::InvalidPolygon => "Polygon rings must not intersect and holes must lie in their polygon",
+            ViolationKind::UnlistedLayer => "Layer is not listed in the vector_layers metadata",
+            ViolationKind::OversizedTile => "Tile is larger than the size limit",
+        }
+    }
+}
+
+/// A failed check, with the layer and index of the feature it was found in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    pub layer: Option<String>,
+    pub feature: Option<usize>,
+    pub detail: Option<String>,
+}
+
+impl Violation {
+    fn new(kind: ViolationKind) -> Violation {
+        Violation {
+            kind,
+            layer: None,
+            feature: None,
+            detail: None,
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        json!({
+            "kind": self.kind.as_str(),
+            "severity": self.kind.severity().as_str(),
+            "layer": self.layer,
+            "feature": self.feature,
+            "detail": self.detail,
+        })
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind.description())?;
+        if let Some(layer) = &self.layer {
+            write!(f, " (layer {:?}", layer)?;
+            if let Some(feature) = self.feature {
+                write!(f, ", feature {}", feature)?;
+            }
+            write!(f, ")")?;
+        }
+        if let Some(detail) = &self.detail {
+            write!(f, ": {}", detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// True if none of the violations is a `Must` violation.
+pub fn is_compliant(violations: &[Violation]) -> bool {
+    violations
+        .iter()
+        .all(|violation| violation.kind.severity() != Severity::Must)
+}
+
+/// Runs every check on an encoded tile, which may be gzip or zstd compressed, and returns the failed ones
+/// in the order of the layers and features.
+///
+/// Besides the encoding rules, polygons are checked with `union::is_valid`, so self-intersecting rings,
+/// overlapping parts and holes outside of their polygon are reported.
+pub fn validate(bytes: &[u8]) -> Vec<Violation> {
+    validate_tile(bytes, None)
+}
+
+/// Like `validate`, also reporting layers missing from `listed` if given.
+fn validate_tile(bytes: &[u8], listed: Option<&HashSet<String>>) -> Vec<Violation> {
+    let decompressed;
+    let bytes = match Compression::detect(bytes) {
+        Compression::None => bytes,
+        compression => match decompress(bytes, compression) {
+            Ok(data) => {
+                decompressed = data;
+                &decompressed
+            }
+            Err(error) => {
+                let mut violation = Violation::new(ViolationKind::Decompression);
+                violation.detail = Some(error.to_string());
+                return vec![violation];
+            }
+        },
+    };
+
+    let mut reader = BytesReader::from_bytes(bytes);
+    let tile = match pbf::Tile::from_reader(&mut reader, bytes) {
+        Ok(tile) => tile,
+        Err(error) => {
+            let mut violation = Violation::new(ViolationKind::InvalidProtobuf);
+            violation.detail = Some(error.to_string());
+            return vec![violation];
+        }
+    };
+
+    let mut violations = Vec::new();
+    let mut names = HashSet::new();
+
+    for layer in &tile.layers {
+        if !names.insert(layer.name.as_ref()) {
+            violations.push(Violation {
+                detail: Some(layer.name.to_string()),
+                ..Violation::new(ViolationKind::IdenticalLayerNames)
+            });
+        }
+        if listed.is_some_and(|listed| !listed.contains(layer.name.as_ref())) {
+            violations.push(Violation {
+                layer: Some(layer.name.to_string()),
+                ..Violation::new(ViolationKind::UnlistedLayer)
+            });
+        }
+        validate_layer(layer, &mut violations);
+    }
+
+    violations
+}
+
+/// Options of `validate_store`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationOptions {
+    /// Largest size of the stored, possibly compressed, tile data in bytes. `None` disables the check.
+    pub max_tile_size: Option<usize>,
+    /// Number of threads validating tiles.
+    pub threads: usize,
+}
+
+impl Default for ValidationOptions {
+    /// 500 KiB tiles at most, one thread per available core.
+    fn default() -> ValidationOptions {
+        ValidationOptions {
+            max_tile_size: Some(500 * 1024),
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+        }
+    }
+}
+
+/// Result of validating all tiles of an archive.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveReport {
+    pub tiles: usize,
+    /// Violations ordered by tile, then in the order `validate` reports them.
+    pub violations: Vec<(TileId, Violation)>,
+}
+
+impl ArchiveReport {
+    /// True if no tile has a `Must` violation.
+    pub fn is_compliant(&self) -> bool {
+        self.violations
+            .iter()
+            .all(|(_, violation)| violation.kind.severity() != Severity::Must)
+    }
+
+    /// The report with violations grouped by kind. Every kind lists at most `limit` occurrences, but
+    /// counts all of them.
+    pub fn to_json(&self, limit: usize) -> JsonValue {
+        let mut kinds: BTreeMap<ViolationKind, Vec<&(TileId, Violation)>> = BTreeMap::new();
+        for violation in &self.violations {
+            kinds.entry(violation.1.kind).or_default().push(violation);
+        }
+
+        let violations: serde_json::Map<String, JsonValue> = kinds
+            .into_iter()
+            .map(|(kind, violations)| {
+                let mut tiles: Vec<TileId> = violations.iter().map(|(id, _)| *id).collect();
+                tiles.dedup();
+
+                let occurrences: Vec<JsonValue> = violations
+                    .iter()
+                    .take(limit)
+                    .map(|(id, violation)| {
+                        json!({
+                            "tile": id.to_string(),
+                            "layer": violation.layer,
+                            "feature": violation.feature,
+                            "detail": violation.detail,
+                        })
+                    })
+                    .collect();
+
+                let group = json!({
+                    "severity": kind.severity().as_str(),
+                    "description": kind.description(),
+                    "count": violations.len(),
+                    "tiles": tiles.len(),
+                    "occurrences": occurrences,
+                });
+                (kind.as_str().to_string(), group)
+            })
+            .collect();
+
+        json!({
+            "tiles": self.tiles,
+            "compliant": self.is_compliant(),
+            "violations": violations,
+        })
+    }
+}
+
+/// Validates every tile of an MBTiles, PMTiles or directory store with `validate`, in parallel.
+///
+/// Layers of the tiles are also checked against the `vector_layers` of the store metadata, unless it lists
+/// no layer at all, and tiles whose
+/// stored data is larger than `options.max_tile_size` are reported. Tiles are read from the store on the
+/// calling thread in batches and the batches are validated on `options.threads` threads.
+pub fn validate_store<S>(store: &mut S, options: &ValidationOptions) -> Result<ArchiveReport, ArchiveError>
+where
+    S: TileStore + ?Sized,
+{
+    let listed: HashSet<String> = store
+        .metadata()?
+        .vector_layers
+        .into_iter()
+        .map(|layer| layer.id)
+        .collect();
+    let ids: Vec<TileId> = store.iter()?.collect();
+
+    let threads = options.threads.max(1);
+    let check = |(id, data): &(TileId, Vec<u8>)| -> Vec<(TileId, Violation)> {
+        let mut violations = validate_tile(data, Some(&listed).filter(|listed| !listed.is_empty()));
+        if options.max_tile_size.is_some_and(|max| data.len() > max) {
+            violations.push(Violation {
+                detail: Some(format!("{} bytes", data.len())),
+                ..Violation::new(ViolationKind::OversizedTile)
+            });
+        }
+        violations.into_iter().map(|violation| (*id, violation)).collect()
+    };
+
+    let check = &check;
+    let mut report = ArchiveReport::default();
+
+    for batch in ids.chunks(threads * 64) {
+        let mut tiles = Vec::with_capacity(batch.len());
+        for id in batch {
+            if let Some(data) = store.get(*id)? {
+                tiles.push((*id, data));
+            }
+        }
+        report.tiles += tiles.len();
+
+        let chunk_size = tiles.len().div_ceil(threads).max(1);
+        thread::scope(|scope| {
+            let workers: Vec<_> = tiles
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || chunk.iter().flat_map(check).collect::<Vec<_>>()))
+                .collect();
+
+            for worker in workers {
+                match worker.join() {
+                    Ok(violations) => report.violations.extend(violations),
+                    Err(error) => panic::resume_unwind(error),
+                }
+            }
+        });
+    }
+
+    report.violations.sort_by_key(|(id, _)| *id);
+
+    Ok(report)
+}
+
+fn validate_layer(layer: &pbf_tile::Layer, violations: &mut Vec<Violation>) {
+    let mut report = |kind: ViolationKind, feature: Option<usize>, detail: Option<String>| {
+        violations.push(Violation {
+            kind,
+            layer: Some(layer.name.to_string()),
+            feature,
+            detail,
+        })
+    };
+
+    if layer.version != 2 {
+        report(ViolationKind::InvalidVersion, None, Some(layer.version.to_string()));
+    }
+    if layer.name.is_empty() {
+        report(ViolationKind::EmptyLayerName, None, None);
+    }
+    if layer.extent == 0 {
+        report(ViolationKind::InvalidExtent, None, None);
+    }
+    if layer.features.is_empty() {
+        report(ViolationKind::EmptyLayer, None, None);
+    }
+
+    let mut keys = HashSet::new();
+    for key in &layer.keys {
+        if !keys.insert(key.as_ref()) {
+            report(ViolationKind::IdenticalKeys, None, Some(key.to_string()));
+        }
+    }
+
+    let mut values = HashSet::new();
+    for (idx, value) in layer.values.iter().enumerate() {
+        match decode_value(value) {
+            Ok(value) => {
+                if !values.insert(value.key()) {
+                    report(ViolationKind::IdenticalValues, None, Some(format!("{:?}", value)));
+                }
+            }
+            Err(_) => report(ViolationKind::InvalidValue, None, Some(format!("value {}", idx))),
+        }
+    }
+
+    let mut ids = HashSet::new();
+
+    for (idx, feature) in layer.features.iter().enumerate() {
+        if feature.id != 0 && !ids.insert(feature.id) {
+            report(
+                ViolationKind::IdenticalFeatureIds,
+                Some(idx),
+                Some(feature.id.to_string()),
+            );
+        }
+
+        let valid_tags = feature.tags.len() % 2 == 0
+            && feature
+                .tags
+                .chunks(2)
+                .all(|pair| (pair[0] as usize) < layer.keys.len() && (pair[1] as usize) < layer.values.len());
+        if !valid_tags {
+            report(ViolationKind::InvalidTagIndex, Some(idx), None);
+        } else {
+            let mut feature_keys = HashSet::new();
+            for pair in feature.tags.chunks(2) {
+                let key = layer.keys[pair[0] as usize].as_ref();
+                if !feature_keys.insert(key) {
+                    report(ViolationKind::IdenticalAttributeKeys, Some(idx), Some(key.to_string()));
+                }
+            }
+        }
+
+        match decode_geometry(feature.type_pb, &feature.geometry) {
+            Ok(None) => report(ViolationKind::UnknownGeometryType, Some(idx), None),
+            Ok(Some(read::Geometry::Polygon(exterior_ring, interior_rings))) => {
+                if !is_valid(&[(exterior_ring, interior_rings)]) {
+                    report(ViolationKind::InvalidPolygon, Some(idx), None);
+                }
+            }
+            Ok(Some(read::Geometry::MultiPolygon(polygons))) => {
+                if !is_valid(&polygons) {
+                    report(ViolationKind::InvalidPolygon, Some(idx), None);
+                }
+            }
+            Ok(Some(_)) => {}
+            Err(_) => report(ViolationKind::InvalidGeometry, Some(idx), None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod validate_test {
+    use super::*;
+    use crate::common::Value;
+    use crate::metadata::VectorLayer;
+    use crate::store::MemoryStore;
+    use crate::write::{self, EncodableGeometry};
+
+    use quick_protobuf::{MessageWrite, Writer};
+
+    use std::borrow::Cow;
+
+    fn encode(tile: &pbf::Tile) -> Vec<u8> {
+        let mut out = Vec::new();
+        tile.write_message(&mut Writer::new(&mut out)).unwrap();
+        out
+    }
+
+    fn feature(id: u64, type_pb: pbf_tile::GeomType, geometry: Vec<u32>, tags: Vec<u32>) -> pbf_tile::Feature {
+        pbf_tile::Feature {
+            id,
+            tags,
+            type_pb,
+            geometry,
+        }
+    }
+
+    #[test]
+    fn valid_tile() {
+        let polygon = write::Geometry::Polygon(&[(0, 0), (10, 0), (10, 10), (0, 10)], &[]);
+        let mut feature = write::Feature::new(polygon.encode().unwrap());
+        feature.add_tag("name", Value::String("square".to_string()));
+        let tile = write::Tile::new(vec![write::Layer::new("squares", vec![feature]).unwrap()]).unwrap();
+
+        assert_eq!(validate(&tile.to_bytes()), vec![]);
+        assert_eq!(
+            validate(&[0xff, 0xff])
+                .iter()
+                .map(|violation| violation.kind)
+                .collect::<Vec<_>>(),
+            vec![ViolationKind::InvalidProtobuf]
+        );
+    }
+
+    #[test]
+    fn violations() {
+        use pbf_tile::GeomType::*;
+
+        // A square with a hole outside of it
+        let polygon = vec![
+            9, 0, 0, 26, 20, 0, 0, 20, 19, 0, 15, 9, 40, 20, 26, 0, 4, 4, 0, 0, 3, 15,
+        ];
+
+        let layer = pbf_tile::Layer {
+            version: 1,
+            name: Cow::Borrowed("roads"),
+            features: vec![
+                feature(1, POINT, vec![9, 2, 2], vec![0, 0, 1, 0]),
+                feature(1, POINT, vec![9, 2, 2], vec![0, 2]),
+                feature(2, LINESTRING, vec![9, 2, 2], vec![]),
+                feature(3, POLYGON, polygon, vec![]),
+                feature(4, UNKNOWN, vec![], vec![]),
+            ],
+            keys: vec![Cow::Borrowed("class"), Cow::Borrowed("class")],
+            values: vec![pbf_tile::Value {
+                string_value: Some(Cow::Borrowed("primary")),
+                ..Default::default()
+            }],
+            extent: 4096,
+        };
+        let empty = pbf_tile::Layer {
+            version: 2,
+            name: Cow::Borrowed("roads"),
+            features: vec![],
+            keys: vec![],
+            values: vec![],
+            extent: 4096,
+        };
+        let bytes = encode(&pbf::Tile {
+            layers: vec![layer, empty],
+        });
+
+        let violations = validate(&bytes);
+        assert_eq!(
+            violations
+                .iter()
+                .map(|violation| (violation.kind, violation.feature))
+                .collect::<Vec<_>>(),
+            vec![
+                (ViolationKind::InvalidVersion, None),
+                (ViolationKind::IdenticalKeys, None),
+                (ViolationKind::IdenticalAttributeKeys, Some(0)),
+                (ViolationKind::IdenticalFeatureIds, Some(1)),
+                (ViolationKind::InvalidTagIndex, Some(1)),
+                (ViolationKind::InvalidGeometry, Some(2)),
+                (ViolationKind::InvalidPolygon, Some(3)),
+                (ViolationKind::UnknownGeometryType, Some(4)),
+                (ViolationKind::IdenticalLayerNames, None),
+                (ViolationKind::EmptyLayer, None),
+            ]
+        );
+        assert!(!is_compliant(&violations));
+        assert!(is_compliant(&violations[1..4]));
+        assert_eq!(
+            violations[1].to_string(),
+            "Layer keys should be unique (layer \"roads\"): class"
+        );
+    }
+
+    #[test]
+    fn archive() {
+        let encode = |name: &str| {
+            let mut feature = write::Feature::new(write::Geometry::Point((1, 1)).encode().unwrap());
+            feature.add_tag("name", Value::String("x".repeat(100)));
+            let layer = write::Layer::new(name, vec![feature]).unwrap();
+            write::Tile::new(vec![layer]).unwrap().to_bytes()
+        };
+
+        let mut store = MemoryStore::new();
+        store.metadata.vector_layers.push(VectorLayer {
+            id: "places".to_string(),
+            ..Default::default()
+        });
+        for (x, data) in [encode("places"), encode("roads"), vec![0xff, 0xff]].iter().enumerate() {
+            store.put(TileId::new(2, x as u32, 0).unwrap(), data).unwrap();
+        }
+
+        let options = ValidationOptions {
+            max_tile_size: Some(100),
+            threads: 2,
+        };
+        let report = validate_store(&mut store, &options).unwrap();
+
+        assert_eq!(report.tiles, 3);
+        assert!(!report.is_compliant());
+        assert_eq!(
+            report
+                .violations
+                .iter()
+                .map(|(id, violation)| (id.x(), violation.kind))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, ViolationKind::OversizedTile),
+                (1, ViolationKind::UnlistedLayer),
+                (1, ViolationKind::OversizedTile),
+                (2, ViolationKind::InvalidProtobuf),
+            ]
+        );
+
+        let json = report.to_json(1);
+        assert_eq!(json["tiles"], json!(3));
+        assert_eq!(json["violations"]["oversized_tile"]["count"], json!(2));
+        assert_eq!(
+            json["violations"]["oversized_tile"]["occurrences"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            json["violations"]["unlisted_layer"]["occurrences"][0]["tile"],
+            json!("2/1/0")
+        );
+        assert_eq!(json["violations"]["invalid_protobuf"]["severity"], json!("must"));
+
+        // Without vector_layers metadata, layers are not checked
+        let mut store = MemoryStore::new();
+        store.put(TileId::new(2, 0, 0).unwrap(), &encode("roads")).unwrap();
+
+        let report = validate_store(&mut store, &ValidationOptions::default()).unwrap();
+        assert!(report.is_compliant());
+        assert!(report.violations.is_empty());
+    }
+}
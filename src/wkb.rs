@@ -0,0 +1,430 @@
+use super::common::TileCoord;
+
+use super::error::WkbError;
+
+use super::read::Geometry;
+
+use super::wkt::{open_ring, orient_polygon};
+
+use std::convert::TryInto;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    BigEndian,
+    LittleEndian,
+}
+
+const POINT: u32 = 1;
+const LINESTRING: u32 = 2;
+const POLYGON: u32 = 3;
+const MULTIPOINT: u32 = 4;
+const MULTILINESTRING: u32 = 5;
+const MULTIPOLYGON: u32 = 6;
+const GEOMETRYCOLLECTION: u32 = 7;
+
+const EWKB_Z: u32 = 0x8000_0000;
+const EWKB_M: u32 = 0x4000_0000;
+const EWKB_SRID: u32 = 0x2000_0000;
+
+/// Deepest nesting of geometry collections accepted by `parse`.
+const MAX_NESTING: usize = 32;
+
+/// Parses (E)WKB, as returned by PostGIS `ST_AsBinary` or `ST_AsEWKB`. Geometry collections are split
+/// into their members and empty geometries are skipped. Polygon rings are oriented as tiles expect. Fails if
+/// bytes are left after the geometry.
+pub fn parse(wkb: &[u8]) -> Result<Vec<Geometry>, WkbError> {
+    let mut reader = Reader {
+        wkb,
+        order: ByteOrder::LittleEndian,
+    };
+    let mut geometries = Vec::new();
+
+    reader.geometry(&mut geometries, 0)?;
+
+    if reader.wkb.is_empty() {
+        Ok(geometries)
+    } else {
+        Err(WkbError::TrailingBytes(reader.wkb.len()))
+    }
+}
+
+/// Serializes a geometry to ISO WKB. Polygon rings are closed by repeating their first vertex.
+pub fn to_bytes(geometry: &Geometry, order: ByteOrder) -> Vec<u8> {
+    let mut writer = Writer { wkb: Vec::new(), order };
+    writer.geometry(geometry);
+    writer.wkb
+}
+
+struct Reader<'a> {
+    wkb: &'a [u8],
+    order: ByteOrder,
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], WkbError> {
+        if self.wkb.len() < N {
+            return Err(WkbError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.wkb.split_at(N);
+        self.wkb = rest;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32, WkbError> {
+        let bytes = self.take()?;
+        Ok(match self.order {
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn f64(&mut self) -> Result<f64, WkbError> {
+        let bytes = self.take()?;
+        Ok(match self.order {
+            ByteOrder::BigEndian => f64::from_be_bytes(bytes),
+            ByteOrder::LittleEndian => f64::from_le_bytes(bytes),
+        })
+    }
+
+    fn coordinate(&mut self) -> Result<Option<i32>, WkbError> {
+        let value = self.f64()?;
+
+        if value.is_nan() {
+            Ok(None)
+        } else if value >= i32::MIN as f64 && value <= i32::MAX as f64 {
+            Ok(Some(value.round() as i32))
+        } else {
+            Err(WkbError::InvalidCoordinate)
+        }
+    }
+
+    fn point(&mut self, dimensions: usize) -> Result<Option<TileCoord>, WkbError> {
+        let x = self.coordinate()?;
+        let y = self.coordinate()?;
+
+        for _ in 2..dimensions {
+            self.f64()?;
+        }
+
+        match (x, y) {
+            (Some(x), Some(y)) => Ok(Some((x, y))),
+            // Empty points are encoded with NaN coordinates
+            (None, None) => Ok(None),
+            _ => Err(WkbError::InvalidCoordinate),
+        }
+    }
+
+    fn points(&mut self, dimensions: usize) -> Result<Vec<TileCoord>, WkbError> {
+        let count = self.u32()?;
+        let mut points = Vec::new();
+        for _ in 0..count {
+            points.push(self.point(dimensions)?.ok_or(WkbError::InvalidCoordinate)?);
+        }
+        Ok(points)
+    }
+
+    fn polygon(&mut self, dimensions: usize) -> Result<Vec<Vec<TileCoord>>, WkbError> {
+        let count = self.u32()?;
+        let mut rings = Vec::new();
+        for _ in 0..count {
+            rings.push(open_ring(self.points(dimensions)?));
+        }
+        Ok(rings)
+    }
+
+    /// Reads a geometry header and returns the base geometry type and the number of dimensions.
+    fn header(&mut self) -> Result<(u32, usize), WkbError> {
+        self.order = match self.take::<1>()?[0] {
+            0 => ByteOrder::BigEndian,
+            1 => ByteOrder::LittleEndian,
+            order => return Err(WkbError::InvalidByteOrder(order)),
+        };
+
+        let code = self.u32()?;
+
+        if code & EWKB_SRID != 0 {
+            self.u32()?;
+        }
+
+        let mut dimensions = 2;
+        if code & EWKB_Z != 0 {
+            dimensions += 1;
+        }
+        if code & EWKB_M != 0 {
+            dimensions += 1;
+        }
+
+        let iso_code = code & 0x0fff_ffff;
+        dimensions += match iso_code / 1000 {
+            0 => 0,
+            1 | 2 => 1,
+            3 => 2,
+            _ => return Err(WkbError::UnsupportedGeometry(code)),
+        };
+
+        Ok((iso_code % 1000, dimensions))
+    }
+
+    fn geometry(&mut self, geometries: &mut Vec<Geometry>, depth: usize) -> Result<(), WkbError> {
+        let (code, dimensions) = self.header()?;
+
+        let geometry = match code {
+            POINT => self.point(dimensions)?.map(Geometry::Point),
+            LINESTRING => Some(self.points(dimensions)?)
+                .filter(|line| !line.is_empty())
+                .map(Geometry::Line),
+            POLYGON => {
+                let mut rings = self.polygon(dimensions)?.into_iter();
+                rings.next().map(|exterior_ring| {
+                    let (exterior_ring, interior_rings) = orient_polygon(exterior_ring, rings.collect());
+                    Geometry::Polygon(exterior_ring, interior_rings)
+                })
+            }
+            MULTIPOINT | MULTILINESTRING | MULTIPOLYGON | GEOMETRYCOLLECTION => {
+                if depth == MAX_NESTING {
+                    return Err(WkbError::NestingTooDeep);
+                }
+                let count = self.u32()?;
+                let mut members = Vec::new();
+                for _ in 0..count {
+                    let (member_code, _) = {
+                        let mut lookahead = Reader {
+                            wkb: self.wkb,
+                            order: self.order,
+                        };
+                        lookahead.header()?
+                    };
+                    if code != GEOMETRYCOLLECTION && member_code != code - 3 {
+                        return Err(WkbError::UnsupportedGeometry(member_code));
+                    }
+                    self.geometry(&mut members, depth + 1)?;
+                }
+
+                if code == GEOMETRYCOLLECTION {
+                    geometries.append(&mut members);
+                    return Ok(());
+                }
+
+                collect_multi(code, members)
+            }
+            _ => return Err(WkbError::UnsupportedGeometry(code)),
+        };
+
+        geometries.extend(geometry);
+
+        Ok(())
+    }
+}
+
+fn collect_multi(code: u32, members: Vec<Geometry>) -> Option<Geometry> {
+    if members.is_empty() {
+        return None;
+    }
+
+    let geometry = match code {
+        MULTIPOINT => Geometry::MultiPoint(
+            members
+                .into_iter()
+                .filter_map(|member| match member {
+                    Geometry::Point(point) => Some(point),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        MULTILINESTRING => Geometry::MultiLine(
+            members
+                .into_iter()
+                .filter_map(|member| match member {
+                    Geometry::Line(line) => Some(line),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        _ => Geometry::MultiPolygon(
+            members
+                .into_iter()
+                .filter_map(|member| match member {
+                    Geometry::Polygon(exterior_ring, interior_rings) => Some((exterior_ring, interior_rings)),
+                    _ => None,
+                })
+                .collect(),
+        ),
+    };
+
+    Some(geometry)
+}
+
+struct Writer {
+    wkb: Vec<u8>,
+    order: ByteOrder,
+}
+
+impl Writer {
+    fn u32(&mut self, value: u32) {
+        match self.order {
+            ByteOrder::BigEndian => self.wkb.extend_from_slice(&value.to_be_bytes()),
+            ByteOrder::LittleEndian => self.wkb.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+
+    fn header(&mut self, code: u32) {
+        self.wkb.push(match self.order {
+            ByteOrder::BigEndian => 0,
+            ByteOrder::LittleEndian => 1,
+        });
+        self.u32(code);
+    }
+
+    fn point(&mut self, point: &TileCoord) {
+        for value in [point.0 as f64, point.1 as f64].iter() {
+            match self.order {
+                ByteOrder::BigEndian => self.wkb.extend_from_slice(&value.to_be_bytes()),
+                ByteOrder::LittleEndian => self.wkb.extend_from_slice(&value.to_le_bytes()),
+            }
+        }
+    }
+
+    fn points(&mut self, points: &[TileCoord]) {
+        self.u32(points.len() as u32);
+        for point in points {
+            self.point(point);
+        }
+    }
+
+    fn ring(&mut self, ring: &[TileCoord]) {
+        let closed = ring.len() > 1 && ring.first() == ring.last();
+        self.u32(ring.len() as u32 + if closed { 0 } else { 1 });
+        for point in ring.iter().chain(ring.first().filter(|_| !closed)) {
+            self.point(point);
+        }
+    }
+
+    fn polygon(&mut self, exterior_ring: &[TileCoord], interior_rings: &[Vec<TileCoord>]) {
+        self.header(POLYGON);
+        self.u32(interior_rings.len() as u32 + 1);
+        self.ring(exterior_ring);
+        for ring in interior_rings {
+            self.ring(ring);
+        }
+    }
+
+    fn geometry(&mut self, geometry: &Geometry) {
+        match geometry {
+            Geometry::Point(point) => {
+                self.header(POINT);
+                self.point(point);
+            }
+            Geometry::MultiPoint(points) => {
+                self.header(MULTIPOINT);
+                self.u32(points.len() as u32);
+                for point in points {
+                    self.header(POINT);
+                    self.point(point);
+                }
+            }
+            Geometry::Line(line) => {
+                self.header(LINESTRING);
+                self.points(line);
+            }
+            Geometry::MultiLine(lines) => {
+                self.header(MULTILINESTRING);
+                self.u32(lines.len() as u32);
+                for line in lines {
+                    self.header(LINESTRING);
+                    self.points(line);
+                }
+            }
+            Geometry::Polygon(exterior_ring, interior_rings) => self.polygon(exterior_ring, interior_rings),
+            Geometry::MultiPolygon(polygons) => {
+                self.header(MULTIPOLYGON);
+                self.u32(polygons.len() as u32);
+                for (exterior_ring, interior_rings) in polygons {
+                    self.polygon(exterior_ring, interior_rings);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod wkb_test {
+    use super::*;
+
+    #[test]
+    fn parse_point() {
+        let little_endian = [1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 36, 64, 0, 0, 0, 0, 0, 0, 52, 64];
+        assert_eq!(parse(&little_endian), Ok(vec![Geometry::Point((10, 20))]));
+
+        let big_endian = [0, 0, 0, 0, 1, 64, 36, 0, 0, 0, 0, 0, 0, 64, 52, 0, 0, 0, 0, 0, 0];
+        assert_eq!(parse(&big_endian), Ok(vec![Geometry::Point((10, 20))]));
+    }
+
+    #[test]
+    fn parse_ewkb() {
+        // SRID=3857;POINT Z (10 20 30)
+        let mut ewkb = vec![1];
+        ewkb.extend_from_slice(&(POINT | EWKB_Z | EWKB_SRID).to_le_bytes());
+        ewkb.extend_from_slice(&3857u32.to_le_bytes());
+        for value in [10.0f64, 20.0, 30.0].iter() {
+            ewkb.extend_from_slice(&value.to_le_bytes());
+        }
+        assert_eq!(parse(&ewkb), Ok(vec![Geometry::Point((10, 20))]));
+    }
+
+    #[test]
+    fn invalid_wkb() {
+        assert_eq!(parse(&[]), Err(WkbError::UnexpectedEnd));
+        assert_eq!(parse(&[2, 1, 0, 0, 0]), Err(WkbError::InvalidByteOrder(2)));
+        assert_eq!(parse(&[1, 17, 0, 0, 0]), Err(WkbError::UnsupportedGeometry(17)));
+        assert_eq!(parse(&[1, 1, 0, 0, 0, 0]), Err(WkbError::UnexpectedEnd));
+
+        let mut point = to_bytes(&Geometry::Point((1, 2)), ByteOrder::LittleEndian);
+        point.push(0);
+        assert_eq!(parse(&point), Err(WkbError::TrailingBytes(1)));
+
+        // Collections nested deeper than the limit, each with a single member
+        let mut nested = Vec::new();
+        for _ in 0..=MAX_NESTING {
+            nested.extend_from_slice(&[1, 7, 0, 0, 0, 1, 0, 0, 0]);
+        }
+        nested.extend_from_slice(&to_bytes(&Geometry::Point((1, 2)), ByteOrder::LittleEndian));
+        assert_eq!(parse(&nested), Err(WkbError::NestingTooDeep));
+        assert_eq!(parse(&nested[9..]), Ok(vec![Geometry::Point((1, 2))]));
+    }
+
+    #[test]
+    fn round_trip() {
+        let geometries = vec![
+            Geometry::Point((5, 7)),
+            Geometry::MultiPoint(vec![(5, 7), (3, 2)]),
+            Geometry::Line(vec![(2, 2), (2, 10), (10, 10)]),
+            Geometry::MultiLine(vec![vec![(2, 2), (2, 10), (10, 10)], vec![(1, 1), (3, 5)]]),
+            Geometry::Polygon(vec![(0, 0), (10, 0), (10, 10)], vec![vec![(1, 1), (2, 2), (2, 1)]]),
+            Geometry::MultiPolygon(vec![
+                (vec![(0, 0), (10, 0), (10, 10)], vec![]),
+                (vec![(11, 11), (20, 11), (20, 20)], vec![]),
+            ]),
+        ];
+
+        for geometry in geometries {
+            for order in [ByteOrder::BigEndian, ByteOrder::LittleEndian].iter() {
+                let wkb = to_bytes(&geometry, *order);
+                assert_eq!(parse(&wkb), Ok(vec![geometry.clone()]));
+            }
+        }
+    }
+
+    #[test]
+    fn split_collection() {
+        let mut wkb = vec![1];
+        wkb.extend_from_slice(&GEOMETRYCOLLECTION.to_le_bytes());
+        wkb.extend_from_slice(&2u32.to_le_bytes());
+        wkb.extend(to_bytes(&Geometry::Point((1, 2)), ByteOrder::BigEndian));
+        wkb.extend(to_bytes(&Geometry::Line(vec![(0, 0), (1, 1)]), ByteOrder::LittleEndian));
+
+        assert_eq!(
+            parse(&wkb),
+            Ok(vec![Geometry::Point((1, 2)), Geometry::Line(vec![(0, 0), (1, 1)])])
+        );
+    }
+}
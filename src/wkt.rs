@@ -0,0 +1,403 @@
+use super::common::TileCoord;
+
+use super::error::WktError;
+
+use super::read::{ring_area, Geometry};
+
+use std::fmt::Write;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+type Ring = Vec<TileCoord>;
+type Polygon = (Ring, Vec<Ring>);
+
+/// Deepest nesting of geometry collections accepted by `parse`.
+const MAX_NESTING: usize = 32;
+
+/// Parses a WKT string into geometries. Geometry collections are split into their members and empty
+/// geometries are skipped, so the result may contain any number of geometries. Polygon rings are oriented as
+/// tiles expect, whatever their orientation in the input.
+pub fn parse(wkt: &str) -> Result<Vec<Geometry>, WktError> {
+    let mut parser = Parser::new(wkt);
+    let mut geometries = Vec::new();
+
+    parser.geometry(&mut geometries, 0)?;
+
+    match parser.next_token()? {
+        None => Ok(geometries),
+        Some(token) => Err(WktError::UnexpectedToken(token.to_string())),
+    }
+}
+
+/// Serializes a geometry to WKT. Polygon rings are closed by repeating their first vertex.
+pub fn to_string(geometry: &Geometry) -> String {
+    let mut wkt = String::new();
+
+    match geometry {
+        Geometry::Point(point) => {
+            wkt.push_str("POINT");
+            write_points(&mut wkt, &[*point]);
+        }
+        Geometry::MultiPoint(points) => {
+            wkt.push_str("MULTIPOINT");
+            write_list(&mut wkt, points, |wkt, point| write_points(wkt, &[*point]));
+        }
+        Geometry::Line(line) => {
+            wkt.push_str("LINESTRING");
+            write_points(&mut wkt, line);
+        }
+        Geometry::MultiLine(lines) => {
+            wkt.push_str("MULTILINESTRING");
+            write_list(&mut wkt, lines, |wkt, line| write_points(wkt, line));
+        }
+        Geometry::Polygon(exterior_ring, interior_rings) => {
+            wkt.push_str("POLYGON");
+            write_polygon(&mut wkt, exterior_ring, interior_rings);
+        }
+        Geometry::MultiPolygon(polygons) => {
+            wkt.push_str("MULTIPOLYGON");
+            write_list(&mut wkt, polygons, |wkt, (exterior_ring, interior_rings)| {
+                write_polygon(wkt, exterior_ring, interior_rings)
+            });
+        }
+    }
+
+    wkt
+}
+
+fn write_list<T, F: Fn(&mut String, &T)>(wkt: &mut String, items: &[T], write_item: F) {
+    wkt.push('(');
+    for (idx, item) in items.iter().enumerate() {
+        if idx > 0 {
+            wkt.push(',');
+        }
+        write_item(wkt, item);
+    }
+    wkt.push(')');
+}
+
+fn write_points(wkt: &mut String, points: &[TileCoord]) {
+    write_list(wkt, points, |wkt, (x, y)| write!(wkt, "{} {}", x, y).unwrap());
+}
+
+fn write_ring(wkt: &mut String, ring: &[TileCoord]) {
+    let mut closed = ring.to_vec();
+    if let (Some(first), Some(last)) = (ring.first(), ring.last()) {
+        if first != last {
+            closed.push(*first);
+        }
+    }
+    write_points(wkt, &closed);
+}
+
+fn write_polygon(wkt: &mut String, exterior_ring: &[TileCoord], interior_rings: &[Ring]) {
+    wkt.push('(');
+    write_ring(wkt, exterior_ring);
+    for ring in interior_rings {
+        wkt.push(',');
+        write_ring(wkt, ring);
+    }
+    wkt.push(')');
+}
+
+/// Drops the closing vertex of a ring, as tile geometries close rings implicitly.
+pub(crate) fn open_ring(mut ring: Ring) -> Ring {
+    if ring.len() > 1 && ring.first() == ring.last() {
+        ring.pop();
+    }
+    ring
+}
+
+/// Orients the rings of a polygon as tiles expect: a positive area for the exterior ring (clockwise with the
+/// y axis pointing down) and negative areas for its holes.
+pub(crate) fn orient_polygon(mut exterior_ring: Ring, mut interior_rings: Vec<Ring>) -> Polygon {
+    if ring_area(&exterior_ring) < 0 {
+        exterior_ring.reverse();
+    }
+    for ring in interior_rings.iter_mut() {
+        if ring_area(ring) > 0 {
+            ring.reverse();
+        }
+    }
+    (exterior_ring, interior_rings)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Parser<'a> {
+        Parser {
+            input,
+            chars: input.char_indices().peekable(),
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<&'a str>, WktError> {
+        while let Some((_, c)) = self.chars.peek() {
+            if c.is_whitespace() {
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+
+        let start = match self.chars.next() {
+            Some((idx, '(')) | Some((idx, ')')) | Some((idx, ',')) => return Ok(Some(&self.input[idx..idx + 1])),
+            Some((idx, _)) => idx,
+            None => return Ok(None),
+        };
+
+        let mut end = self.input.len();
+        while let Some((idx, c)) = self.chars.peek() {
+            if c.is_whitespace() || *c == '(' || *c == ')' || *c == ',' {
+                end = *idx;
+                break;
+            }
+            self.chars.next();
+        }
+
+        Ok(Some(&self.input[start..end]))
+    }
+
+    fn peek_token(&mut self) -> Result<Option<&'a str>, WktError> {
+        let chars = self.chars.clone();
+        let token = self.next_token();
+        self.chars = chars;
+        token
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), WktError> {
+        match self.next_token()? {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(WktError::UnexpectedToken(token.to_string())),
+            None => Err(WktError::UnexpectedEnd),
+        }
+    }
+
+    /// Consumes an optional dimension marker and `EMPTY`. Returns `true` if the geometry is empty.
+    fn empty(&mut self) -> Result<bool, WktError> {
+        if let Some(token) = self.peek_token()? {
+            let token = token.to_ascii_uppercase();
+            if token == "Z" || token == "M" || token == "ZM" {
+                self.next_token()?;
+            }
+        }
+
+        match self.peek_token()? {
+            Some(token) if token.eq_ignore_ascii_case("EMPTY") => {
+                self.next_token()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn geometry(&mut self, geometries: &mut Vec<Geometry>, depth: usize) -> Result<(), WktError> {
+        let name = self.next_token()?.ok_or(WktError::UnexpectedEnd)?.to_ascii_uppercase();
+
+        if self.empty()? {
+            return Ok(());
+        }
+
+        let geometry = match name.as_str() {
+            "POINT" => {
+                self.expect("(")?;
+                let point = self.point()?;
+                self.expect(")")?;
+                Geometry::Point(point)
+            }
+            "MULTIPOINT" => Geometry::MultiPoint(self.list(|parser| {
+                if parser.peek_token()? == Some("(") {
+                    parser.next_token()?;
+                    let point = parser.point()?;
+                    parser.expect(")")?;
+                    Ok(point)
+                } else {
+                    parser.point()
+                }
+            })?),
+            "LINESTRING" => Geometry::Line(self.points()?),
+            "MULTILINESTRING" => Geometry::MultiLine(self.list(Parser::points)?),
+            "POLYGON" => {
+                let (exterior_ring, interior_rings) = self.polygon()?;
+                Geometry::Polygon(exterior_ring, interior_rings)
+            }
+            "MULTIPOLYGON" => Geometry::MultiPolygon(self.list(Parser::polygon)?),
+            "GEOMETRYCOLLECTION" => {
+                if depth == MAX_NESTING {
+                    return Err(WktError::NestingTooDeep);
+                }
+                self.expect("(")?;
+                loop {
+                    self.geometry(geometries, depth + 1)?;
+                    match self.next_token()? {
+                        Some(",") => continue,
+                        Some(")") => break,
+                        Some(token) => return Err(WktError::UnexpectedToken(token.to_string())),
+                        None => return Err(WktError::UnexpectedEnd),
+                    }
+                }
+                return Ok(());
+            }
+            _ => return Err(WktError::UnsupportedGeometry(name)),
+        };
+
+        geometries.push(geometry);
+
+        Ok(())
+    }
+
+    fn list<T, F>(&mut self, mut item: F) -> Result<Vec<T>, WktError>
+    where
+        F: FnMut(&mut Parser<'a>) -> Result<T, WktError>,
+    {
+        self.expect("(")?;
+        let mut items = Vec::new();
+        loop {
+            items.push(item(self)?);
+            match self.next_token()? {
+                Some(",") => continue,
+                Some(")") => break,
+                Some(token) => return Err(WktError::UnexpectedToken(token.to_string())),
+                None => return Err(WktError::UnexpectedEnd),
+            }
+        }
+        Ok(items)
+    }
+
+    fn point(&mut self) -> Result<TileCoord, WktError> {
+        let x = self.coordinate()?;
+        let y = self.coordinate()?;
+
+        // Z and M values have no place in a vector tile
+        while let Some(token) = self.peek_token()? {
+            if token == "," || token == ")" {
+                break;
+            }
+            self.coordinate()?;
+        }
+
+        Ok((x, y))
+    }
+
+    fn points(&mut self) -> Result<Vec<TileCoord>, WktError> {
+        self.list(Parser::point)
+    }
+
+    fn polygon(&mut self) -> Result<Polygon, WktError> {
+        let mut rings = self.list(Parser::points)?.into_iter().map(open_ring);
+        let exterior_ring = rings.next().unwrap_or_default();
+        Ok(orient_polygon(exterior_ring, rings.collect()))
+    }
+
+    fn coordinate(&mut self) -> Result<i32, WktError> {
+        let token = self.next_token()?.ok_or(WktError::UnexpectedEnd)?;
+        let value: f64 = token
+            .parse()
+            .map_err(|_| WktError::InvalidCoordinate(token.to_string()))?;
+
+        if value.is_finite() && value >= i32::MIN as f64 && value <= i32::MAX as f64 {
+            Ok(value.round() as i32)
+        } else {
+            Err(WktError::InvalidCoordinate(token.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod wkt_test {
+    use super::*;
+    use crate::write::EncodableGeometry;
+
+    #[test]
+    fn parse_geometries() {
+        assert_eq!(parse("POINT (25 17)"), Ok(vec![Geometry::Point((25, 17))]));
+        assert_eq!(parse("POINT Z (25.2 16.8 3)"), Ok(vec![Geometry::Point((25, 17))]));
+        assert_eq!(
+            parse("MULTIPOINT ((5 7), (3 2))"),
+            Ok(vec![Geometry::MultiPoint(vec![(5, 7), (3, 2)])])
+        );
+        assert_eq!(
+            parse("multipoint (5 7, 3 2)"),
+            Ok(vec![Geometry::MultiPoint(vec![(5, 7), (3, 2)])])
+        );
+        assert_eq!(
+            parse("LINESTRING (2 2, 2 10, 10 10)"),
+            Ok(vec![Geometry::Line(vec![(2, 2), (2, 10), (10, 10)])])
+        );
+        assert_eq!(
+            parse("POLYGON ((3 6, 8 12, 20 34, 3 6))"),
+            Ok(vec![Geometry::Polygon(vec![(3, 6), (8, 12), (20, 34)], vec![])])
+        );
+        assert_eq!(
+            parse("MULTIPOLYGON (((0 0, 10 0, 10 10, 0 0)), ((11 11, 20 11, 20 20, 11 11), (12 12, 13 12, 13 13, 12 12)))"),
+            Ok(vec![Geometry::MultiPolygon(vec![
+                (vec![(0, 0), (10, 0), (10, 10)], vec![]),
+                (vec![(11, 11), (20, 11), (20, 20)], vec![vec![(13, 13), (13, 12), (12, 12)]]),
+            ])])
+        );
+    }
+
+    #[test]
+    fn orient_rings() {
+        // Counter-clockwise exterior ring and clockwise hole as in OGC, with the y axis pointing up
+        let polygon = parse("POLYGON ((0 0, 0 10, 10 10, 10 0, 0 0), (2 2, 8 2, 8 8, 2 8, 2 2))").unwrap();
+        assert_eq!(
+            polygon,
+            vec![Geometry::Polygon(
+                vec![(10, 0), (10, 10), (0, 10), (0, 0)],
+                vec![vec![(2, 8), (8, 8), (8, 2), (2, 2)]]
+            )]
+        );
+
+        assert!(polygon[0].encode().is_ok());
+    }
+
+    #[test]
+    fn split_collection() {
+        assert_eq!(
+            parse("GEOMETRYCOLLECTION (POINT (1 2), POINT EMPTY, GEOMETRYCOLLECTION (LINESTRING (0 0, 1 1)))"),
+            Ok(vec![Geometry::Point((1, 2)), Geometry::Line(vec![(0, 0), (1, 1)])])
+        );
+        assert_eq!(parse("GEOMETRYCOLLECTION EMPTY"), Ok(vec![]));
+    }
+
+    #[test]
+    fn invalid_wkt() {
+        assert_eq!(parse("POINT (1"), Err(WktError::UnexpectedEnd));
+        assert_eq!(parse("POINT (1 a)"), Err(WktError::InvalidCoordinate("a".into())));
+        assert_eq!(
+            parse("CIRCLE (1 1)"),
+            Err(WktError::UnsupportedGeometry("CIRCLE".into()))
+        );
+        assert_eq!(parse("POINT (1 1))"), Err(WktError::UnexpectedToken(")".into())));
+
+        let nested = |depth: usize| {
+            format!(
+                "{}POINT (1 2){}",
+                "GEOMETRYCOLLECTION (".repeat(depth),
+                ")".repeat(depth)
+            )
+        };
+        assert_eq!(parse(&nested(MAX_NESTING + 1)), Err(WktError::NestingTooDeep));
+        assert_eq!(parse(&nested(MAX_NESTING)), Ok(vec![Geometry::Point((1, 2))]));
+    }
+
+    #[test]
+    fn round_trip() {
+        let wkt = [
+            "POINT(25 17)",
+            "MULTIPOINT((5 7),(3 2))",
+            "MULTILINESTRING((2 2,2 10,10 10),(1 1,3 5))",
+            "POLYGON((0 0,10 0,10 10,0 10,0 0),(1 1,1 2,2 2,2 1,1 1))",
+        ];
+
+        for wkt in wkt.iter() {
+            let geometries = parse(wkt).unwrap();
+            assert_eq!(to_string(&geometries[0]), *wkt);
+        }
+    }
+}
@@ -1,6 +1,11 @@
-use super::common::Value;
+use super::common::{GeometryType, TileCoord, Value};
+
+use super::compression::{compress, Compression};
 
 use super::error::{InvalidGeometry, SpecViolation};
+use super::linemerge::merge_lines;
+use super::read;
+use super::union::union;
 
 use super::proto::vector_tile as pbf;
 use pbf::mod_Tile as pbf_tile;
@@ -9,8 +14,7 @@ use quick_protobuf::{MessageWrite, Writer};
 
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
-use std::convert::Into;
-use std::io::Write;
+use std::io::{self, Write};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Tile {
@@ -37,12 +41,27 @@ impl Tile {
         let message: pbf::Tile = self.into();
         message.write_message(&mut pbf_writer).unwrap(); // FIXME: is it safe to unwrap?
     }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes);
+        bytes
+    }
+
+    /// Encodes the tile and writes it compressed. Fails if the codec's cargo feature is disabled.
+    pub fn write_compressed<W: Write>(self, writer: &mut W, compression: Compression) -> io::Result<()> {
+        writer.write_all(&compress(&self.to_bytes(), compression)?)
+    }
 }
 
-impl<'a> Into<pbf::Tile<'a>> for Tile {
-    fn into(self) -> pbf::Tile<'a> {
+impl<'a> From<Tile> for pbf::Tile<'a> {
+    fn from(tile: Tile) -> pbf::Tile<'a> {
         pbf::Tile {
-            layers: self.layers.into_iter().map(|l| l.into()).collect(),
+            layers: tile.layers.into_iter().map(|l| l.into()).collect(),
         }
     }
 }
@@ -82,6 +101,74 @@ impl Layer {
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The key and value of every feature tag in the layer.
+    pub fn tags(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.features.iter().flat_map(move |feature| {
+            feature
+                .tags
+                .chunks(2)
+                .map(move |tag| (self.keys[tag[0] as usize].as_str(), &self.values[tag[1] as usize]))
+        })
+    }
+
+    /// Merges features with identical tags and geometry type into one feature each: points into multipoints,
+    /// lines into multilines joined end-to-end where possible, and polygons into their union. Merged features
+    /// lose their ids, features without anything to merge with are kept as they are.
+    pub fn coalesce(mut self) -> Layer {
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut group_lookup: HashMap<(i32, Vec<(u32, u32)>), usize> = HashMap::new();
+
+        for (idx, feature) in self.features.iter().enumerate() {
+            let mut tags: Vec<(u32, u32)> = feature.tags.chunks(2).map(|tag| (tag[0], tag[1])).collect();
+            tags.sort_unstable();
+
+            let group = *group_lookup.entry((feature.type_pb as i32, tags)).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(idx);
+        }
+
+        let mut features: Vec<Option<pbf_tile::Feature>> = self.features.drain(..).map(Some).collect();
+        let mut coalesced = Vec::with_capacity(groups.len());
+
+        for group in groups {
+            if group.len() > 1 {
+                let geometries: Option<Vec<read::Geometry>> = group
+                    .iter()
+                    .map(|idx| {
+                        let feature = features[*idx].as_ref()?;
+                        read::decode_geometry(feature.type_pb, &feature.geometry).ok()?
+                    })
+                    .collect();
+
+                let geometry = geometries
+                    .and_then(coalesce_geometries)
+                    .and_then(|geometry| geometry.encode().ok());
+
+                if let Some(geometry) = geometry {
+                    let first = features[group[0]].take().unwrap();
+                    coalesced.push(pbf_tile::Feature {
+                        id: 0,
+                        tags: first.tags,
+                        type_pb: geometry.r#type,
+                        geometry: geometry.commands,
+                    });
+                    continue;
+                }
+            }
+
+            coalesced.extend(group.into_iter().filter_map(|idx| features[idx].take()));
+        }
+
+        self.features = coalesced;
+        self
+    }
+
     fn encode_features_tags(features: &mut [Feature]) -> Result<(Vec<String>, Vec<Value>), SpecViolation> {
         let mut keys = Vec::new();
         let mut key_lookup = HashMap::new(); // FIXME: for a small amount of tags a simple linear search would be enough
@@ -150,19 +237,59 @@ impl Layer {
     }
 }
 
-impl<'a> Into<pbf_tile::Layer<'a>> for Layer {
-    fn into(self) -> pbf_tile::Layer<'a> {
+impl<'a> From<Layer> for pbf_tile::Layer<'a> {
+    fn from(layer: Layer) -> pbf_tile::Layer<'a> {
         pbf_tile::Layer {
             version: Layer::VERSION,
-            name: Cow::Owned(self.name),
-            features: self.features,
-            keys: self.keys.iter().map(|s| Cow::Owned(s.clone())).collect(),
-            values: self.values.into_iter().map(|v| v.into()).collect(),
-            extent: self.extent,
+            name: Cow::Owned(layer.name),
+            features: layer.features,
+            keys: layer.keys.into_iter().map(Cow::Owned).collect(),
+            values: layer.values.into_iter().map(|v| v.into()).collect(),
+            extent: layer.extent,
         }
     }
 }
 
+/// Merges geometries of the same type, `None` if the result is empty.
+pub(crate) fn coalesce_geometries(geometries: Vec<read::Geometry>) -> Option<read::Geometry> {
+    let mut points = Vec::new();
+    let mut lines = Vec::new();
+    let mut polygons = Vec::new();
+
+    for geometry in geometries {
+        match geometry {
+            read::Geometry::Point(point) => points.push(point),
+            read::Geometry::MultiPoint(more_points) => points.extend(more_points),
+            read::Geometry::Line(line) => lines.push(line),
+            read::Geometry::MultiLine(more_lines) => lines.extend(more_lines),
+            read::Geometry::Polygon(exterior_ring, interior_rings) => polygons.push((exterior_ring, interior_rings)),
+            read::Geometry::MultiPolygon(more_polygons) => polygons.extend(more_polygons),
+        }
+    }
+
+    let geometry = if !points.is_empty() {
+        read::Geometry::MultiPoint(points)
+    } else if !lines.is_empty() {
+        let mut lines = merge_lines(lines);
+        match lines.len() {
+            1 => read::Geometry::Line(lines.remove(0)),
+            _ => read::Geometry::MultiLine(lines),
+        }
+    } else {
+        let mut polygons = union(&polygons);
+        match polygons.len() {
+            0 => return None,
+            1 => {
+                let (exterior_ring, interior_rings) = polygons.remove(0);
+                read::Geometry::Polygon(exterior_ring, interior_rings)
+            }
+            _ => read::Geometry::MultiPolygon(polygons),
+        }
+    };
+
+    Some(geometry)
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Feature {
     pub id: Option<u64>,
@@ -181,6 +308,10 @@ impl Feature {
         }
     }
 
+    pub fn geometry_type(&self) -> GeometryType {
+        geometry_type(self.geometry.r#type)
+    }
+
     pub fn add_tag<Key>(&mut self, key: Key, value: Value)
     where
         Key: Into<String>,
@@ -189,10 +320,10 @@ impl Feature {
     }
 }
 
-impl<'a> Into<pbf_tile::Value<'a>> for Value {
-    fn into(self) -> pbf_tile::Value<'a> {
+impl<'a> From<Value> for pbf_tile::Value<'a> {
+    fn from(v: Value) -> pbf_tile::Value<'a> {
         let mut value = pbf_tile::Value::default();
-        match self {
+        match v {
             Value::String(v) => value.string_value = Some(Cow::Owned(v)),
             Value::Float(v) => value.float_value = Some(v),
             Value::Double(v) => value.double_value = Some(v),
@@ -216,7 +347,7 @@ fn encode_command(command: &Command, count: u32) -> u32 {
     match command {
         Command::MoveTo(_) => (1 & 0x7) | (count << 3),
         Command::LineTo(_) => (2 & 0x7) | (count << 3),
-        Command::ClosePath => (7 & 0x7),
+        Command::ClosePath => 7 | (count << 3),
     }
 }
 
@@ -230,9 +361,8 @@ fn diff_to(from: &TileCoord, to: &TileCoord) -> TileCoord {
 
 fn encode_geometry(commands: &[Command]) -> Vec<u32> {
     let mut encoded_commands = Vec::with_capacity(commands.len() * 3);
-    let mut cursor: TileCoord = (0, 0).into();
-    let mut command_buffer = &commands[..];
-    command_buffer = &[];
+    let mut cursor: TileCoord = (0, 0);
+    let mut command_buffer: &[Command] = &[];
 
     let mut move_cursor = |to: TileCoord| -> TileCoord {
         let diff = diff_to(&cursor, &to);
@@ -254,7 +384,7 @@ fn encode_geometry(commands: &[Command]) -> Vec<u32> {
                     ec.push(encode_param(x));
                     ec.push(encode_param(y));
                 }
-                Command::ClosePath => assert!(false),
+                Command::ClosePath => unreachable!(),
             }
         }
 
@@ -266,12 +396,9 @@ fn encode_geometry(commands: &[Command]) -> Vec<u32> {
     for (idx, command) in commands.iter().enumerate() {
         match command {
             Command::MoveTo(_) => {
-                match command_buffer.last() {
-                    Some(Command::LineTo(_)) => {
-                        flush_command_buffer(&mut command_buffer, &mut encoded_commands);
-                        start = None;
-                    }
-                    _ => {}
+                if let Some(Command::LineTo(_)) = command_buffer.last() {
+                    flush_command_buffer(&mut command_buffer, &mut encoded_commands);
+                    start = None;
                 }
 
                 if start.is_none() {
@@ -280,12 +407,9 @@ fn encode_geometry(commands: &[Command]) -> Vec<u32> {
                 command_buffer = &commands[start.unwrap()..=idx];
             }
             Command::LineTo(_) => {
-                match command_buffer.last() {
-                    Some(Command::MoveTo(_)) => {
-                        flush_command_buffer(&mut command_buffer, &mut encoded_commands);
-                        start = None;
-                    }
-                    _ => {}
+                if let Some(Command::MoveTo(_)) = command_buffer.last() {
+                    flush_command_buffer(&mut command_buffer, &mut encoded_commands);
+                    start = None;
                 }
 
                 if start.is_none() {
@@ -293,7 +417,11 @@ fn encode_geometry(commands: &[Command]) -> Vec<u32> {
                 }
                 command_buffer = &commands[start.unwrap()..=idx];
             }
-            Command::ClosePath => encoded_commands.push(encode_command(command, 0)),
+            Command::ClosePath => {
+                flush_command_buffer(&mut command_buffer, &mut encoded_commands);
+                start = None;
+                encoded_commands.push(encode_command(command, 1));
+            }
         }
     }
 
@@ -308,11 +436,26 @@ pub struct EncodedGeometry {
     commands: Vec<u32>,
 }
 
+impl EncodedGeometry {
+    /// Wraps geometry commands taken from an encoded tile, which are copied as they are.
+    pub(crate) fn from_commands(r#type: pbf_tile::GeomType, commands: Vec<u32>) -> EncodedGeometry {
+        EncodedGeometry { r#type, commands }
+    }
+}
+
+pub(crate) fn geometry_type(r#type: pbf_tile::GeomType) -> GeometryType {
+    match r#type {
+        pbf_tile::GeomType::UNKNOWN => GeometryType::Unknown,
+        pbf_tile::GeomType::POINT => GeometryType::Point,
+        pbf_tile::GeomType::LINESTRING => GeometryType::LineString,
+        pbf_tile::GeomType::POLYGON => GeometryType::Polygon,
+    }
+}
+
 pub trait EncodableGeometry {
     fn encode(&self) -> Result<EncodedGeometry, InvalidGeometry>;
 }
 
-type TileCoord = (i32, i32);
 type TileCoords<'a> = &'a [TileCoord];
 
 pub enum Geometry<'a> {
@@ -321,6 +464,7 @@ pub enum Geometry<'a> {
     Line(TileCoords<'a>),
     MultiLine(&'a [TileCoords<'a>]),
     Polygon(TileCoords<'a>, &'a [TileCoords<'a>]),
+    MultiPolygon(&'a [(TileCoords<'a>, &'a [TileCoords<'a>])]),
 }
 
 fn encode_line(line: &[TileCoord], commands: &mut Vec<Command>) -> Result<(), InvalidGeometry> {
@@ -385,6 +529,30 @@ fn encode_ring(ring: &[TileCoord], commands: &mut Vec<Command>) -> Result<i32, I
     Ok(area)
 }
 
+fn encode_polygon(
+    exterior_ring: &[TileCoord],
+    interior_rings: &[TileCoords],
+    commands: &mut Vec<Command>,
+) -> Result<(), InvalidGeometry> {
+    let area = encode_ring(exterior_ring, commands)?;
+
+    if area.is_negative() {
+        return Err(InvalidGeometry::InvalidPolygonGeometry);
+    }
+
+    for ring in interior_rings.iter() {
+        let area = encode_ring(ring, commands)?;
+
+        if area.is_positive() {
+            return Err(InvalidGeometry::InvalidPolygonGeometry);
+        }
+    }
+
+    // TODO: check intersection/enclosement
+
+    Ok(())
+}
+
 impl<'a> EncodableGeometry for Geometry<'a> {
     fn encode(&self) -> Result<EncodedGeometry, InvalidGeometry> {
         match self {
@@ -453,21 +621,30 @@ impl<'a> EncodableGeometry for Geometry<'a> {
 
                 let mut commands = Vec::with_capacity(command_count);
 
-                let area = encode_ring(exterior_ring, &mut commands)?;
+                encode_polygon(exterior_ring, interior_rings, &mut commands)?;
 
-                if area.is_negative() {
-                    return Err(InvalidGeometry::InvalidPolygonGeometry);
+                Ok(EncodedGeometry {
+                    r#type: pbf_tile::GeomType::POLYGON,
+                    commands: encode_geometry(&commands),
+                })
+            }
+            Geometry::MultiPolygon(polygons) => {
+                if polygons.is_empty() {
+                    return Err(InvalidGeometry::EmptyPolygonGeometry);
                 }
 
-                for line in interior_rings.iter() {
-                    let area = encode_ring(line, &mut commands)?;
+                let command_count: usize = polygons
+                    .iter()
+                    .map(|(exterior_ring, interior_rings)| {
+                        exterior_ring.len() + interior_rings.iter().map(|ring| ring.len()).sum::<usize>()
+                    })
+                    .sum();
 
-                    if area.is_positive() {
-                        return Err(InvalidGeometry::InvalidPolygonGeometry);
-                    }
-                }
+                let mut commands = Vec::with_capacity(command_count);
 
-                // TODO: check intersection/enclosement
+                for (exterior_ring, interior_rings) in polygons.iter() {
+                    encode_polygon(exterior_ring, interior_rings, &mut commands)?;
+                }
 
                 Ok(EncodedGeometry {
                     r#type: pbf_tile::GeomType::POLYGON,
@@ -585,6 +762,13 @@ mod mvt_writer_test {
         assert_eq!(geometry.encode(), Err(InvalidGeometry::InvalidPolygonGeometry));
     }
 
+    #[test]
+    fn polygon_commands() {
+        // Example from 4.3.5.3. of the specification
+        let geometry = Geometry::Polygon(&[(3, 6), (8, 12), (20, 34)], &[]).encode().unwrap();
+        assert_eq!(geometry.commands, vec![9, 6, 12, 18, 10, 12, 24, 44, 15]);
+    }
+
     #[test]
     fn read_back() {
         let tile = create_test_tile().unwrap();
@@ -601,4 +785,54 @@ mod mvt_writer_test {
 
         assert_eq!(message, read_message);
     }
+
+    #[test]
+    fn coalesce() {
+        let feature = |id: u64, geometry: Geometry, class: &str| {
+            let mut feature = Feature::new(geometry.encode().unwrap());
+            feature.id = Some(id);
+            feature.add_tag("class", Value::String(class.to_string()));
+            feature
+        };
+
+        let features = vec![
+            feature(1, Geometry::Line(&[(0, 0), (10, 0)]), "primary"),
+            feature(2, Geometry::Line(&[(20, 0), (10, 0)]), "primary"),
+            feature(3, Geometry::Line(&[(0, 10), (10, 10)]), "secondary"),
+            feature(
+                4,
+                Geometry::Polygon(&[(0, 0), (10, 0), (10, 10), (0, 10)], &[]),
+                "primary",
+            ),
+            feature(
+                5,
+                Geometry::Polygon(&[(10, 0), (20, 0), (20, 10), (10, 10)], &[]),
+                "primary",
+            ),
+            feature(6, Geometry::Point((5, 5)), "primary"),
+            feature(7, Geometry::Point((6, 6)), "primary"),
+        ];
+
+        let layer = Layer::new("layer", features).unwrap().coalesce();
+        let tile = read::Tile::from_bytes(&Tile::new(vec![layer]).unwrap().to_bytes()).unwrap();
+
+        let coalesced: Vec<(Option<u64>, &read::Geometry)> = tile.layers[0]
+            .features
+            .iter()
+            .map(|feature| (feature.id, &feature.geometry))
+            .collect();
+
+        assert_eq!(
+            coalesced,
+            vec![
+                (None, &read::Geometry::Line(vec![(0, 0), (10, 0), (20, 0)])),
+                (Some(3), &read::Geometry::Line(vec![(0, 10), (10, 10)])),
+                (
+                    None,
+                    &read::Geometry::Polygon(vec![(0, 0), (20, 0), (20, 10), (0, 10)], vec![])
+                ),
+                (None, &read::Geometry::MultiPoint(vec![(5, 5), (6, 6)])),
+            ]
+        );
+    }
 }
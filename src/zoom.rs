@@ -0,0 +1,417 @@
+use super::common::TileCoord;
+
+use super::error::ZoomError;
+use super::quantize::{PointF64, PolygonF64};
+use super::read::{self, Geometry};
+use super::simplify::simplify;
+use super::tile::TileId;
+use super::transform::{GeometryF64, TileTransform};
+use super::write::{self, coalesce_geometries, EncodableGeometry};
+
+use std::collections::HashMap;
+
+/// Options of `underzoom`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnderzoomOptions {
+    /// Buffer of the parent tile in tile units.
+    pub buffer: u32,
+    /// Simplification tolerance in tile units of the parent tile, no simplification if not positive.
+    pub tolerance: f64,
+    /// Whether features of a layer sharing an id and geometry type are merged into one. Without merging,
+    /// features split across children make encoding fail with `SpecViolation::IdenticalFeatureIds`.
+    pub merge_ids: bool,
+}
+
+impl Default for UnderzoomOptions {
+    fn default() -> UnderzoomOptions {
+        UnderzoomOptions {
+            buffer: 64,
+            tolerance: 0.0,
+            merge_ids: true,
+        }
+    }
+}
+
+/// Derives a tile of a higher zoom level from an encoded, possibly compressed, tile of one of its ancestors.
+///
+/// Coordinates are scaled up, clipped to the child tile grown by `buffer` tile units and snapped, see
+/// `TileTransform`. Layers keep their extent, features keep their tags and ids, and features or layers
+/// left empty are dropped. Returns `None` if nothing remains of the parent tile.
+pub fn overzoom(bytes: &[u8], parent: TileId, child: TileId, buffer: u32) -> Result<Option<Vec<u8>>, ZoomError> {
+    if !contains(parent, child) {
+        return Err(ZoomError::UnrelatedTiles(parent, child));
+    }
+
+    let tile = read::Tile::from_compressed_bytes(bytes)?;
+    let mut layers = Vec::with_capacity(tile.layers.len());
+
+    for layer in &tile.layers {
+        let transform = TileTransform::unit(child, layer.extent).with_buffer(buffer);
+        let to_unit = unit_coordinates(parent, layer.extent);
+
+        let features: Vec<write::Feature> = layer
+            .features
+            .iter()
+            .filter_map(|feature| {
+                let geometry = transform_geometry(&feature.geometry, &to_unit, &transform)?;
+                let mut encoded = write::Feature::new(geometry.encode().ok()?);
+                encoded.id = feature.id;
+                encoded.tags = feature.tags.clone();
+                Some(encoded)
+            })
+            .collect();
+
+        if !features.is_empty() {
+            let mut encoded = write::Layer::new(layer.name.clone(), features)?;
+            encoded.extent = layer.extent;
+            layers.push(encoded);
+        }
+    }
+
+    if layers.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(write::Tile::new(layers)?.to_bytes()))
+}
+
+/// Assembles a tile from encoded, possibly compressed, tiles of its (up to four) children.
+///
+/// Coordinates are scaled down by 2. Every child only contributes the quarter of the parent tile it covers
+/// (and the adjoining part of the parent buffer), so that features repeated in the buffers of neighbouring
+/// children are not duplicated. Layers with the same name are merged and their key and value tables rebuilt,
+/// taking the extent of the first child having the layer. With `merge_ids`, the pieces of a feature split
+/// across children are put back together: polygons are unioned and lines joined. Returns `None` if all
+/// children are empty.
+pub fn underzoom(
+    parent: TileId,
+    children: &[(TileId, &[u8])],
+    options: &UnderzoomOptions,
+) -> Result<Option<Vec<u8>>, ZoomError> {
+    let mut layers: Vec<(String, u32, Vec<read::Feature>)> = Vec::new();
+
+    for (child, bytes) in children {
+        if child.z() != parent.z() + 1 || !contains(parent, *child) {
+            return Err(ZoomError::UnrelatedTiles(parent, *child));
+        }
+
+        let tile = read::Tile::from_compressed_bytes(bytes)?;
+
+        for layer in tile.layers {
+            let idx = match layers.iter().position(|(name, _, _)| *name == layer.name) {
+                Some(idx) => idx,
+                None => {
+                    layers.push((layer.name.clone(), layer.extent, Vec::new()));
+                    layers.len() - 1
+                }
+            };
+            let extent = layers[idx].1;
+
+            let (half, buffer) = (extent as f64 / 2.0, options.buffer as f64);
+            let (min_x, max_x) = if child.x() % 2 == 0 {
+                (-buffer, half)
+            } else {
+                (half, extent as f64 + buffer)
+            };
+            let (min_y, max_y) = if child.y() % 2 == 0 {
+                (-buffer, half)
+            } else {
+                (half, extent as f64 + buffer)
+            };
+
+            let transform = TileTransform::unit(parent, extent).with_clip_box((min_x, min_y, max_x, max_y));
+            let to_unit = unit_coordinates(*child, layer.extent);
+
+            for feature in layer.features {
+                if let Some(geometry) = transform_geometry(&feature.geometry, &to_unit, &transform) {
+                    layers[idx].2.push(read::Feature { geometry, ..feature });
+                }
+            }
+        }
+    }
+
+    let mut encoded_layers = Vec::with_capacity(layers.len());
+
+    for (name, extent, features) in layers {
+        let features = if options.merge_ids {
+            merge_ids(features)
+        } else {
+            features
+        };
+
+        let features: Vec<write::Feature> = features
+            .into_iter()
+            .filter_map(|feature| {
+                let geometry = if options.tolerance > 0.0 {
+                    simplify(&feature.geometry, options.tolerance)?
+                } else {
+                    feature.geometry
+                };
+
+                let mut encoded = write::Feature::new(geometry.encode().ok()?);
+                encoded.id = feature.id;
+                encoded.tags = feature.tags;
+                Some(encoded)
+            })
+            .collect();
+
+        if !features.is_empty() {
+            let mut encoded = write::Layer::new(name, features)?;
+            encoded.extent = extent;
+            encoded_layers.push(encoded);
+        }
+    }
+
+    if encoded_layers.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(write::Tile::new(encoded_layers)?.to_bytes()))
+}
+
+/// Merges the geometries of features sharing an id and geometry type, in the place of the first of them.
+fn merge_ids(features: Vec<read::Feature>) -> Vec<read::Feature> {
+    let kind = |geometry: &Geometry| match geometry {
+        Geometry::Point(_) | Geometry::MultiPoint(_) => 0,
+        Geometry::Line(_) | Geometry::MultiLine(_) => 1,
+        Geometry::Polygon(..) | Geometry::MultiPolygon(_) => 2,
+    };
+
+    let mut groups: Vec<Vec<read::Feature>> = Vec::new();
+    let mut lookup: HashMap<(u64, u8), usize> = HashMap::new();
+
+    for feature in features {
+        match feature.id {
+            Some(id) => {
+                let group = *lookup.entry((id, kind(&feature.geometry))).or_insert_with(|| {
+                    groups.push(Vec::new());
+                    groups.len() - 1
+                });
+                groups[group].push(feature);
+            }
+            None => groups.push(vec![feature]),
+        }
+    }
+
+    groups
+        .into_iter()
+        .filter_map(|mut group| {
+            if group.len() == 1 {
+                return group.pop();
+            }
+
+            let first = group.remove(0);
+            let geometries = std::iter::once(first.geometry)
+                .chain(group.into_iter().map(|feature| feature.geometry))
+                .collect();
+
+            Some(read::Feature {
+                geometry: coalesce_geometries(geometries)?,
+                ..first
+            })
+        })
+        .collect()
+}
+
+/// Whether `tile` is `ancestor` or one of its descendants.
+fn contains(ancestor: TileId, tile: TileId) -> bool {
+    match tile.z().checked_sub(ancestor.z()) {
+        Some(shift) => (tile.x() >> shift, tile.y() >> shift) == (ancestor.x(), ancestor.y()),
+        None => false,
+    }
+}
+
+/// Maps the coordinates of a tile to Web Mercator coordinates normalized to the unit square.
+fn unit_coordinates(tile: TileId, extent: u32) -> impl Fn(TileCoord) -> PointF64 {
+    let size = (1u64 << tile.z()) as f64;
+    let extent = extent as f64;
+
+    move |point| {
+        (
+            (tile.x() as f64 + point.0 as f64 / extent) / size,
+            (tile.y() as f64 + point.1 as f64 / extent) / size,
+        )
+    }
+}
+
+/// Moves a geometry from one tile to another through unit square coordinates.
+fn transform_geometry<F>(geometry: &Geometry, to_unit: &F, transform: &TileTransform) -> Option<Geometry>
+where
+    F: Fn(TileCoord) -> PointF64,
+{
+    let points = |points: &[TileCoord]| -> Vec<PointF64> { points.iter().map(|point| to_unit(*point)).collect() };
+    let polygon = |exterior_ring: &[TileCoord], interior_rings: &[Vec<TileCoord>]| -> PolygonF64 {
+        (
+            points(exterior_ring),
+            interior_rings.iter().map(|ring| points(ring)).collect(),
+        )
+    };
+
+    match geometry {
+        Geometry::Point(point) => transform.transform(&GeometryF64::Point(to_unit(*point))),
+        Geometry::MultiPoint(multi_points) => transform.transform(&GeometryF64::MultiPoint(&points(multi_points))),
+        Geometry::Line(line) => transform.transform(&GeometryF64::Line(&points(line))),
+        Geometry::MultiLine(lines) => {
+            let lines: Vec<Vec<PointF64>> = lines.iter().map(|line| points(line)).collect();
+            let lines: Vec<&[PointF64]> = lines.iter().map(Vec::as_slice).collect();
+            transform.transform(&GeometryF64::MultiLine(&lines))
+        }
+        Geometry::Polygon(exterior_ring, interior_rings) => {
+            let (exterior_ring, interior_rings) = polygon(exterior_ring, interior_rings);
+            let interior_rings: Vec<&[PointF64]> = interior_rings.iter().map(Vec::as_slice).collect();
+            transform.transform(&GeometryF64::Polygon(&exterior_ring, &interior_rings))
+        }
+        Geometry::MultiPolygon(polygons) => {
+            let polygons: Vec<PolygonF64> = polygons
+                .iter()
+                .map(|(exterior_ring, interior_rings)| polygon(exterior_ring, interior_rings))
+                .collect();
+            let interior_rings: Vec<Vec<&[PointF64]>> = polygons
+                .iter()
+                .map(|(_, rings)| rings.iter().map(Vec::as_slice).collect())
+                .collect();
+            let polygons: Vec<(&[PointF64], &[&[PointF64]])> = polygons
+                .iter()
+                .zip(&interior_rings)
+                .map(|((exterior_ring, _), interior_rings)| (exterior_ring.as_slice(), interior_rings.as_slice()))
+                .collect();
+            transform.transform(&GeometryF64::MultiPolygon(&polygons))
+        }
+    }
+}
+
+#[cfg(test)]
+mod zoom_test {
+    use super::*;
+    use crate::common::Value;
+
+    fn feature(id: u64, geometry: Geometry) -> write::Feature {
+        let mut feature = write::Feature::new(geometry.encode().unwrap());
+        feature.id = Some(id);
+        feature.add_tag("name", Value::String(format!("feature {}", id)));
+        feature
+    }
+
+    fn encode(layers: Vec<(&str, Vec<write::Feature>)>) -> Vec<u8> {
+        let layers = layers
+            .into_iter()
+            .map(|(name, features)| write::Layer::new(name, features).unwrap())
+            .collect();
+        write::Tile::new(layers).unwrap().to_bytes()
+    }
+
+    #[test]
+    fn overzooming() {
+        let parent = TileId::new(0, 0, 0).unwrap();
+        let bytes = encode(vec![
+            (
+                "places",
+                vec![
+                    feature(1, Geometry::Point((3000, 1000))),
+                    feature(2, Geometry::Point((1000, 1000))),
+                ],
+            ),
+            (
+                "roads",
+                vec![feature(3, Geometry::Line(vec![(1000, 1000), (3000, 1000)]))],
+            ),
+            (
+                "water",
+                vec![feature(
+                    4,
+                    Geometry::Polygon(vec![(100, 3000), (1000, 3000), (1000, 4000), (100, 4000)], vec![]),
+                )],
+            ),
+        ]);
+
+        let child = TileId::new(1, 1, 0).unwrap();
+        let tile = read::Tile::from_bytes(&overzoom(&bytes, parent, child, 64).unwrap().unwrap()).unwrap();
+
+        assert_eq!(tile.layers.len(), 2);
+        let places = tile.layer("places").unwrap();
+        assert_eq!(places.features.len(), 1);
+        assert_eq!(places.features[0].id, Some(1));
+        assert_eq!(places.features[0].geometry, Geometry::Point((1904, 2000)));
+        assert_eq!(
+            places.features[0].tag("name"),
+            Some(&Value::String("feature 1".to_string()))
+        );
+        assert_eq!(
+            tile.layer("roads").unwrap().features[0].geometry,
+            Geometry::Line(vec![(-64, 2000), (1904, 2000)])
+        );
+
+        // Grand-children work as well, empty children give nothing and unrelated tiles an error
+        let grand_child = TileId::new(2, 0, 3).unwrap();
+        let tile = read::Tile::from_bytes(&overzoom(&bytes, parent, grand_child, 0).unwrap().unwrap()).unwrap();
+        assert_eq!(
+            tile.layer("water").unwrap().features[0].geometry,
+            Geometry::Polygon(vec![(4000, 0), (4000, 3712), (400, 3712), (400, 0)], vec![])
+        );
+        assert_eq!(overzoom(&bytes, parent, TileId::new(2, 3, 3).unwrap(), 0), Ok(None));
+        assert_eq!(
+            overzoom(&bytes, child, grand_child, 0),
+            Err(ZoomError::UnrelatedTiles(child, grand_child))
+        );
+    }
+
+    #[test]
+    fn underzooming() {
+        let parent = TileId::new(0, 0, 0).unwrap();
+
+        // A building split between the two upper children, repeated in their buffers
+        let left = encode(vec![
+            (
+                "buildings",
+                vec![feature(
+                    1,
+                    Geometry::Polygon(vec![(3000, 1000), (4160, 1000), (4160, 2000), (3000, 2000)], vec![]),
+                )],
+            ),
+            ("places", vec![feature(2, Geometry::Point((1000, 1000)))]),
+        ]);
+        let right = encode(vec![(
+            "buildings",
+            vec![feature(
+                1,
+                Geometry::Polygon(vec![(-64, 1000), (1000, 1000), (1000, 2000), (-64, 2000)], vec![]),
+            )],
+        )]);
+
+        let children = [
+            (TileId::new(1, 0, 0).unwrap(), left.as_slice()),
+            (TileId::new(1, 1, 0).unwrap(), right.as_slice()),
+        ];
+        let bytes = underzoom(parent, &children, &UnderzoomOptions::default())
+            .unwrap()
+            .unwrap();
+        let tile = read::Tile::from_bytes(&bytes).unwrap();
+
+        let buildings = tile.layer("buildings").unwrap();
+        assert_eq!(buildings.features.len(), 1);
+        assert_eq!(buildings.features[0].id, Some(1));
+        assert_eq!(
+            buildings.features[0].geometry,
+            Geometry::Polygon(vec![(1500, 500), (2548, 500), (2548, 1000), (1500, 1000)], vec![])
+        );
+        assert_eq!(
+            tile.layer("places").unwrap().features[0].geometry,
+            Geometry::Point((500, 500))
+        );
+
+        let options = UnderzoomOptions {
+            merge_ids: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            underzoom(parent, &children, &options),
+            Err(ZoomError::Write(crate::error::SpecViolation::IdenticalFeatureIds(1)))
+        );
+
+        let grand_child = (TileId::new(2, 0, 0).unwrap(), left.as_slice());
+        assert_eq!(
+            underzoom(parent, &[grand_child], &options),
+            Err(ZoomError::UnrelatedTiles(parent, grand_child.0))
+        );
+    }
+}